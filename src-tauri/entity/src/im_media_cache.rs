@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
+#[sea_orm(table_name = "im_media_cache")]
+#[serde(rename_all = "camelCase")]
+pub struct Model {
+    /// 账号缓存目录下的文件名，原图与各尺寸缩略图各占一条
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub cache_key: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    #[serde(skip)]
+    pub login_uid: String,
+    pub mxc_uri: String,
+    pub size: i64,
+    pub mime_type: Option<String>,
+    pub room_id: Option<String>,
+    /// 是否为服务端生成的缩略图
+    pub thumbnail: bool,
+    /// 固定的缓存不参与 LRU 淘汰
+    pub pinned: bool,
+    pub created_at: i64,
+    pub last_accessed_at: i64,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
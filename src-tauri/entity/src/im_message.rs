@@ -27,6 +27,8 @@ pub struct Model {
     pub mxc_url: Option<String>,
     pub sender: Option<String>,
     pub origin_server_ts: Option<i64>,
+    /// 本地生成或下载的缩略图路径
+    pub thumbnail_path: Option<String>,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,37 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
+#[sea_orm(table_name = "im_upload_queue")]
+#[serde(rename_all = "camelCase")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    #[serde(skip)]
+    pub login_uid: String,
+    pub room_id: String,
+    /// 引用该媒体的本地消息 ID
+    pub message_id: String,
+    /// 通过 `/_matrix/media/v1/create` 预先创建的 mxc URI
+    pub mxc_uri: String,
+    pub file_path: String,
+    pub file_name: Option<String>,
+    pub mime_type: String,
+    /// 加密房间的 `EncryptedFile` JSON，包含上传时使用的密钥
+    #[serde(skip)]
+    pub encrypted_file: Option<String>,
+    /// 上传状态: pending, uploading, success, fail
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: i64,
+    /// 服务端回收未上传 mxc 的时间
+    pub unused_expires_at: Option<i64>,
+    pub last_error: Option<String>,
+    pub create_time: i64,
+    pub update_time: i64,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
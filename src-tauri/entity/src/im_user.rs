@@ -29,6 +29,8 @@ pub struct Model {
     pub is_init: bool,
     pub token: Option<String>,
     pub refresh_token: Option<String>,
+    pub homeserver: Option<String>,
+    pub last_active_time: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
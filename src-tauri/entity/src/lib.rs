@@ -1,7 +1,9 @@
 pub mod im_config;
 pub mod im_contact;
+pub mod im_media_cache;
 pub mod im_message;
 pub mod im_room;
 pub mod im_room_member;
+pub mod im_upload_queue;
 pub mod im_user;
 pub mod prelude;
@@ -8,6 +8,11 @@ mod m20250917_000002_add_thumbnail_path;
 mod m20251207_000001_add_matrix_fields;
 mod m20251207_000002_add_indexes;
 mod m20251207_000003_unique_event_per_room;
+mod m20251215_000001_create_message_fts;
+mod m20251216_000001_create_media_cache;
+mod m20251217_000001_create_upload_queue;
+mod m20251218_000001_add_room_member_indexes;
+mod m20251219_000001_account_isolation;
 
 pub struct Migrator;
 
@@ -23,6 +28,11 @@ impl MigratorTrait for Migrator {
             Box::new(m20251207_000001_add_matrix_fields::Migration),
             Box::new(m20251207_000002_add_indexes::Migration),
             Box::new(m20251207_000003_unique_event_per_room::Migration),
+            Box::new(m20251215_000001_create_message_fts::Migration),
+            Box::new(m20251216_000001_create_media_cache::Migration),
+            Box::new(m20251217_000001_create_upload_queue::Migration),
+            Box::new(m20251218_000001_add_room_member_indexes::Migration),
+            Box::new(m20251219_000001_account_isolation::Migration),
         ]
     }
 }
@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // 已索引的消息，doc_id 即 im_message_fts 的 rowid
+        // im_message 没有 INTEGER PRIMARY KEY，其 rowid 在 VACUUM 或 sqlcipher_export 后会重新编号，
+        // 不能作为索引的关联键，因此按 (id, login_uid) 关联
+        // 没有可检索文本的消息（撤回、纯媒体）也会记录，避免每次启动重复扫描
+        db.execute_unprepared(
+            "CREATE TABLE IF NOT EXISTS im_message_fts_doc (
+                doc_id INTEGER PRIMARY KEY,
+                message_id TEXT NOT NULL,
+                login_uid TEXT NOT NULL,
+                UNIQUE (message_id, login_uid)
+            )",
+        )
+        .await?;
+
+        // 聊天记录全文索引
+        // tokens 列存放应用侧分好词的文本（中日韩二元组 + 小写单词），索引数据由应用维护
+        db.execute_unprepared(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS im_message_fts USING fts5(
+                tokens,
+                tokenize = 'unicode61'
+            )",
+        )
+        .await?;
+
+        // 消息删除或正文变化时移除旧索引，重新索引由应用写入
+        db.execute_unprepared(
+            "CREATE TRIGGER IF NOT EXISTS im_message_fts_ad AFTER DELETE ON im_message BEGIN
+                DELETE FROM im_message_fts WHERE rowid IN (
+                    SELECT doc_id FROM im_message_fts_doc
+                    WHERE message_id = old.id AND login_uid = old.login_uid
+                );
+                DELETE FROM im_message_fts_doc WHERE message_id = old.id AND login_uid = old.login_uid;
+            END",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE TRIGGER IF NOT EXISTS im_message_fts_au AFTER UPDATE OF body, message_type ON im_message BEGIN
+                DELETE FROM im_message_fts WHERE rowid IN (
+                    SELECT doc_id FROM im_message_fts_doc
+                    WHERE message_id = old.id AND login_uid = old.login_uid
+                );
+                DELETE FROM im_message_fts_doc WHERE message_id = old.id AND login_uid = old.login_uid;
+            END",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared("DROP TRIGGER IF EXISTS im_message_fts_au")
+            .await?;
+        db.execute_unprepared("DROP TRIGGER IF EXISTS im_message_fts_ad")
+            .await?;
+        db.execute_unprepared("DROP TABLE IF EXISTS im_message_fts")
+            .await?;
+        db.execute_unprepared("DROP TABLE IF EXISTS im_message_fts_doc")
+            .await?;
+
+        Ok(())
+    }
+}
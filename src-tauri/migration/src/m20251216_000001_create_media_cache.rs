@@ -0,0 +1,105 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // 媒体缓存索引，cache_key 为账号 media_cache 目录下的文件名
+        // 多个账号可能缓存同一个文件，主键需要包含账号
+        manager
+            .create_table(
+                Table::create()
+                    .table(ImMediaCache::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(ImMediaCache::CacheKey).string().not_null())
+                    .col(ColumnDef::new(ImMediaCache::LoginUid).string().not_null())
+                    .col(ColumnDef::new(ImMediaCache::MxcUri).string().not_null())
+                    .col(
+                        ColumnDef::new(ImMediaCache::Size)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(ImMediaCache::MimeType).string())
+                    .col(ColumnDef::new(ImMediaCache::RoomId).string())
+                    .col(
+                        ColumnDef::new(ImMediaCache::Thumbnail)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(ImMediaCache::Pinned)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(ImMediaCache::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ImMediaCache::LastAccessedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(ImMediaCache::CacheKey)
+                            .col(ImMediaCache::LoginUid),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_im_media_cache_mxc_uri")
+                    .table(ImMediaCache::Table)
+                    .col(ImMediaCache::LoginUid)
+                    .col(ImMediaCache::MxcUri)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_im_media_cache_last_accessed")
+                    .table(ImMediaCache::Table)
+                    .col(ImMediaCache::LoginUid)
+                    .col(ImMediaCache::LastAccessedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ImMediaCache::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum ImMediaCache {
+    Table,
+    CacheKey,
+    LoginUid,
+    MxcUri,
+    Size,
+    MimeType,
+    RoomId,
+    Thumbnail,
+    Pinned,
+    CreatedAt,
+    LastAccessedAt,
+}
@@ -0,0 +1,105 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // 异步上传队列（MSC2246）：先创建 mxc 发送消息，再在后台上传文件内容
+        manager
+            .create_table(
+                Table::create()
+                    .table(ImUploadQueue::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ImUploadQueue::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ImUploadQueue::LoginUid).string().not_null())
+                    .col(ColumnDef::new(ImUploadQueue::RoomId).string().not_null())
+                    .col(ColumnDef::new(ImUploadQueue::MessageId).string().not_null())
+                    .col(ColumnDef::new(ImUploadQueue::MxcUri).string().not_null())
+                    .col(ColumnDef::new(ImUploadQueue::FilePath).string().not_null())
+                    .col(ColumnDef::new(ImUploadQueue::FileName).string())
+                    .col(ColumnDef::new(ImUploadQueue::MimeType).string().not_null())
+                    .col(ColumnDef::new(ImUploadQueue::EncryptedFile).text())
+                    .col(
+                        ColumnDef::new(ImUploadQueue::Status)
+                            .string()
+                            .not_null()
+                            .default("pending"),
+                    )
+                    .col(
+                        ColumnDef::new(ImUploadQueue::Attempts)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(ImUploadQueue::NextAttemptAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ImUploadQueue::UnusedExpiresAt).big_integer())
+                    .col(ColumnDef::new(ImUploadQueue::LastError).string())
+                    .col(
+                        ColumnDef::new(ImUploadQueue::CreateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ImUploadQueue::UpdateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_im_upload_queue_status")
+                    .table(ImUploadQueue::Table)
+                    .col(ImUploadQueue::LoginUid)
+                    .col(ImUploadQueue::Status)
+                    .col(ImUploadQueue::NextAttemptAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ImUploadQueue::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum ImUploadQueue {
+    Table,
+    Id,
+    LoginUid,
+    RoomId,
+    MessageId,
+    MxcUri,
+    FilePath,
+    FileName,
+    MimeType,
+    EncryptedFile,
+    Status,
+    Attempts,
+    NextAttemptAt,
+    UnusedExpiresAt,
+    LastError,
+    CreateTime,
+    UpdateTime,
+}
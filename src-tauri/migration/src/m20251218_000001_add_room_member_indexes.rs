@@ -0,0 +1,66 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // 房间成员按名称或最后活跃时间分页
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_im_room_member_room_name")
+                    .table(ImRoomMember::Table)
+                    .col(ImRoomMember::LoginUid)
+                    .col(ImRoomMember::RoomId)
+                    .col(ImRoomMember::Name)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_im_room_member_room_opt_time")
+                    .table(ImRoomMember::Table)
+                    .col(ImRoomMember::LoginUid)
+                    .col(ImRoomMember::RoomId)
+                    .col(ImRoomMember::LastOptTime)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_im_room_member_room_name")
+                    .table(ImRoomMember::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_im_room_member_room_opt_time")
+                    .table(ImRoomMember::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum ImRoomMember {
+    Table,
+    RoomId,
+    Name,
+    LastOptTime,
+    LoginUid,
+}
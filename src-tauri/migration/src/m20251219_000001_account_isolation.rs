@@ -0,0 +1,119 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::TransactionTrait;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite 的迁移不在事务中执行，语句会分散到连接池的不同连接上，
+        // 其他连接看不到刚删除的索引，重建同名索引会失败，因此放在同一个事务中
+        let txn = manager.get_connection().begin().await?;
+        let manager = &SchemaManager::new(&txn);
+
+        // 账号列表：账号所在的 homeserver 与最后使用时间
+        // SQLite 每条 ALTER TABLE 只能添加一列
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ImUser::Table)
+                    .add_column(ColumnDef::new(ImUser::Homeserver).string())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ImUser::Table)
+                    .add_column(ColumnDef::new(ImUser::LastActiveTime).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        // 多个账号在同一房间时会收到相同的事件，去重需要区分账号
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("uniq_room_event")
+                    .table(ImMessage::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("uniq_room_event")
+                    .table(ImMessage::Table)
+                    .col(ImMessage::LoginUid)
+                    .col(ImMessage::RoomId)
+                    .col(ImMessage::EventId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        txn.commit().await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite 的迁移不在事务中执行，语句会分散到连接池的不同连接上，
+        // 其他连接看不到刚删除的索引，重建同名索引会失败，因此放在同一个事务中
+        let txn = manager.get_connection().begin().await?;
+        let manager = &SchemaManager::new(&txn);
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("uniq_room_event")
+                    .table(ImMessage::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("uniq_room_event")
+                    .table(ImMessage::Table)
+                    .col(ImMessage::RoomId)
+                    .col(ImMessage::EventId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ImUser::Table)
+                    .drop_column(ImUser::LastActiveTime)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ImUser::Table)
+                    .drop_column(ImUser::Homeserver)
+                    .to_owned(),
+            )
+            .await?;
+
+        txn.commit().await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ImUser {
+    Table,
+    Homeserver,
+    LastActiveTime,
+}
+
+#[derive(DeriveIden)]
+enum ImMessage {
+    Table,
+    LoginUid,
+    RoomId,
+    EventId,
+}
@@ -0,0 +1,241 @@
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, State};
+use tracing::{info, warn};
+
+use crate::AppData;
+use crate::command::media::get_cache_root;
+use crate::command::upload_queue_command::wake_upload_worker;
+use crate::command::user_command::{load_column, persist_tokens, run_blocking};
+use crate::repository::im_user_repository;
+use crate::state::AppState;
+use crate::utils::account_storage::{self, account_dir_name};
+use crate::utils::secret_store;
+
+/// 当前账号变化事件，退出当前账号时 uid 为空
+const ACCOUNT_SWITCHED_EVENT: &str = "account-switched";
+
+/// 本机登录过的账号
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountInfo {
+    /// Matrix 用户 ID
+    pub uid: String,
+    pub name: Option<String>,
+    pub avatar: Option<String>,
+    pub homeserver: Option<String>,
+    /// 是否为当前账号
+    pub active: bool,
+    /// 最后使用时间（毫秒）
+    pub last_active_time: Option<i64>,
+    /// 是否保存了令牌，没有令牌的账号切换前需要重新登录
+    pub logged_in: bool,
+}
+
+/// 添加账号的参数
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AddAccountReq {
+    pub uid: String,
+    pub homeserver: String,
+    pub token: String,
+    pub refresh_token: Option<String>,
+    pub name: Option<String>,
+    pub avatar: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct AccountSwitchedPayload {
+    uid: Option<String>,
+    homeserver: Option<String>,
+}
+
+/// 查询本机的全部账号，按最后使用时间倒序
+#[tauri::command]
+pub async fn list_accounts(state: State<'_, AppData>) -> Result<Vec<AccountInfo>, String> {
+    let active_uid = state.user_info.lock().await.uid.clone();
+    let users = im_user_repository::find_accounts(state.db_conn.as_ref()).await?;
+
+    Ok(users
+        .into_iter()
+        .map(|user| AccountInfo {
+            active: user.id == active_uid,
+            logged_in: user.token.as_deref().is_some_and(|token| !token.is_empty()),
+            uid: user.id,
+            name: user.name,
+            avatar: user.avatar,
+            homeserver: user.homeserver,
+            last_active_time: user.last_active_time,
+        })
+        .collect())
+}
+
+/// 添加账号：保存账号资料与令牌，不会切换当前账号
+#[tauri::command]
+pub async fn add_account(req: AddAccountReq, state: State<'_, AppData>) -> Result<(), String> {
+    if req.uid.is_empty() || req.token.is_empty() {
+        return Err("用户 ID 与令牌不能为空".to_string());
+    }
+
+    let db = state.db_conn.as_ref();
+    let homeserver = req.homeserver.trim_end_matches('/').to_string();
+    im_user_repository::save_account(db, &req.uid, Some(homeserver), req.name, req.avatar).await?;
+    persist_tokens(
+        db,
+        &req.uid,
+        req.token,
+        req.refresh_token.filter(|token| !token.is_empty()),
+    )
+    .await
+    .map_err(|e| format!("保存令牌失败: {e}"))?;
+
+    info!("Added account {}", req.uid);
+    Ok(())
+}
+
+/// 切换当前账号，无需重启应用
+///
+/// 从安全存储恢复令牌并同步到 Rust 侧的会话，媒体缓存与日志随之切换到该账号的目录。
+#[tauri::command]
+pub async fn switch_account(
+    uid: String,
+    app_handle: AppHandle,
+    state: State<'_, AppData>,
+    app_state: State<'_, AppState>,
+) -> Result<AccountInfo, String> {
+    let db = state.db_conn.as_ref();
+    let user = im_user_repository::find_user(db, &uid)
+        .await?
+        .ok_or_else(|| format!("账号不存在: {uid}"))?;
+
+    let (token_column, refresh_column) = (user.token.clone(), user.refresh_token.clone());
+    let (token, refresh_token) =
+        run_blocking(move || Ok((load_column(token_column)?, load_column(refresh_column)?)))
+            .await
+            .map_err(|e| format!("读取令牌失败: {e}"))?;
+    let token = token.ok_or_else(|| "账号已退出登录，请重新登录".to_string())?;
+
+    {
+        let mut user_info = state.user_info.lock().await;
+        user_info.uid = uid.clone();
+        user_info.token = token.clone();
+        user_info.refresh_token = refresh_token.unwrap_or_default();
+    }
+    {
+        let mut config = app_state.config.lock().await;
+        if let Some(homeserver) = user.homeserver.as_deref().filter(|h| !h.is_empty()) {
+            config.homeserver = homeserver.to_string();
+        }
+        config.access_token = Some(token);
+    }
+    // 不同 homeserver 对认证媒体的支持不同，重新探测
+    app_state.authenticated_media.lock().await.clear();
+
+    im_user_repository::touch_account(db, &uid).await?;
+    account_storage::set_account(Some(&uid));
+    // 继续处理该账号未完成的上传
+    wake_upload_worker();
+
+    let payload = AccountSwitchedPayload {
+        uid: Some(uid.clone()),
+        homeserver: user.homeserver.clone(),
+    };
+    if let Err(e) = app_handle.emit(ACCOUNT_SWITCHED_EVENT, &payload) {
+        warn!("Failed to emit {} event: {}", ACCOUNT_SWITCHED_EVENT, e);
+    }
+
+    info!("Switched to account {}", uid);
+    Ok(AccountInfo {
+        uid: user.id,
+        name: user.name,
+        avatar: user.avatar,
+        homeserver: user.homeserver,
+        active: true,
+        last_active_time: Some(chrono::Utc::now().timestamp_millis()),
+        logged_in: true,
+    })
+}
+
+/// 删除账号及其全部本地数据：所有 im_* 表中的记录、令牌、媒体缓存与日志
+///
+/// 删除当前账号时会同时退出登录。
+#[tauri::command]
+pub async fn remove_account(
+    uid: String,
+    app_handle: AppHandle,
+    state: State<'_, AppData>,
+    app_state: State<'_, AppState>,
+) -> Result<(), String> {
+    if uid.is_empty() {
+        return Err("用户 ID 不能为空".to_string());
+    }
+
+    let db = state.db_conn.as_ref();
+    let user = im_user_repository::find_user(db, &uid).await?;
+
+    let txn = db.begin().await.map_err(|e| e.to_string())?;
+    im_user_repository::delete_account_data(&txn, &uid).await?;
+    txn.commit().await.map_err(|e| e.to_string())?;
+
+    // 数据库记录删除后再清理令牌与文件，失败时只记录日志，不影响账号删除
+    if let Some(user) = user {
+        let result = run_blocking(move || {
+            for reference in [user.token, user.refresh_token].into_iter().flatten() {
+                if secret_store::is_reference(&reference) {
+                    secret_store::delete(&reference)?;
+                }
+            }
+            Ok(())
+        })
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to delete tokens of {}: {}", uid, e);
+        }
+    }
+
+    let is_active = {
+        let mut user_info = state.user_info.lock().await;
+        let is_active = user_info.uid == uid;
+        if is_active {
+            user_info.uid.clear();
+            user_info.token.clear();
+            user_info.refresh_token.clear();
+        }
+        is_active
+    };
+    if is_active {
+        // 先关闭日志文件，否则 Windows 上无法删除日志目录
+        account_storage::set_account(None);
+        app_state.config.lock().await.access_token = None;
+    }
+
+    match get_cache_root(&app_handle) {
+        Ok(root) => remove_dir(root.join(account_dir_name(&uid))).await,
+        Err(e) => warn!("Failed to resolve media cache dir: {}", e),
+    }
+    if let Some(log_dir) = account_storage::account_log_dir(&uid) {
+        remove_dir(log_dir).await;
+    }
+
+    if is_active {
+        let payload = AccountSwitchedPayload {
+            uid: None,
+            homeserver: None,
+        };
+        if let Err(e) = app_handle.emit(ACCOUNT_SWITCHED_EVENT, &payload) {
+            warn!("Failed to emit {} event: {}", ACCOUNT_SWITCHED_EVENT, e);
+        }
+    }
+
+    info!("Removed account {}", uid);
+    Ok(())
+}
+
+async fn remove_dir(dir: std::path::PathBuf) {
+    match tokio::fs::remove_dir_all(&dir).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove {}: {}", dir.display(), e),
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use tauri::{AppHandle, State};
+use tracing::{info, warn};
+
+use crate::AppData;
+use crate::utils::db_cipher;
+
+async fn ensure_encrypted(state: &State<'_, AppData>) -> Result<(), String> {
+    if state.config.lock().await.database.encrypted {
+        Ok(())
+    } else {
+        Err("数据库未启用加密".to_string())
+    }
+}
+
+/// 更换数据库密钥
+///
+/// 完成后应用会自动重启，以便连接池中的连接使用新密钥重新打开数据库。
+#[tauri::command]
+pub async fn rekey(app_handle: AppHandle, state: State<'_, AppData>) -> Result<(), String> {
+    ensure_encrypted(&state).await?;
+    db_cipher::rekey(state.db_conn.get_sqlite_connection_pool())
+        .await
+        .map_err(|e| format!("更换数据库密钥失败: {e}"))?;
+
+    warn!("Database key changed, restarting");
+    app_handle.request_restart();
+    Ok(())
+}
+
+/// 把数据库导出为未加密的副本，供排查问题使用
+///
+/// `path` 为导出文件路径，文件已存在时返回错误。
+#[tauri::command]
+pub async fn export_plaintext(path: String, state: State<'_, AppData>) -> Result<String, String> {
+    ensure_encrypted(&state).await?;
+    let target = PathBuf::from(&path);
+    db_cipher::export_plaintext(state.db_conn.get_sqlite_connection_pool(), &target)
+        .await
+        .map_err(|e| format!("导出数据库失败: {e}"))?;
+
+    info!("Exported plaintext database to {}", path);
+    Ok(path)
+}
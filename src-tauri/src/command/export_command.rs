@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use entity::{im_media_cache, im_message};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::AppData;
+use crate::command::media::get_account_cache_dir;
+use crate::error::AppError;
+use crate::repository::im_media_cache_repository;
+use crate::repository::im_message_repository::{self, ChatHistoryFilter, RECALL_MESSAGE_TYPE};
+
+/// 导出进度事件
+const EXPORT_PROGRESS_EVENT: &str = "room-history-export-progress";
+
+/// 每批从数据库读取的消息条数
+const EXPORT_PAGE_SIZE: u64 = 500;
+
+/// 压缩包内保存媒体文件的目录
+const MEDIA_DIR: &str = "media";
+
+/// 导出格式
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// 可直接用浏览器打开的网页，媒体文件与网页一同放在压缩包中
+    Html,
+    /// 与 Matrix 事件结构一致的 JSON
+    Json,
+    Txt,
+}
+
+impl ExportFormat {
+    fn file_name(self) -> &'static str {
+        match self {
+            ExportFormat::Html => "messages.html",
+            ExportFormat::Json => "messages.json",
+            ExportFormat::Txt => "messages.txt",
+        }
+    }
+}
+
+/// 导出聊天记录的参数
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRoomHistoryReq {
+    pub room_id: String,
+    /// 房间名称，写入导出文件的标题
+    pub room_name: Option<String>,
+    pub format: ExportFormat,
+    /// 开始时间（毫秒），为空表示不限制
+    pub start_time: Option<i64>,
+    /// 结束时间（毫秒），为空表示不限制
+    pub end_time: Option<i64>,
+    /// 是否从媒体缓存复制图片、视频等文件，默认复制
+    pub include_media: Option<bool>,
+    /// 导出的 zip 文件路径
+    pub path: String,
+}
+
+/// 导出结果
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRoomHistoryResp {
+    pub path: String,
+    pub message_count: u64,
+    pub media_count: u64,
+    /// 未缓存到本地而没有导出的媒体数
+    pub missing_media_count: u64,
+}
+
+/// 导出进度，`stage` 为 messages、media 或 done
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ExportProgress<'a> {
+    room_id: &'a str,
+    stage: &'a str,
+    current: u64,
+    total: u64,
+}
+
+/// 待写入的消息与其引用的媒体缓存
+struct ExportItem {
+    message: im_message::Model,
+    media: Option<im_media_cache::Model>,
+}
+
+/// 写入压缩包的统计
+#[derive(Default)]
+struct ArchiveStats {
+    media_count: u64,
+    missing_media_count: u64,
+}
+
+fn emit_progress(app_handle: &AppHandle, room_id: &str, stage: &str, current: u64, total: u64) {
+    let progress = ExportProgress {
+        room_id,
+        stage,
+        current,
+        total,
+    };
+    if let Err(e) = app_handle.emit(EXPORT_PROGRESS_EVENT, &progress) {
+        warn!("Failed to emit {} event: {}", EXPORT_PROGRESS_EVENT, e);
+    }
+}
+
+/// 导出房间的聊天记录到 zip 文件
+///
+/// 消息按发送时间分批读取并直接写入压缩包，大房间也不会一次性加载到内存。
+/// 媒体文件从当前账号的媒体缓存复制到压缩包的 `media` 目录，未缓存的媒体只保留链接。
+/// 导出先写入临时文件，完成后再重命名，失败时不会留下不完整的文件。
+#[tauri::command]
+pub async fn export_room_history(
+    req: ExportRoomHistoryReq,
+    app_handle: AppHandle,
+    state: State<'_, AppData>,
+) -> Result<ExportRoomHistoryResp, String> {
+    if req.room_id.is_empty() {
+        return Err("房间 ID 不能为空".to_string());
+    }
+    if req
+        .start_time
+        .zip(req.end_time)
+        .is_some_and(|(start_time, end_time)| start_time > end_time)
+    {
+        return Err("开始时间不能晚于结束时间".to_string());
+    }
+    let target = PathBuf::from(&req.path);
+    if target.file_name().is_none() {
+        return Err("导出路径无效".to_string());
+    }
+
+    let db = state.db_conn.as_ref();
+    let login_uid = state.user_info.lock().await.uid.clone();
+    let filter = || ChatHistoryFilter {
+        room_id: req.room_id.clone(),
+        start_time: req.start_time,
+        end_time: req.end_time,
+        ascending: true,
+        ..Default::default()
+    };
+    let total = im_message_repository::count_chat_history(db, &filter(), &login_uid).await?;
+    let cache_dir = get_account_cache_dir(&app_handle, &login_uid)
+        .await
+        .map_err(|e| format!("获取媒体缓存目录失败: {e}"))?;
+
+    let part_path = partial_path(&target);
+    let (tx, rx) = mpsc::channel::<Vec<ExportItem>>(2);
+    let header = ArchiveHeader {
+        room_id: req.room_id.clone(),
+        room_name: req.room_name.clone(),
+        format: req.format,
+        start_time: req.start_time,
+        end_time: req.end_time,
+        total,
+    };
+    let writer = {
+        let app_handle = app_handle.clone();
+        let part_path = part_path.clone();
+        tokio::task::spawn_blocking(move || {
+            write_archive(&part_path, header, cache_dir, rx, &app_handle)
+        })
+    };
+
+    let include_media = req.include_media.unwrap_or(true);
+    let mut exported = 0;
+    let mut uncached = 0;
+    emit_progress(&app_handle, &req.room_id, "messages", 0, total);
+    let read_result: Result<(), String> = async {
+        let mut page = 1;
+        loop {
+            let (messages, has_more) = im_message_repository::query_chat_history(
+                db,
+                filter(),
+                &login_uid,
+                page,
+                EXPORT_PAGE_SIZE,
+            )
+            .await?;
+
+            let mut items = Vec::with_capacity(messages.len());
+            for message in messages {
+                let media = match message.mxc_url.as_deref() {
+                    Some(mxc_uri) if include_media => {
+                        let media = find_cached_media(db, mxc_uri, &login_uid).await?;
+                        if media.is_none() {
+                            uncached += 1;
+                        }
+                        media
+                    }
+                    _ => None,
+                };
+                items.push(ExportItem { message, media });
+            }
+
+            exported += items.len() as u64;
+            // 写入线程出错时通道会关闭，错误在下方等待写入线程时返回
+            if tx.send(items).await.is_err() {
+                return Ok(());
+            }
+            emit_progress(&app_handle, &req.room_id, "messages", exported, total);
+
+            if !has_more {
+                return Ok(());
+            }
+            page += 1;
+        }
+    }
+    .await;
+    drop(tx);
+
+    let write_result = writer
+        .await
+        .map_err(|e| AppError::Unexpected(e.to_string()))
+        .and_then(|result| result)
+        .map_err(|e| e.to_string());
+    let stats = match read_result.and(write_result) {
+        Ok(stats) => stats,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(format!("导出聊天记录失败: {e}"));
+        }
+    };
+    tokio::fs::rename(&part_path, &target)
+        .await
+        .map_err(|e| format!("保存导出文件失败: {e}"))?;
+
+    emit_progress(&app_handle, &req.room_id, "done", exported, total);
+    info!(
+        "Exported {} messages and {} media files of room {} to {}",
+        exported,
+        stats.media_count,
+        req.room_id,
+        target.display()
+    );
+    Ok(ExportRoomHistoryResp {
+        path: req.path,
+        message_count: exported,
+        media_count: stats.media_count,
+        missing_media_count: stats.missing_media_count + uncached,
+    })
+}
+
+fn partial_path(target: &Path) -> PathBuf {
+    let mut path = target.as_os_str().to_os_string();
+    path.push(".part");
+    PathBuf::from(path)
+}
+
+/// 查找 mxc URI 对应的缓存文件，优先使用原图，没有原图时使用缩略图
+async fn find_cached_media<C>(
+    db: &C,
+    mxc_uri: &str,
+    login_uid: &str,
+) -> Result<Option<im_media_cache::Model>, String>
+where
+    C: sea_orm::ConnectionTrait,
+{
+    let mut entries = im_media_cache_repository::find_by_mxc_uri(db, mxc_uri, login_uid).await?;
+    let original = entries
+        .iter()
+        .position(|entry| !entry.thumbnail)
+        .unwrap_or(0);
+    Ok((!entries.is_empty()).then(|| entries.swap_remove(original)))
+}
+
+/// 压缩包中的媒体文件名，按 MIME 类型补上扩展名，便于直接打开
+fn archive_media_name(entry: &im_media_cache::Model) -> String {
+    match entry.mime_type.as_deref().and_then(media_extension) {
+        Some(extension) => format!("{MEDIA_DIR}/{}.{extension}", entry.cache_key),
+        None => format!("{MEDIA_DIR}/{}", entry.cache_key),
+    }
+}
+
+/// MIME 类型对应的扩展名，优先使用与子类型同名的扩展名，如 image/jpeg 取 jpeg 而不是 jfif
+fn media_extension(mime_type: &str) -> Option<&'static str> {
+    let essence = mime_type.split(';').next()?.trim().to_ascii_lowercase();
+    let extensions = mime_guess::get_mime_extensions_str(&essence)?;
+    let subtype = essence.split_once('/')?.1;
+    extensions
+        .iter()
+        .find(|extension| **extension == subtype)
+        .or_else(|| extensions.first())
+        .copied()
+}
+
+/// 导出文件开头的房间信息
+struct ArchiveHeader {
+    room_id: String,
+    room_name: Option<String>,
+    format: ExportFormat,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    total: u64,
+}
+
+/// 在阻塞线程中写入压缩包
+///
+/// zip 同一时间只能写入一个文件，因此先写完消息文件，再追加消息中引用的媒体文件。
+fn write_archive(
+    path: &Path,
+    header: ArchiveHeader,
+    cache_dir: PathBuf,
+    mut rx: mpsc::Receiver<Vec<ExportItem>>,
+    app_handle: &AppHandle,
+) -> Result<ArchiveStats, AppError> {
+    let file = File::create(path).map_err(|e| AppError::Io(e.to_string()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    let text_options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file(header.format.file_name(), text_options)
+        .map_err(zip_error)?;
+    let mut renderer = Renderer::new(header.format);
+    renderer.begin(&mut zip, &header).map_err(io_error)?;
+
+    // 媒体文件名 -> 缓存中的文件路径，同一文件只复制一次
+    let mut media = HashMap::new();
+    while let Some(items) = rx.blocking_recv() {
+        for item in items {
+            let media_name = item.media.map(|entry| {
+                let name = archive_media_name(&entry);
+                media
+                    .entry(name.clone())
+                    .or_insert_with(|| cache_dir.join(&entry.cache_key));
+                name
+            });
+            renderer
+                .message(&mut zip, &item.message, media_name.as_deref())
+                .map_err(io_error)?;
+        }
+    }
+    renderer.end(&mut zip).map_err(io_error)?;
+
+    let mut stats = ArchiveStats::default();
+    let media_total = media.len() as u64;
+    for (index, (name, source)) in media.into_iter().enumerate() {
+        let mut source_file = match File::open(&source) {
+            Ok(file) => file,
+            Err(e) => {
+                // 索引中存在但文件已被清理
+                warn!("Skipped missing media {}: {}", source.display(), e);
+                stats.missing_media_count += 1;
+                continue;
+            }
+        };
+        let size = source_file.metadata().map(|m| m.len()).unwrap_or_default();
+        // 媒体文件大多已压缩，直接存储
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(size >= u64::from(u32::MAX));
+        zip.start_file(name, options).map_err(zip_error)?;
+        io::copy(&mut source_file, &mut zip).map_err(io_error)?;
+        stats.media_count += 1;
+        emit_progress(
+            app_handle,
+            &header.room_id,
+            "media",
+            index as u64 + 1,
+            media_total,
+        );
+    }
+
+    let mut writer = zip.finish().map_err(zip_error)?;
+    writer.flush().map_err(io_error)?;
+    Ok(stats)
+}
+
+fn io_error(e: io::Error) -> AppError {
+    AppError::Io(e.to_string())
+}
+
+fn zip_error(e: zip::result::ZipError) -> AppError {
+    AppError::Io(e.to_string())
+}
+
+/// 按导出格式逐条输出消息
+struct Renderer {
+    format: ExportFormat,
+    /// JSON 数组中是否已经写入过元素
+    has_events: bool,
+}
+
+impl Renderer {
+    fn new(format: ExportFormat) -> Self {
+        Self {
+            format,
+            has_events: false,
+        }
+    }
+
+    fn begin(&mut self, w: &mut impl Write, header: &ArchiveHeader) -> io::Result<()> {
+        let title = header.room_name.as_deref().unwrap_or(&header.room_id);
+        let range = format!(
+            "{} ~ {}",
+            header.start_time.map(format_time).unwrap_or_default(),
+            header.end_time.map(format_time).unwrap_or_default()
+        );
+        match self.format {
+            ExportFormat::Html => write!(
+                w,
+                "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n\
+                 <title>{title}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n\
+                 <header><h1>{title}</h1><p>{room_id}</p><p>{range} · {total}</p></header>\n<main>\n",
+                title = escape_html(title),
+                room_id = escape_html(&header.room_id),
+                range = escape_html(&range),
+                total = header.total,
+            ),
+            ExportFormat::Json => {
+                // 房间信息写在事件列表之前，事件逐条追加
+                let meta = json!({
+                    "room_id": header.room_id,
+                    "room_name": header.room_name,
+                    "start_time": header.start_time,
+                    "end_time": header.end_time,
+                    "exported_at": chrono::Utc::now().timestamp_millis(),
+                    "total": header.total,
+                });
+                let meta = meta.to_string();
+                // 去掉结尾的 `}`，接着写入 events 数组
+                write!(w, "{},\"events\":[", &meta[..meta.len() - 1])
+            }
+            ExportFormat::Txt => write!(
+                w,
+                "{title}\n{room_id}\n{range}\n\n",
+                room_id = header.room_id
+            ),
+        }
+    }
+
+    fn message(
+        &mut self,
+        w: &mut impl Write,
+        message: &im_message::Model,
+        media: Option<&str>,
+    ) -> io::Result<()> {
+        let body = parse_body(message.body.as_deref());
+        let recalled = message.message_type == Some(RECALL_MESSAGE_TYPE);
+        let text = if recalled {
+            "[消息已撤回]".to_string()
+        } else {
+            message_text(&body)
+        };
+        let sender = message.sender.as_deref().unwrap_or(&message.uid);
+        let name = message.nickname.as_deref().unwrap_or(sender);
+        let time = message
+            .send_time
+            .or(message.origin_server_ts)
+            .unwrap_or_default();
+
+        match self.format {
+            ExportFormat::Html => {
+                write!(
+                    w,
+                    "<article><div class=\"meta\"><span class=\"name\" title=\"{sender}\">{name}</span>\
+                     <time>{time}</time></div>",
+                    sender = escape_html(sender),
+                    name = escape_html(name),
+                    time = format_time(time),
+                )?;
+                if !text.is_empty() {
+                    write!(w, "<p>{}</p>", escape_html(&text).replace('\n', "<br>"))?;
+                }
+                if let Some(media) = media {
+                    let src = escape_html(media);
+                    match msgtype(message.message_type) {
+                        "m.image" => write!(w, "<img src=\"{src}\" loading=\"lazy\" alt=\"\">")?,
+                        "m.video" => write!(w, "<video src=\"{src}\" controls></video>")?,
+                        "m.audio" => write!(w, "<audio src=\"{src}\" controls></audio>")?,
+                        _ => write!(w, "<a href=\"{src}\">{src}</a>")?,
+                    }
+                }
+                writeln!(w, "</article>")
+            }
+            ExportFormat::Json => {
+                if self.has_events {
+                    w.write_all(b",")?;
+                }
+                self.has_events = true;
+                let event = matrix_event(message, body, recalled, sender, time, media);
+                serde_json::to_writer(&mut *w, &event)?;
+                w.write_all(b"\n")
+            }
+            ExportFormat::Txt => {
+                write!(w, "[{}] {}: {}", format_time(time), name, text)?;
+                if let Some(media) = media {
+                    write!(w, " ({media})")?;
+                }
+                writeln!(w)
+            }
+        }
+    }
+
+    fn end(&mut self, w: &mut impl Write) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Html => w.write_all(b"</main>\n</body>\n</html>\n"),
+            ExportFormat::Json => w.write_all(b"]}\n"),
+            ExportFormat::Txt => Ok(()),
+        }
+    }
+}
+
+/// 网页导出的样式，内联在网页中，无需额外文件
+const HTML_STYLE: &str = "body{margin:0;font-family:system-ui,-apple-system,sans-serif;\
+background:#f5f5f5;color:#222}header{padding:16px 24px;background:#fff;\
+border-bottom:1px solid #e5e5e5}header h1{margin:0 0 4px;font-size:20px}\
+header p{margin:2px 0;color:#888;font-size:13px}main{max-width:860px;margin:0 auto;\
+padding:16px}article{background:#fff;border-radius:8px;padding:10px 14px;margin:8px 0}\
+.meta{font-size:13px;color:#888;margin-bottom:4px}.name{color:#13987f;font-weight:600;\
+margin-right:8px}article p{margin:0;white-space:pre-wrap;word-break:break-word}\
+article img,article video{display:block;max-width:100%;max-height:480px;margin-top:6px;\
+border-radius:6px}article audio{margin-top:6px}";
+
+fn parse_body(body: Option<&str>) -> Value {
+    match body {
+        Some(body) => {
+            serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string()))
+        }
+        None => Value::Null,
+    }
+}
+
+/// 消息的文本内容，兼容 Matrix 的 `body` 与本地消息的 `content`
+fn message_text(body: &Value) -> String {
+    match body {
+        Value::String(text) => text.clone(),
+        Value::Object(map) => ["body", "content", "text", "fileName"]
+            .iter()
+            .find_map(|key| map.get(*key).and_then(Value::as_str))
+            .unwrap_or_default()
+            .to_string(),
+        _ => String::new(),
+    }
+}
+
+/// 消息类型对应的 Matrix msgtype，取值与前端 `MsgEnum` 一致
+fn msgtype(message_type: Option<u8>) -> &'static str {
+    match message_type {
+        Some(3) => "m.image",
+        Some(4) => "m.file",
+        Some(5) => "m.audio",
+        Some(6) => "m.video",
+        Some(10) => "m.notice",
+        Some(17) => "m.location",
+        _ => "m.text",
+    }
+}
+
+/// 转换为 Matrix 的 m.room.message 事件，撤回的消息按 redaction 后的结构输出
+fn matrix_event(
+    message: &im_message::Model,
+    body: Value,
+    recalled: bool,
+    sender: &str,
+    time: i64,
+    media: Option<&str>,
+) -> Value {
+    let content = if recalled {
+        Value::Object(Map::new())
+    } else {
+        let mut content = match body {
+            Value::Object(map) if map.contains_key("msgtype") => map,
+            body => {
+                let mut content = Map::new();
+                content.insert("msgtype".into(), msgtype(message.message_type).into());
+                content.insert("body".into(), message_text(&body).into());
+                content
+            }
+        };
+        if let Some(mxc_url) = &message.mxc_url {
+            content
+                .entry("url")
+                .or_insert_with(|| Value::String(mxc_url.clone()));
+        }
+        Value::Object(content)
+    };
+
+    let mut unsigned = Map::new();
+    if let Some(nickname) = &message.nickname {
+        unsigned.insert("displayname".into(), nickname.clone().into());
+    }
+    if let Some(media) = media {
+        unsigned.insert("local_file".into(), media.into());
+    }
+    if recalled {
+        unsigned.insert("redacted".into(), true.into());
+    }
+
+    json!({
+        "event_id": message.event_id.as_deref().unwrap_or(&message.id),
+        "room_id": message.room_id,
+        "sender": sender,
+        "origin_server_ts": message.origin_server_ts.unwrap_or(time),
+        "type": "m.room.message",
+        "content": content,
+        "unsigned": unsigned,
+    })
+}
+
+fn format_time(millis: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(millis)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
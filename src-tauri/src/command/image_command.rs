@@ -0,0 +1,629 @@
+//! 发送前的图片处理
+//!
+//! 按 EXIF 方向旋转、删除元数据、按长边缩小并重新编码，同时生成 Matrix 消息
+//! `info` 块所需的宽高、MIME 类型与大小。选择“原图”时不重新压缩，
+//! 只无损删除元数据，避免泄露拍摄位置。
+//!
+//! 另提供 BlurHash 的生成与解码，用于媒体加载前的模糊占位图，
+//! 以及把 HEIC/AVIF/动图等 webview 无法直接显示的图片转换为 PNG/JPEG。
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, State};
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::AppData;
+use crate::command::media::get_cache_dir;
+use crate::error::AppError;
+use crate::utils::blurhash;
+use crate::utils::image_decode;
+use crate::utils::image_metadata::{strip_jpeg_metadata, strip_png_metadata, strip_webp_metadata};
+
+/// 处理结果的保存目录，位于应用缓存目录下
+const PREPARED_IMAGE_DIR: &str = "prepared_images";
+
+/// 计算 BlurHash 前把图片缩小到的边长，分量计算量与像素数成正比
+const BLURHASH_SAMPLE_SIZE: u32 = 64;
+
+/// 解码 BlurHash 时的默认与最大边长，占位图会被拉伸显示，无需太大
+const BLURHASH_DEFAULT_SIZE: u32 = 32;
+const BLURHASH_MAX_SIZE: u32 = 128;
+
+/// 转换结果的保存目录，位于媒体缓存目录下
+pub(crate) const CONVERTED_IMAGE_DIR: &str = "converted_images";
+
+/// 转换结果的保留时间
+const CONVERTED_IMAGE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// 转换为 JPEG 时的编码质量
+const CONVERTED_JPEG_QUALITY: u8 = 90;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PrepareImageReq {
+    /// 本地图片路径
+    pub path: String,
+    /// 长边最大像素，默认取配置
+    pub max_edge: Option<u32>,
+    /// JPEG 编码质量（1-100），默认取配置
+    pub quality: Option<u8>,
+    /// 输出格式：jpeg、png、webp，默认与原图一致
+    ///
+    /// WebP 只有无损编码，压缩时改为输出 JPEG（有透明度时为 PNG）
+    pub format: Option<String>,
+    /// 发送原图：不缩放、不重新压缩，只删除元数据
+    #[serde(default)]
+    pub original_quality: bool,
+}
+
+/// Matrix `m.image` 消息的 `info` 块
+#[derive(Serialize, Debug, Clone)]
+pub struct ImageInfo {
+    pub w: u32,
+    pub h: u32,
+    pub mimetype: String,
+    pub size: u64,
+    #[serde(
+        rename = "xyz.amorgan.blurhash",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub blurhash: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PrepareImageResp {
+    /// 处理后的文件路径，用于上传
+    pub path: String,
+    pub info: ImageInfo,
+    /// 原文件大小
+    pub original_size: u64,
+    /// 是否重新编码
+    pub recompressed: bool,
+}
+
+/// 图片处理参数
+struct PrepareOptions {
+    max_edge: u32,
+    quality: u8,
+    format: Option<ImageFormat>,
+    original_quality: bool,
+}
+
+/// 图片处理结果
+struct PreparedImage {
+    data: Vec<u8>,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    recompressed: bool,
+}
+
+/// 处理待发送的图片
+#[tauri::command]
+pub async fn prepare_image_for_upload(
+    req: PrepareImageReq,
+    app_handle: AppHandle,
+    data: State<'_, AppData>,
+) -> Result<PrepareImageResp, AppError> {
+    info!(
+        "Preparing image for upload: {} (original: {})",
+        req.path, req.original_quality
+    );
+
+    let settings = data.config.lock().await.image_upload.clone();
+    let options = PrepareOptions {
+        max_edge: req.max_edge.unwrap_or(settings.max_edge).max(1),
+        quality: req.quality.unwrap_or(settings.quality).clamp(1, 100),
+        format: req.format.as_deref().map(parse_format).transpose()?,
+        original_quality: req.original_quality,
+    };
+
+    let source = fs::read(&req.path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    let original_size = source.len() as u64;
+
+    let (prepared, blurhash) = tokio::task::spawn_blocking(move || {
+        let prepared = prepare_image(source, &options)?;
+        // 占位图不影响发送，生成失败时忽略
+        let blurhash = blurhash_from_bytes(&prepared.data, None)
+            .inspect_err(|e| warn!("Failed to generate blurhash: {}", e))
+            .ok();
+        Ok::<_, AppError>((prepared, blurhash))
+    })
+    .await
+    .map_err(|e| AppError::Unexpected(e.to_string()))??;
+
+    let output_dir = get_output_dir(&app_handle).await?;
+    let extension = prepared.format.extensions_str().first().unwrap_or(&"img");
+    let output_path = output_dir.join(format!("{}.{extension}", uuid::Uuid::new_v4()));
+    fs::write(&output_path, &prepared.data)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    Ok(PrepareImageResp {
+        path: output_path.to_string_lossy().to_string(),
+        info: ImageInfo {
+            w: prepared.width,
+            h: prepared.height,
+            mimetype: prepared.format.to_mime_type().to_string(),
+            size: prepared.data.len() as u64,
+            blurhash,
+        },
+        original_size,
+        recompressed: prepared.recompressed,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeBlurhashReq {
+    /// 本地图片路径
+    pub path: Option<String>,
+    /// base64 或 data URL 形式的图片，例如视频缩略图
+    pub data: Option<String>,
+    /// 水平与垂直分量数（1-9），默认按宽高比取 4x3 或 3x4
+    pub components: Option<(u32, u32)>,
+}
+
+/// 为本地图片或视频缩略图生成 BlurHash
+#[tauri::command]
+pub async fn encode_blurhash(req: EncodeBlurhashReq) -> Result<String, AppError> {
+    let data = match (req.path, req.data) {
+        (Some(path), _) => fs::read(&path)
+            .await
+            .map_err(|e| AppError::Io(e.to_string()))?,
+        (None, Some(data)) => {
+            // 去掉 `data:image/jpeg;base64,` 前缀
+            let encoded = data.split_once(',').map_or(data.as_str(), |(_, data)| data);
+            STANDARD
+                .decode(encoded.trim())
+                .map_err(|e| AppError::Request(format!("Invalid base64 image: {e}")))?
+        }
+        (None, None) => {
+            return Err(AppError::Request(
+                "Either path or data is required".to_string(),
+            ));
+        }
+    };
+
+    tokio::task::spawn_blocking(move || blurhash_from_bytes(&data, req.components))
+        .await
+        .map_err(|e| AppError::Unexpected(e.to_string()))?
+}
+
+/// 把收到的 BlurHash 解码为 PNG data URL
+#[tauri::command]
+pub async fn decode_blurhash(
+    blurhash: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    punch: Option<f32>,
+) -> Result<String, AppError> {
+    let width = width
+        .unwrap_or(BLURHASH_DEFAULT_SIZE)
+        .clamp(1, BLURHASH_MAX_SIZE);
+    let height = height
+        .unwrap_or(BLURHASH_DEFAULT_SIZE)
+        .clamp(1, BLURHASH_MAX_SIZE);
+
+    let image = blurhash::decode(&blurhash, width, height, punch.unwrap_or(1.0))?;
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| AppError::Unexpected(e.to_string()))?;
+
+    Ok(format!("data:image/png;base64,{}", STANDARD.encode(png)))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertImageReq {
+    /// 本地图片路径
+    pub path: String,
+    /// 输出格式：png 或 jpeg，默认有透明度时为 PNG，否则为 JPEG
+    pub format: Option<String>,
+    /// 长边最大像素，默认不缩放
+    pub max_edge: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertImageResp {
+    /// 转换后的文件路径
+    pub path: String,
+    pub mimetype: String,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+    /// 原图是否为动图，动图只输出第一帧作为静态封面
+    pub animated: bool,
+}
+
+/// 把 HEIC/AVIF/WebP/GIF 等图片转换为 webview 可直接显示的 PNG/JPEG
+///
+/// 结果保存在媒体缓存目录中，同一文件以相同参数再次转换时直接返回缓存。
+#[tauri::command]
+pub async fn convert_image(
+    req: ConvertImageReq,
+    app_handle: AppHandle,
+) -> Result<ConvertImageResp, AppError> {
+    let format = match req.format.as_deref().map(parse_format).transpose()? {
+        Some(ImageFormat::WebP) => {
+            return Err(AppError::Request(
+                "Unsupported output format: webp".to_string(),
+            ));
+        }
+        format => format,
+    };
+
+    let output_dir = get_converted_dir(&app_handle).await?;
+    let key = conversion_key(&req).await?;
+    if let Some(cached) = find_converted(&output_dir, &key, format).await {
+        return Ok(cached);
+    }
+
+    info!("Converting image: {}", req.path);
+    let source = PathBuf::from(&req.path);
+    let max_edge = req.max_edge;
+    let (decoded, format, data) = tokio::task::spawn_blocking(move || {
+        let mut decoded = image_decode::decode_file(&source)?;
+        if let Some(max_edge) = max_edge.map(|edge| edge.max(1))
+            && decoded.image.width().max(decoded.image.height()) > max_edge
+        {
+            decoded.image = decoded
+                .image
+                .resize(max_edge, max_edge, FilterType::Lanczos3);
+        }
+        let format = format.unwrap_or(if decoded.image.color().has_alpha() {
+            ImageFormat::Png
+        } else {
+            ImageFormat::Jpeg
+        });
+        let data = encode_image(&decoded.image, format, CONVERTED_JPEG_QUALITY)?;
+        Ok::<_, AppError>((decoded, format, data))
+    })
+    .await
+    .map_err(|e| AppError::Unexpected(e.to_string()))??;
+
+    let output_path = output_dir.join(converted_file_name(&key, format, decoded.animated));
+    fs::write(&output_path, &data)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    info!(
+        "Image converted from {} to {}: {}x{}",
+        decoded.mime_type,
+        format.to_mime_type(),
+        decoded.image.width(),
+        decoded.image.height()
+    );
+
+    Ok(ConvertImageResp {
+        path: output_path.to_string_lossy().to_string(),
+        mimetype: format.to_mime_type().to_string(),
+        width: decoded.image.width(),
+        height: decoded.image.height(),
+        size: data.len() as u64,
+        animated: decoded.animated,
+    })
+}
+
+/// 获取转换结果目录，并顺带清理过期的转换结果
+async fn get_converted_dir(app_handle: &AppHandle) -> Result<PathBuf, AppError> {
+    let output_dir = get_cache_dir(app_handle).await?.join(CONVERTED_IMAGE_DIR);
+
+    fs::create_dir_all(&output_dir)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    if let Err(e) = remove_stale_files(&output_dir, CONVERTED_IMAGE_MAX_AGE).await {
+        warn!("Failed to clean up converted images: {}", e);
+    }
+
+    Ok(output_dir)
+}
+
+/// 由源文件路径、大小、修改时间与转换参数计算缓存键，源文件变化后缓存自然失效
+async fn conversion_key(req: &ConvertImageReq) -> Result<String, AppError> {
+    let metadata = fs::metadata(&req.path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    hasher.update(req.path.as_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(modified.as_nanos().to_le_bytes());
+    hasher.update(req.max_edge.unwrap_or_default().to_le_bytes());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// 转换结果的文件名，动图的封面带 `_poster` 后缀，以便命中缓存时还原该标记
+fn converted_file_name(key: &str, format: ImageFormat, animated: bool) -> String {
+    let extension = if format == ImageFormat::Png {
+        "png"
+    } else {
+        "jpg"
+    };
+    let suffix = if animated { "_poster" } else { "" };
+    format!("{key}{suffix}.{extension}")
+}
+
+/// 查找已有的转换结果；未指定格式时 PNG 与 JPEG 均可
+async fn find_converted(
+    output_dir: &Path,
+    key: &str,
+    format: Option<ImageFormat>,
+) -> Option<ConvertImageResp> {
+    let formats = match format {
+        Some(format) => vec![format],
+        None => vec![ImageFormat::Png, ImageFormat::Jpeg],
+    };
+
+    for format in formats {
+        for animated in [false, true] {
+            let path = output_dir.join(converted_file_name(key, format, animated));
+            let Ok(metadata) = fs::metadata(&path).await else {
+                continue;
+            };
+            let dimensions_path = path.clone();
+            let Ok(Ok((width, height))) =
+                tokio::task::spawn_blocking(move || image::image_dimensions(dimensions_path)).await
+            else {
+                continue;
+            };
+            return Some(ConvertImageResp {
+                path: path.to_string_lossy().to_string(),
+                mimetype: format.to_mime_type().to_string(),
+                width,
+                height,
+                size: metadata.len(),
+                animated,
+            });
+        }
+    }
+    None
+}
+
+/// 解码图片并按 EXIF 方向旋转后计算 BlurHash
+fn blurhash_from_bytes(data: &[u8], components: Option<(u32, u32)>) -> Result<String, AppError> {
+    let mut decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| AppError::Io(e.to_string()))?
+        .into_decoder()
+        .map_err(|e| AppError::Request(format!("Failed to read image: {e}")))?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder)
+        .map_err(|e| AppError::Request(format!("Failed to decode image: {e}")))?;
+    image.apply_orientation(orientation);
+
+    let (components_x, components_y) = components.unwrap_or(if image.width() >= image.height() {
+        (4, 3)
+    } else {
+        (3, 4)
+    });
+    let sample = image
+        .thumbnail(BLURHASH_SAMPLE_SIZE, BLURHASH_SAMPLE_SIZE)
+        .to_rgb8();
+
+    blurhash::encode(&sample, components_x, components_y)
+}
+
+fn parse_format(format: &str) -> Result<ImageFormat, AppError> {
+    match format.to_ascii_lowercase().as_str() {
+        "jpeg" | "jpg" | "image/jpeg" => Ok(ImageFormat::Jpeg),
+        "png" | "image/png" => Ok(ImageFormat::Png),
+        "webp" | "image/webp" => Ok(ImageFormat::WebP),
+        _ => Err(AppError::Request(format!(
+            "Unsupported output format: {format}"
+        ))),
+    }
+}
+
+/// 获取输出目录，并顺带清理过期的处理结果
+async fn get_output_dir(app_handle: &AppHandle) -> Result<PathBuf, AppError> {
+    let output_dir = app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| AppError::Io(e.to_string()))?
+        .join(PREPARED_IMAGE_DIR);
+
+    fs::create_dir_all(&output_dir)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    if let Err(e) = remove_stale_files(&output_dir, PROCESSED_FILE_MAX_AGE).await {
+        warn!("Failed to clean up prepared images: {}", e);
+    }
+
+    Ok(output_dir)
+}
+
+/// 发送前处理结果（图片、视频与语音）的保留时间
+///
+/// 上传队列最多自动重试 8 次、每次等待不超过 10 分钟，之后失败的上传只能通过
+/// `retry_media_upload` 手动重试；处理结果保留 7 天，供用户在此期间手动重试。
+pub(crate) const PROCESSED_FILE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// 删除目录中修改时间早于 `max_age` 的文件
+pub(crate) async fn remove_stale_files(dir: &Path, max_age: Duration) -> std::io::Result<()> {
+    let now = SystemTime::now();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let modified = entry.metadata().await?.modified()?;
+        if now.duration_since(modified).is_ok_and(|age| age > max_age) {
+            fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 方向为旋转 90° 或 270° 时宽高互换
+fn oriented_dimensions((width, height): (u32, u32), orientation: Orientation) -> (u32, u32) {
+    match orientation {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => (height, width),
+        _ => (width, height),
+    }
+}
+
+fn prepare_image(data: Vec<u8>, options: &PrepareOptions) -> Result<PreparedImage, AppError> {
+    let format = image::guess_format(&data)
+        .map_err(|e| AppError::Request(format!("Unrecognized image: {e}")))?;
+
+    let mut decoder = ImageReader::with_format(Cursor::new(&data), format)
+        .into_decoder()
+        .map_err(|e| AppError::Request(format!("Failed to read image: {e}")))?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let (width, height) = oriented_dimensions(decoder.dimensions(), orientation);
+
+    // 动图重新编码会丢失动画，原样发送
+    if format == ImageFormat::Gif {
+        drop(decoder);
+        return Ok(PreparedImage {
+            data,
+            format,
+            width,
+            height,
+            recompressed: false,
+        });
+    }
+
+    // 无损删除元数据的版本；JPEG 会写回方向，其他格式只有无需旋转时才能直接使用
+    let stripped = match format {
+        ImageFormat::Jpeg => strip_jpeg_metadata(&data, orientation.to_exif()),
+        ImageFormat::Png => strip_png_metadata(&data),
+        ImageFormat::WebP => strip_webp_metadata(&data),
+        _ => {
+            return Err(AppError::Request(format!(
+                "Unsupported image format: {}",
+                format.to_mime_type()
+            )));
+        }
+    }
+    .filter(|_| format == ImageFormat::Jpeg || orientation == Orientation::NoTransforms);
+
+    if options.original_quality
+        && let Some(stripped) = stripped.as_ref()
+    {
+        return Ok(PreparedImage {
+            data: stripped.clone(),
+            format,
+            width,
+            height,
+            recompressed: false,
+        });
+    }
+
+    let mut image = DynamicImage::from_decoder(decoder)
+        .map_err(|e| AppError::Request(format!("Failed to decode image: {e}")))?;
+    image.apply_orientation(orientation);
+
+    // 原图但无法无损处理时（如 PNG 需要旋转），以原格式无损或最高质量重新编码
+    if options.original_quality {
+        let data = encode_image(&image, format, 100)?;
+        return Ok(PreparedImage {
+            data,
+            format,
+            width,
+            height,
+            recompressed: true,
+        });
+    }
+
+    let resized = width.max(height) > options.max_edge;
+    if resized {
+        image = image.resize(options.max_edge, options.max_edge, FilterType::Lanczos3);
+    }
+
+    // 无损 WebP 往往比原图还大，改用 JPEG；JPEG 不支持透明度
+    let target = match options.format.unwrap_or(format) {
+        ImageFormat::Jpeg | ImageFormat::WebP
+            if image.color().has_alpha() && format != ImageFormat::Jpeg =>
+        {
+            ImageFormat::Png
+        }
+        ImageFormat::WebP => ImageFormat::Jpeg,
+        target => target,
+    };
+    let encoded = encode_image(&image, target, options.quality)?;
+
+    // 未缩放时重新编码可能反而更大，此时发送删除了元数据的原文件
+    if !resized
+        && target == format
+        && let Some(stripped) = stripped
+        && stripped.len() <= encoded.len()
+    {
+        return Ok(PreparedImage {
+            data: stripped,
+            format,
+            width,
+            height,
+            recompressed: false,
+        });
+    }
+
+    Ok(PreparedImage {
+        data: encoded,
+        format: target,
+        width: image.width(),
+        height: image.height(),
+        recompressed: true,
+    })
+}
+
+/// 编码图片；PNG 与 WebP 为无损编码，`quality` 只作用于 JPEG
+///
+/// image 只提供无损的 WebP 编码器，WebP 仅用于原图需要旋转时重新编码
+fn encode_image(
+    image: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+) -> Result<Vec<u8>, AppError> {
+    let mut data = Vec::new();
+    let result = match format {
+        ImageFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, quality)),
+        ImageFormat::Png => image.write_with_encoder(PngEncoder::new_with_quality(
+            &mut data,
+            CompressionType::Best,
+            PngFilterType::Adaptive,
+        )),
+        // WebP 编码器只支持 8 位 RGB/RGBA
+        ImageFormat::WebP if image.color().has_alpha() => image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut data)),
+        ImageFormat::WebP => image
+            .to_rgb8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut data)),
+        _ => {
+            return Err(AppError::Request(format!(
+                "Unsupported output format: {}",
+                format.to_mime_type()
+            )));
+        }
+    };
+    result.map_err(|e| AppError::Unexpected(format!("Failed to encode image: {e}")))?;
+
+    Ok(data)
+}
@@ -1,14 +1,63 @@
 //! Media commands for handling file uploads, downloads, and caching
 
-use crate::{error::AppError, state::AppState};
+use crate::command::image_command::CONVERTED_IMAGE_DIR;
+use crate::repository::im_config_repository::{self, MEDIA_CACHE_MAX_SIZE_KEY};
+use crate::repository::im_media_cache_repository::{
+    self, MediaCachePruneFilter, MediaCacheSummary,
+};
+use crate::repository::im_message_repository;
+use crate::utils::account_storage::account_dir_name;
+use crate::utils::attachment_crypto::{AttachmentCipher, AttachmentEncryptor, EncryptedFile};
+use crate::utils::image_decode;
+use crate::{AppData, error::AppError, state::AppState};
+use entity::im_media_cache;
+use sea_orm::DatabaseConnection;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use tauri::{Manager, State, command};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{Emitter, Manager, State, command};
 use tokio::fs;
-use tracing::info;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{oneshot, watch};
+use tracing::{info, warn};
+
+/// Event emitted while a download is in progress
+const DOWNLOAD_PROGRESS_EVENT: &str = "media-download-progress";
+
+/// Event emitted while an upload is in progress
+const UPLOAD_PROGRESS_EVENT: &str = "media-upload-progress";
+
+/// Size of the chunks an upload is read and sent in
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// JPEG quality of locally generated thumbnails
+const THUMBNAIL_JPEG_QUALITY: u8 = 80;
+
+/// Minimum interval between two progress events of the same transfer
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Partial downloads left untouched this long are no longer resumed and get deleted
+const STALE_PARTIAL_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+lazy_static::lazy_static! {
+    /// In-flight downloads, keyed by the cache file they write
+    static ref ACTIVE_DOWNLOADS: Mutex<HashMap<PathBuf, DownloadEntry>> =
+        Mutex::new(HashMap::new());
+}
+
+/// An in-flight download in [`ACTIVE_DOWNLOADS`]
+struct DownloadEntry {
+    /// Id the download was started with, used to cancel it
+    download_id: String,
+    cancel_tx: watch::Sender<bool>,
+    /// Closed when the download finishes
+    finished_rx: watch::Receiver<()>,
+}
 
 /// Media download options
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DownloadMediaOptions {
     /// Matrix content URI (mxc://)
     pub mxc_uri: String,
@@ -16,10 +65,129 @@ pub struct DownloadMediaOptions {
     pub force: bool,
     /// Maximum file size in bytes
     pub max_size: Option<usize>,
+    /// Request a server-side thumbnail instead of the original file
+    #[serde(default)]
+    pub thumbnail: Option<ThumbnailOptions>,
+    /// Id used to cancel the download, defaults to [`download_key`]
+    #[serde(default)]
+    pub download_id: Option<String>,
+    /// Room the media belongs to, recorded in the cache index
+    #[serde(default)]
+    pub room_id: Option<String>,
 }
 
-/// Media download result
+/// Thumbnail request options
+#[derive(Debug, Deserialize)]
+pub struct MediaThumbnailOptions {
+    /// Matrix content URI (mxc://)
+    pub mxc_uri: String,
+    /// Desired width in pixels
+    pub width: u32,
+    /// Desired height in pixels
+    pub height: u32,
+    /// Resize method: `crop` or `scale` (default)
+    pub method: Option<String>,
+    /// Maximum size of the original file when the thumbnail is generated locally
+    pub max_size: Option<usize>,
+    /// Room the media belongs to, recorded in the cache index
+    #[serde(default)]
+    pub room_id: Option<String>,
+    /// Message the thumbnail path is saved to
+    #[serde(default)]
+    pub message_id: Option<String>,
+}
+
+/// Media upload options
+#[derive(Debug, Deserialize)]
+pub struct UploadMediaOptions {
+    /// Local file to upload
+    pub path: String,
+    /// MIME type, guessed from the file extension if omitted
+    pub mime_type: Option<String>,
+    /// File name sent to the server, defaults to the local file name
+    pub file_name: Option<String>,
+    /// Encrypt the file for an end-to-end encrypted room
+    #[serde(default)]
+    pub encrypt: bool,
+    /// Id reported in progress events, defaults to the file path
+    #[serde(default)]
+    pub upload_id: Option<String>,
+}
+
+/// Media upload result
 #[derive(Debug, Serialize)]
+pub struct UploadMediaResult {
+    /// Matrix content URI (mxc://)
+    pub content_uri: String,
+    /// File size in bytes
+    pub size: u64,
+    /// MIME type
+    pub mime_type: String,
+    /// Image width in pixels
+    pub width: Option<u32>,
+    /// Image height in pixels
+    pub height: Option<u32>,
+    /// `EncryptedFile` to send instead of `url` when `encrypt` was set
+    pub file: Option<EncryptedFile>,
+}
+
+/// Payload of the `media-upload-progress` event
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaUploadProgress {
+    /// Id passed in `UploadMediaOptions`, or the file path
+    pub upload_id: String,
+    /// Local file being uploaded
+    pub path: String,
+    /// Bytes sent so far
+    pub bytes: u64,
+    /// File size in bytes
+    pub total: u64,
+    /// Average speed in bytes per second
+    pub speed: f64,
+}
+
+/// Encrypted media download options
+#[derive(Debug, Deserialize)]
+pub struct DownloadEncryptedMediaOptions {
+    /// `EncryptedFile` from the event content (`file` or `thumbnail_file`)
+    pub file: EncryptedFile,
+    /// MIME type from the event `info`; the server only sees ciphertext
+    pub mime_type: Option<String>,
+    /// Force re-download even if cached
+    #[serde(default)]
+    pub force: bool,
+    /// Maximum file size in bytes
+    pub max_size: Option<usize>,
+    /// Id used to cancel the download, defaults to the MXC URI
+    #[serde(default)]
+    pub download_id: Option<String>,
+    /// Room the media belongs to, recorded in the cache index
+    #[serde(default)]
+    pub room_id: Option<String>,
+}
+
+/// Server-side thumbnail parameters
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThumbnailOptions {
+    /// Desired width in pixels
+    pub width: u32,
+    /// Desired height in pixels
+    pub height: u32,
+    /// Resize method: `crop` or `scale` (default)
+    pub method: Option<String>,
+}
+
+impl ThumbnailOptions {
+    fn method(&self) -> &str {
+        match self.method.as_deref() {
+            Some("crop") => "crop",
+            _ => "scale",
+        }
+    }
+}
+
+/// Media download result
+#[derive(Debug, Clone, Serialize)]
 pub struct DownloadMediaResult {
     /// Local file path
     pub local_path: String,
@@ -29,8 +197,37 @@ pub struct DownloadMediaResult {
     pub mime_type: String,
 }
 
+/// Payload of the `media-download-progress` event
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaDownloadProgress {
+    /// Id passed in `DownloadMediaOptions`, or the MXC URI
+    pub download_id: String,
+    /// Matrix content URI being downloaded
+    pub mxc_uri: String,
+    /// Bytes on disk so far, including a resumed prefix
+    pub bytes: u64,
+    /// Total size if known
+    pub total: Option<u64>,
+    /// Average speed of this transfer in bytes per second
+    pub speed: f64,
+}
+
+/// Options of `prune_media_cache`; all given conditions must match
+#[derive(Debug, Default, Deserialize)]
+pub struct PruneMediaCacheOptions {
+    /// Remove media not accessed within this many seconds
+    pub max_age_secs: Option<u64>,
+    /// Remove media of this room only
+    pub room_id: Option<String>,
+    /// MIME type or prefix, e.g. `image/` or `video/mp4`
+    pub mime_type: Option<String>,
+    /// Also remove pinned media
+    #[serde(default)]
+    pub include_pinned: bool,
+}
+
 /// Cache statistics
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub struct CacheStats {
     /// Number of cached files
     pub count: usize,
@@ -42,8 +239,23 @@ pub struct CacheStats {
     pub newest_entry: Option<u64>,
 }
 
+impl From<MediaCacheSummary> for CacheStats {
+    fn from(summary: MediaCacheSummary) -> Self {
+        Self {
+            count: summary.count as usize,
+            total_size: summary.total_size,
+            oldest_entry: summary
+                .oldest_created_at
+                .map(|ms| (ms / 1000).max(0) as u64),
+            newest_entry: summary
+                .newest_created_at
+                .map(|ms| (ms / 1000).max(0) as u64),
+        }
+    }
+}
+
 /// Parse MXC URI to extract server name and media ID
-fn parse_mxc_uri(mxc_uri: &str) -> Result<(String, String), AppError> {
+pub(crate) fn parse_mxc_uri(mxc_uri: &str) -> Result<(String, String), AppError> {
     if !mxc_uri.starts_with("mxc://") {
         return Err(AppError::InvalidUri("Invalid MXC URI format".to_string()));
     }
@@ -67,14 +279,36 @@ fn parse_mxc_uri(mxc_uri: &str) -> Result<(String, String), AppError> {
     Ok((server_name, media_id))
 }
 
-/// Get media cache directory
-async fn get_cache_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, AppError> {
+/// Root of the media cache; every account caches into its own subdirectory
+pub(crate) fn get_cache_root(app_handle: &tauri::AppHandle) -> Result<PathBuf, AppError> {
     let app_dir = app_handle
         .path()
         .app_data_dir()
         .map_err(|e| AppError::Io(e.to_string()))?;
 
-    let cache_dir = app_dir.join("media_cache");
+    Ok(app_dir.join("media_cache"))
+}
+
+/// Login uid of the active account, empty when logged out
+async fn current_login_uid(data: &AppData) -> String {
+    data.user_info.lock().await.uid.clone()
+}
+
+/// Get the media cache directory of the active account
+pub(crate) async fn get_cache_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, AppError> {
+    let login_uid = match app_handle.try_state::<AppData>() {
+        Some(data) => current_login_uid(&data).await,
+        None => String::new(),
+    };
+    get_account_cache_dir(app_handle, &login_uid).await
+}
+
+/// Get the media cache directory of an account
+pub(crate) async fn get_account_cache_dir(
+    app_handle: &tauri::AppHandle,
+    login_uid: &str,
+) -> Result<PathBuf, AppError> {
+    let cache_dir = get_cache_root(app_handle)?.join(account_dir_name(login_uid));
 
     if !cache_dir.exists() {
         fs::create_dir_all(&cache_dir)
@@ -93,66 +327,971 @@ fn get_cache_path(cache_dir: &Path, server_name: &str, media_id: &str) -> PathBu
     cache_dir.join(format!("{safe_server}_{safe_media}"))
 }
 
+/// Get the temporary path a download is streamed into before being renamed
+fn get_partial_path(local_path: &Path) -> PathBuf {
+    let mut path = local_path.as_os_str().to_os_string();
+    path.push(".part");
+    PathBuf::from(path)
+}
+
+/// File name of a cached file, used as its key in the cache index
+fn get_cache_key(local_path: &Path) -> String {
+    local_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Current time in milliseconds
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// Delete cached files together with their index entries, returning what was removed
+async fn remove_cache_entries(
+    db: &DatabaseConnection,
+    cache_dir: &Path,
+    entries: Vec<im_media_cache::Model>,
+    login_uid: &str,
+) -> Result<CacheStats, AppError> {
+    let mut removed = CacheStats::default();
+    let mut cache_keys = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        match fs::remove_file(cache_dir.join(&entry.cache_key)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!("Failed to delete cached media {}: {}", entry.cache_key, e);
+                continue;
+            }
+        }
+
+        let created_at = (entry.created_at / 1000).max(0) as u64;
+        removed.count += 1;
+        removed.total_size += entry.size.max(0) as u64;
+        removed.oldest_entry = Some(
+            removed
+                .oldest_entry
+                .map_or(created_at, |ts| ts.min(created_at)),
+        );
+        removed.newest_entry = Some(
+            removed
+                .newest_entry
+                .map_or(created_at, |ts| ts.max(created_at)),
+        );
+        cache_keys.push(entry.cache_key);
+    }
+
+    im_media_cache_repository::delete_entries(db, cache_keys, login_uid).await?;
+
+    Ok(removed)
+}
+
+/// Cache quota of an account: the one saved with `set_media_cache_quota`,
+/// or the configured default
+async fn get_cache_quota(data: &AppData, login_uid: &str) -> Result<u64, AppError> {
+    let saved =
+        im_config_repository::get_value(data.db_conn.as_ref(), MEDIA_CACHE_MAX_SIZE_KEY, login_uid)
+            .await?
+            .and_then(|value| value.parse::<u64>().ok());
+
+    match saved {
+        Some(max_size) => Ok(max_size),
+        None => Ok(data.config.lock().await.media_cache.max_size_bytes),
+    }
+}
+
+/// Whether a `.part` file has not been written to for [`STALE_PARTIAL_AGE`]
+fn is_stale_partial(metadata: &std::fs::Metadata) -> bool {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > STALE_PARTIAL_AGE)
+}
+
+/// Delete abandoned partial downloads, returning how many files and bytes were freed
+///
+/// Partial files have no index entry, so the quota can't see them otherwise.
+async fn remove_stale_partials(cache_dir: &Path) -> Result<(u64, u64), AppError> {
+    let (mut count, mut size) = (0, 0);
+    let mut entries = fs::read_dir(cache_dir)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?
+    {
+        if !entry.file_name().to_string_lossy().ends_with(".part") {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        if !metadata.is_file() || !is_stale_partial(&metadata) {
+            continue;
+        }
+
+        match fs::remove_file(entry.path()).await {
+            Ok(()) => {
+                count += 1;
+                size += metadata.len();
+            }
+            Err(e) => warn!(
+                "Failed to delete partial download {}: {}",
+                entry.path().display(),
+                e
+            ),
+        }
+    }
+
+    Ok((count, size))
+}
+
+/// Evict least recently used media until the account's cache fits in its quota
+async fn enforce_cache_quota(
+    data: &AppData,
+    cache_dir: &Path,
+    keep: Option<&str>,
+    login_uid: &str,
+) -> Result<(), AppError> {
+    let max_size = get_cache_quota(data, login_uid).await?;
+    let candidates = im_media_cache_repository::find_eviction_candidates(
+        data.db_conn.as_ref(),
+        max_size,
+        keep,
+        login_uid,
+    )
+    .await?;
+
+    if !candidates.is_empty() {
+        let (partials, partial_size) = remove_stale_partials(cache_dir).await?;
+        if partials > 0 {
+            info!(
+                "Removed {} stale partial downloads ({} bytes)",
+                partials, partial_size
+            );
+        }
+
+        let evicted =
+            remove_cache_entries(data.db_conn.as_ref(), cache_dir, candidates, login_uid).await?;
+        info!(
+            "Evicted {} cached media files ({} bytes) to stay under {} bytes",
+            evicted.count, evicted.total_size, max_size
+        );
+    }
+
+    Ok(())
+}
+
+/// Total size from a `Content-Range: bytes start-end/total` header
+fn get_content_range_total(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit_once('/')?
+        .1
+        .parse()
+        .ok()
+}
+
+/// Average speed in bytes per second
+fn get_speed(bytes: u64, started_at: Instant) -> f64 {
+    let elapsed = started_at.elapsed().as_secs_f64();
+    if elapsed > 0.0 {
+        bytes as f64 / elapsed
+    } else {
+        0.0
+    }
+}
+
+/// Get local file path for a decrypted end-to-end encrypted attachment
+fn get_decrypted_cache_path(cache_dir: &Path, server_name: &str, media_id: &str) -> PathBuf {
+    let mut path = get_cache_path(cache_dir, server_name, media_id).into_os_string();
+    path.push("_decrypted");
+    PathBuf::from(path)
+}
+
+/// Get local file path for a cached thumbnail variant
+fn get_thumbnail_cache_path(
+    cache_dir: &Path,
+    server_name: &str,
+    media_id: &str,
+    thumbnail: &ThumbnailOptions,
+) -> PathBuf {
+    let mut path = get_cache_path(cache_dir, server_name, media_id).into_os_string();
+    path.push(format!(
+        "_thumb_{}x{}_{}",
+        thumbnail.width,
+        thumbnail.height,
+        thumbnail.method()
+    ));
+    PathBuf::from(path)
+}
+
+/// Response of `/_matrix/client/versions`
+#[derive(Debug, Deserialize)]
+struct VersionsResponse {
+    #[serde(default)]
+    versions: Vec<String>,
+    #[serde(default)]
+    unstable_features: HashMap<String, bool>,
+}
+
+impl VersionsResponse {
+    /// Authenticated media was stabilised in Matrix v1.11 (MSC3916)
+    fn supports_authenticated_media(&self) -> bool {
+        let spec_version = self.versions.iter().any(|version| {
+            version
+                .strip_prefix('v')
+                .and_then(|version| version.split_once('.'))
+                .and_then(|(major, minor)| {
+                    Some((major.parse::<u32>().ok()?, minor.parse::<u32>().ok()?))
+                })
+                .is_some_and(|version| version >= (1, 11))
+        });
+
+        spec_version
+            || self
+                .unstable_features
+                .get("org.matrix.msc3916.stable")
+                .copied()
+                .unwrap_or(false)
+    }
+}
+
+/// Check whether the homeserver serves authenticated media, caching the answer per homeserver
+async fn supports_authenticated_media(state: &AppState, homeserver: &str) -> bool {
+    if let Some(supported) = state.authenticated_media.lock().await.get(homeserver) {
+        return *supported;
+    }
+
+    let url = format!("{homeserver}/_matrix/client/versions");
+    let versions = async {
+        state
+            .http_client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<VersionsResponse>()
+            .await
+    }
+    .await;
+
+    match versions {
+        Ok(versions) => {
+            let supported = versions.supports_authenticated_media();
+            state
+                .authenticated_media
+                .lock()
+                .await
+                .insert(homeserver.to_string(), supported);
+            supported
+        }
+        Err(e) => {
+            // Do not cache failures; modern servers are the common case
+            warn!("Failed to query {}: {}", url, e);
+            true
+        }
+    }
+}
+
+/// Build the download or thumbnail URL for a piece of media
+fn build_media_url(
+    homeserver: &str,
+    authenticated: bool,
+    server_name: &str,
+    media_id: &str,
+    thumbnail: Option<&ThumbnailOptions>,
+) -> String {
+    let base = if authenticated {
+        "_matrix/client/v1/media"
+    } else {
+        "_matrix/media/v3"
+    };
+
+    match thumbnail {
+        Some(thumbnail) => format!(
+            "{homeserver}/{base}/thumbnail/{server_name}/{media_id}?width={}&height={}&method={}",
+            thumbnail.width,
+            thumbnail.height,
+            thumbnail.method()
+        ),
+        None => format!("{homeserver}/{base}/download/{server_name}/{media_id}"),
+    }
+}
+
+/// Matrix error response body
+#[derive(Debug, Deserialize)]
+struct MatrixErrorBody {
+    errcode: String,
+    #[serde(default)]
+    error: String,
+    retry_after_ms: Option<u64>,
+}
+
+/// Convert a failed media response into an `AppError`
+pub(crate) async fn media_error(response: reqwest::Response, mxc_uri: &str) -> AppError {
+    let status = response.status();
+    let body = response.json::<MatrixErrorBody>().await.ok();
+
+    match body.as_ref().map(|body| body.errcode.as_str()) {
+        Some("M_NOT_FOUND") => AppError::MediaNotFound(mxc_uri.to_string()),
+        Some("M_TOO_LARGE") => AppError::MediaTooLarge(mxc_uri.to_string()),
+        Some("M_LIMIT_EXCEEDED") => {
+            AppError::RateLimited(body.and_then(|body| body.retry_after_ms).unwrap_or(0))
+        }
+        Some("M_UNKNOWN_TOKEN" | "M_MISSING_TOKEN") => AppError::TokenExpired,
+        _ => match status {
+            reqwest::StatusCode::NOT_FOUND => AppError::MediaNotFound(mxc_uri.to_string()),
+            reqwest::StatusCode::PAYLOAD_TOO_LARGE => AppError::MediaTooLarge(mxc_uri.to_string()),
+            reqwest::StatusCode::TOO_MANY_REQUESTS => AppError::RateLimited(0),
+            reqwest::StatusCode::UNAUTHORIZED => AppError::TokenExpired,
+            _ => AppError::Network(format!(
+                "HTTP {status}: {}",
+                body.map(|body| body.error).unwrap_or_default()
+            )),
+        },
+    }
+}
+
 /// Download media from Matrix server
 #[command]
 pub async fn download_media(
     options: DownloadMediaOptions,
     app_handle: tauri::AppHandle,
     state: State<'_, AppState>,
+    data: State<'_, AppData>,
 ) -> Result<DownloadMediaResult, AppError> {
     info!("Downloading media: {}", options.mxc_uri);
 
+    get_or_download_media(&options, &app_handle, &state, &data).await
+}
+
+/// Return the cached copy of a media file, downloading it on a cache miss
+pub(crate) async fn get_or_download_media(
+    options: &DownloadMediaOptions,
+    app_handle: &tauri::AppHandle,
+    state: &AppState,
+    data: &AppData,
+) -> Result<DownloadMediaResult, AppError> {
     // Parse MXC URI
     let (server_name, media_id) = parse_mxc_uri(&options.mxc_uri)?;
 
-    // Get cache directory
-    let cache_dir = get_cache_dir(&app_handle).await?;
-    let local_path = get_cache_path(&cache_dir, &server_name, &media_id);
+    // Get cache directory; the account is fixed for the whole download
+    let login_uid = current_login_uid(data).await;
+    let cache_dir = get_account_cache_dir(app_handle, &login_uid).await?;
+    let local_path = match &options.thumbnail {
+        Some(thumbnail) => get_thumbnail_cache_path(&cache_dir, &server_name, &media_id, thumbnail),
+        None => get_cache_path(&cache_dir, &server_name, &media_id),
+    };
+
+    let download_id = options
+        .download_id
+        .clone()
+        .unwrap_or_else(|| download_key(options));
+
+    // An identical download that finished while waiting satisfies a forced one too
+    let mut force = options.force;
+    let active = loop {
+        // Check if file exists in cache
+        if !force && let Some(result) = get_cached_media(data, &local_path, &login_uid).await? {
+            return Ok(result);
+        }
+        match begin_download(&local_path, &download_id).await {
+            Some(active) => break active,
+            None => force = false,
+        }
+    };
+
+    let result = fetch_to_cache(
+        options,
+        &download_id,
+        &server_name,
+        &media_id,
+        &local_path,
+        active.cancel_rx.clone(),
+        app_handle,
+        state,
+    )
+    .await?;
+
+    record_cache_entry(
+        data,
+        options,
+        &local_path,
+        result.size,
+        &result.mime_type,
+        &login_uid,
+    )
+    .await?;
+    enforce_cache_quota(
+        data,
+        &cache_dir,
+        Some(&get_cache_key(&local_path)),
+        &login_uid,
+    )
+    .await?;
+    // Waiters read the cache index, so the download stays registered until it is written
+    drop(active);
+
+    Ok(result)
+}
+
+/// Look up a cached file and its MIME type in the cache index
+///
+/// Files without an index entry are treated as a cache miss: their name has no
+/// extension, so the MIME type can only come from the server's `Content-Type`.
+async fn get_cached_media(
+    data: &AppData,
+    local_path: &Path,
+    login_uid: &str,
+) -> Result<Option<DownloadMediaResult>, AppError> {
+    let Ok(metadata) = fs::metadata(local_path).await else {
+        return Ok(None);
+    };
+
+    let cache_key = get_cache_key(local_path);
+    let Some(mime_type) =
+        im_media_cache_repository::find_entry(data.db_conn.as_ref(), &cache_key, login_uid)
+            .await?
+            .and_then(|entry| entry.mime_type)
+    else {
+        return Ok(None);
+    };
+    im_media_cache_repository::touch_entry(
+        data.db_conn.as_ref(),
+        &cache_key,
+        now_millis(),
+        login_uid,
+    )
+    .await?;
+
+    Ok(Some(DownloadMediaResult {
+        local_path: local_path.to_string_lossy().to_string(),
+        size: metadata.len(),
+        mime_type,
+    }))
+}
+
+/// Get a thumbnail variant of a piece of media
+///
+/// Every width/height/method combination is cached separately from the
+/// original file. When the server cannot produce a thumbnail, the original is
+/// downloaded and the thumbnail is generated locally.
+#[command]
+pub async fn get_media_thumbnail(
+    options: MediaThumbnailOptions,
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    data: State<'_, AppData>,
+) -> Result<DownloadMediaResult, AppError> {
+    info!(
+        "Getting media thumbnail: {} ({}x{})",
+        options.mxc_uri, options.width, options.height
+    );
+
+    let download = DownloadMediaOptions {
+        mxc_uri: options.mxc_uri.clone(),
+        force: false,
+        max_size: None,
+        thumbnail: Some(ThumbnailOptions {
+            width: options.width,
+            height: options.height,
+            method: options.method.clone(),
+        }),
+        download_id: None,
+        room_id: options.room_id.clone(),
+    };
+
+    let result = match get_or_download_media(&download, &app_handle, &state, &data).await {
+        Ok(result) => result,
+        // Errors that a local thumbnail would run into as well
+        Err(
+            e @ (AppError::InvalidUri(_)
+            | AppError::TokenExpired
+            | AppError::RateLimited(_)
+            | AppError::Cancelled(_)
+            | AppError::Database(_)),
+        ) => return Err(e),
+        Err(e) => {
+            warn!(
+                "Server thumbnail unavailable for {}, generating locally: {}",
+                options.mxc_uri, e
+            );
+            generate_local_thumbnail(&download, options.max_size, &app_handle, &state, &data)
+                .await?
+        }
+    };
+
+    if let Some(message_id) = &options.message_id {
+        let login_uid = data.user_info.lock().await.uid.clone();
+        im_message_repository::update_message_thumbnail_path(
+            data.db_conn.as_ref(),
+            message_id,
+            &login_uid,
+            &result.local_path,
+        )
+        .await?;
+    }
+
+    Ok(result)
+}
+
+/// Download the original file and generate the requested thumbnail from it
+async fn generate_local_thumbnail(
+    download: &DownloadMediaOptions,
+    max_size: Option<usize>,
+    app_handle: &tauri::AppHandle,
+    state: &AppState,
+    data: &AppData,
+) -> Result<DownloadMediaResult, AppError> {
+    let Some(thumbnail) = download.thumbnail.clone() else {
+        return Err(AppError::Unexpected(
+            "Missing thumbnail options".to_string(),
+        ));
+    };
+
+    let original = get_or_download_media(
+        &DownloadMediaOptions {
+            max_size,
+            thumbnail: None,
+            ..download.clone()
+        },
+        app_handle,
+        state,
+        data,
+    )
+    .await?;
+    if !original.mime_type.starts_with("image/") {
+        return Err(AppError::Request(format!(
+            "Cannot generate a thumbnail for {}",
+            original.mime_type
+        )));
+    }
+
+    let (server_name, media_id) = parse_mxc_uri(&download.mxc_uri)?;
+    let login_uid = current_login_uid(data).await;
+    let cache_dir = get_account_cache_dir(app_handle, &login_uid).await?;
+    let local_path = get_thumbnail_cache_path(&cache_dir, &server_name, &media_id, &thumbnail);
+
+    let source = PathBuf::from(&original.local_path);
+    let (bytes, mime_type) =
+        tokio::task::spawn_blocking(move || render_thumbnail(&source, &thumbnail))
+            .await
+            .map_err(|e| AppError::Unexpected(e.to_string()))??;
+
+    let partial_path = get_partial_path(&local_path);
+    fs::write(&partial_path, &bytes)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    fs::rename(&partial_path, &local_path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    let size = bytes.len() as u64;
+    record_cache_entry(data, download, &local_path, size, mime_type, &login_uid).await?;
+    enforce_cache_quota(
+        data,
+        &cache_dir,
+        Some(&get_cache_key(&local_path)),
+        &login_uid,
+    )
+    .await?;
+
+    Ok(DownloadMediaResult {
+        local_path: local_path.to_string_lossy().to_string(),
+        size,
+        mime_type: mime_type.to_string(),
+    })
+}
+
+/// Resize an image following the thumbnail method of the Matrix spec
+///
+/// `crop` fills the requested size exactly, `scale` fits the image inside it
+/// keeping the aspect ratio. Images are never upscaled. HEIC/AVIF sources are
+/// decoded through the system tools and animated ones yield their first frame.
+/// Returns the encoded thumbnail and its MIME type: PNG when the image has
+/// transparency, JPEG otherwise.
+fn render_thumbnail(
+    source: &Path,
+    thumbnail: &ThumbnailOptions,
+) -> Result<(Vec<u8>, &'static str), AppError> {
+    let image = image_decode::decode_file(source)?.image;
+
+    let (width, height) = (thumbnail.width.max(1), thumbnail.height.max(1));
+    let image = if image.width() <= width && image.height() <= height {
+        image
+    } else if thumbnail.method() == "crop" {
+        image.resize_to_fill(width, height, image::imageops::FilterType::Lanczos3)
+    } else {
+        image.resize(width, height, image::imageops::FilterType::Lanczos3)
+    };
+
+    let mut bytes = Vec::new();
+    let mime_type = if image.color().has_alpha() {
+        image
+            .write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                image::ImageFormat::Png,
+            )
+            .map_err(|e| AppError::Unexpected(e.to_string()))?;
+        "image/png"
+    } else {
+        image
+            .to_rgb8()
+            .write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(
+                &mut bytes,
+                THUMBNAIL_JPEG_QUALITY,
+            ))
+            .map_err(|e| AppError::Unexpected(e.to_string()))?;
+        "image/jpeg"
+    };
+
+    Ok((bytes, mime_type))
+}
+
+/// Key identifying the cached file a download produces
+///
+/// Used as the default download id, so the original file and every thumbnail
+/// size of the same media are downloaded independently.
+pub(crate) fn download_key(options: &DownloadMediaOptions) -> String {
+    match &options.thumbnail {
+        Some(thumbnail) => format!(
+            "{}#{}x{}_{}",
+            options.mxc_uri,
+            thumbnail.width,
+            thumbnail.height,
+            thumbnail.method()
+        ),
+        None => options.mxc_uri.clone(),
+    }
+}
 
-    // Check if file exists in cache
-    if !options.force && local_path.exists() {
+/// Registration of an in-flight download, removed when dropped
+struct ActiveDownload {
+    local_path: PathBuf,
+    cancel_rx: watch::Receiver<bool>,
+    /// Dropped after the entry is removed, which wakes the waiters
+    _finished_tx: watch::Sender<()>,
+}
+
+impl Drop for ActiveDownload {
+    fn drop(&mut self) {
+        ACTIVE_DOWNLOADS.lock().unwrap().remove(&self.local_path);
+    }
+}
+
+/// Register a download of `local_path` under `download_id`
+///
+/// Downloads are deduplicated by the file they write, whatever id they were
+/// started with. When the file is already being downloaded, waits for that
+/// download to finish and returns `None`; the caller then finds its result in
+/// the cache.
+async fn begin_download(local_path: &Path, download_id: &str) -> Option<ActiveDownload> {
+    let mut finished_rx = {
+        let mut active_downloads = ACTIVE_DOWNLOADS.lock().unwrap();
+        match active_downloads.get(local_path) {
+            Some(entry) => entry.finished_rx.clone(),
+            None => {
+                let (cancel_tx, cancel_rx) = watch::channel(false);
+                let (finished_tx, finished_rx) = watch::channel(());
+                active_downloads.insert(
+                    local_path.to_path_buf(),
+                    DownloadEntry {
+                        download_id: download_id.to_string(),
+                        cancel_tx,
+                        finished_rx,
+                    },
+                );
+                return Some(ActiveDownload {
+                    local_path: local_path.to_path_buf(),
+                    cancel_rx,
+                    _finished_tx: finished_tx,
+                });
+            }
+        }
+    };
+
+    info!("Waiting for identical download: {}", download_id);
+    // Resolves with an error once the sender is dropped
+    let _ = finished_rx.changed().await;
+    None
+}
+
+/// Whether `path` is written by an in-flight download: the target itself, its
+/// `.part` file, or the ciphertext of an encrypted attachment
+fn is_active_download(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    ACTIVE_DOWNLOADS.lock().unwrap().keys().any(|active| {
+        path.strip_prefix(active.to_string_lossy().as_ref())
+            .is_some_and(|suffix| matches!(suffix, "" | ".part" | ".enc" | ".enc.part"))
+    })
+}
+
+/// Download and decrypt an end-to-end encrypted attachment into the cache
+///
+/// The ciphertext is downloaded like any other media, its SHA-256 is checked
+/// against the `EncryptedFile` before any plaintext is written, and it is
+/// removed once decrypted.
+#[command]
+pub async fn download_encrypted_media(
+    options: DownloadEncryptedMediaOptions,
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    data: State<'_, AppData>,
+) -> Result<DownloadMediaResult, AppError> {
+    info!("Downloading encrypted media: {}", options.file.url);
+
+    // Reject unusable keys before downloading anything
+    let cipher = AttachmentCipher::new(&options.file)?;
+    let (server_name, media_id) = parse_mxc_uri(&options.file.url)?;
+
+    let login_uid = current_login_uid(&data).await;
+    let cache_dir = get_account_cache_dir(&app_handle, &login_uid).await?;
+    let local_path = get_decrypted_cache_path(&cache_dir, &server_name, &media_id);
+    let mime_type = options
+        .mime_type
+        .clone()
+        .filter(|mime_type| !mime_type.is_empty())
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+
+    let download = DownloadMediaOptions {
+        mxc_uri: options.file.url.clone(),
+        force: options.force,
+        max_size: options.max_size,
+        thumbnail: None,
+        download_id: options.download_id.clone(),
+        room_id: options.room_id.clone(),
+    };
+    let download_id = options
+        .download_id
+        .clone()
+        .unwrap_or_else(|| format!("{}#decrypted", options.file.url));
+
+    // Same as `get_or_download_media`: wait for an identical download instead of failing
+    let mut force = options.force;
+    let active = loop {
+        if !force && local_path.exists() {
+            break None;
+        }
+        match begin_download(&local_path, &download_id).await {
+            Some(active) => break Some(active),
+            None => force = false,
+        }
+    };
+
+    // The decrypted file is already cached
+    let Some(active) = active else {
         let metadata = fs::metadata(&local_path)
             .await
             .map_err(|e| AppError::Io(e.to_string()))?;
 
-        let size = metadata.len();
-
-        // Try to determine MIME type
-        let mime_type = mime_guess::from_path(&local_path)
-            .first_or_octet_stream()
-            .to_string();
+        record_cache_entry(
+            &data,
+            &download,
+            &local_path,
+            metadata.len(),
+            &mime_type,
+            &login_uid,
+        )
+        .await?;
 
         return Ok(DownloadMediaResult {
             local_path: local_path.to_string_lossy().to_string(),
-            size,
+            size: metadata.len(),
             mime_type,
         });
+    };
+
+    let mut encrypted_path = local_path.clone().into_os_string();
+    encrypted_path.push(".enc");
+    let encrypted_path = PathBuf::from(encrypted_path);
+
+    fetch_to_cache(
+        &download,
+        &download_id,
+        &server_name,
+        &media_id,
+        &encrypted_path,
+        active.cancel_rx.clone(),
+        &app_handle,
+        &state,
+    )
+    .await?;
+
+    let decrypted = decrypt_to_cache(&cipher, &encrypted_path, &local_path).await;
+    let _ = fs::remove_file(&encrypted_path).await;
+    let size = decrypted?;
+
+    record_cache_entry(&data, &download, &local_path, size, &mime_type, &login_uid).await?;
+    enforce_cache_quota(
+        &data,
+        &cache_dir,
+        Some(&get_cache_key(&local_path)),
+        &login_uid,
+    )
+    .await?;
+    drop(active);
+
+    info!(
+        "Encrypted media decrypted: {} -> {} ({} bytes)",
+        options.file.url,
+        local_path.display(),
+        size
+    );
+
+    Ok(DownloadMediaResult {
+        local_path: local_path.to_string_lossy().to_string(),
+        size,
+        mime_type,
+    })
+}
+
+/// Verify a downloaded ciphertext and decrypt it into place
+async fn decrypt_to_cache(
+    cipher: &AttachmentCipher,
+    encrypted_path: &Path,
+    local_path: &Path,
+) -> Result<u64, AppError> {
+    let verified = cipher
+        .verify_file(encrypted_path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    if !verified {
+        return Err(AppError::IntegrityCheckFailed(
+            "SHA-256 of the encrypted file does not match".to_string(),
+        ));
     }
 
-    // Construct download URL
-    let homeserver = state.config.lock().await.homeserver.clone();
-    let download_url = format!(
-        "{}/_matrix/media/r0/download/{}/{}",
-        homeserver.trim_end_matches('/'),
+    let part_path = get_partial_path(local_path);
+    let size = cipher
+        .decrypt_file(encrypted_path, &part_path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    fs::rename(&part_path, local_path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    Ok(size)
+}
+
+/// Record a cached file in the cache index
+async fn record_cache_entry(
+    data: &AppData,
+    options: &DownloadMediaOptions,
+    local_path: &Path,
+    size: u64,
+    mime_type: &str,
+    login_uid: &str,
+) -> Result<(), AppError> {
+    let now = now_millis();
+    im_media_cache_repository::upsert_entry(
+        data.db_conn.as_ref(),
+        im_media_cache::Model {
+            cache_key: get_cache_key(local_path),
+            login_uid: login_uid.to_string(),
+            mxc_uri: options.mxc_uri.clone(),
+            size: size as i64,
+            mime_type: Some(mime_type.to_string()),
+            room_id: options.room_id.clone(),
+            thumbnail: options.thumbnail.is_some(),
+            pinned: false,
+            created_at: now,
+            last_accessed_at: now,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Stream media into `<local_path>.part`, resuming a previous partial download
+/// when possible, and move it into place once complete
+#[allow(clippy::too_many_arguments)]
+async fn fetch_to_cache(
+    options: &DownloadMediaOptions,
+    download_id: &str,
+    server_name: &str,
+    media_id: &str,
+    local_path: &Path,
+    mut cancel_rx: watch::Receiver<bool>,
+    app_handle: &tauri::AppHandle,
+    state: &AppState,
+) -> Result<DownloadMediaResult, AppError> {
+    // Construct download URL, preferring authenticated media (Matrix v1.11)
+    let homeserver = state.homeserver().await.trim_end_matches('/').to_string();
+    let authenticated = supports_authenticated_media(state, &homeserver).await;
+    let download_url = build_media_url(
+        &homeserver,
+        authenticated,
         server_name,
-        media_id
+        media_id,
+        options.thumbnail.as_ref(),
     );
+    let access_token = state.access_token().await;
 
-    // Download file
-    let response = state
-        .http_client
-        .get(&download_url)
-        .send()
+    let part_path = get_partial_path(local_path);
+    if options.force {
+        let _ = fs::remove_file(&part_path).await;
+    }
+    let mut resume_from = fs::metadata(&part_path)
         .await
-        .map_err(|e| AppError::Network(e.to_string()))?;
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+
+    let mut response = loop {
+        let mut request = state.http_client.get(&download_url);
+        if authenticated && let Some(access_token) = &access_token {
+            request = request.bearer_auth(access_token);
+        }
+        if resume_from > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={resume_from}-"));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| AppError::Network(e.to_string()))?;
+
+        // The partial file no longer matches the remote content; start over
+        if resume_from > 0 && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            fs::remove_file(&part_path)
+                .await
+                .map_err(|e| AppError::Io(e.to_string()))?;
+            resume_from = 0;
+            continue;
+        }
+
+        break response;
+    };
+
+    if !response.status().is_success() {
+        return Err(media_error(response, &options.mxc_uri).await);
+    }
+
+    // Servers that ignore Range answer 200 with the whole body
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        resume_from = 0;
+    }
+
+    let total = get_content_range_total(&response)
+        .or_else(|| response.content_length().map(|len| len + resume_from));
 
     // Check content length
-    if let Some(content_length) = response.content_length()
+    if let Some(total) = total
         && let Some(max_size) = options.max_size
-        && content_length > max_size as u64
+        && total > max_size as u64
     {
         return Err(AppError::FileTooLarge(format!(
-            "File size {content_length} exceeds maximum {max_size}"
+            "File size {total} exceeds maximum {max_size}"
         )));
     }
 
@@ -164,125 +1303,483 @@ pub async fn download_media(
         .unwrap_or("application/octet-stream")
         .to_string();
 
-    // Download content
-    let bytes = response
-        .bytes()
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resume_from > 0)
+        .truncate(resume_from == 0)
+        .open(&part_path)
         .await
-        .map_err(|e| AppError::Network(e.to_string()))?;
+        .map_err(|e| AppError::Io(e.to_string()))?;
 
-    // Check file size
-    if let Some(max_size) = options.max_size
-        && bytes.len() > max_size
-    {
-        return Err(AppError::FileTooLarge(format!(
-            "File size {} exceeds maximum {max_size}",
-            bytes.len()
-        )));
+    let started_at = Instant::now();
+    let mut last_emit = started_at;
+    let mut progress = MediaDownloadProgress {
+        download_id: download_id.to_string(),
+        mxc_uri: options.mxc_uri.clone(),
+        bytes: resume_from,
+        total,
+        speed: 0.0,
+    };
+
+    loop {
+        let chunk = tokio::select! {
+            _ = cancel_rx.wait_for(|cancelled| *cancelled) => {
+                // Keep the partial file so the download can be resumed later
+                file.flush().await.map_err(|e| AppError::Io(e.to_string()))?;
+                info!("Media download cancelled: {}", download_id);
+                return Err(AppError::Cancelled(download_id.to_string()));
+            }
+            chunk = response.chunk() => chunk.map_err(|e| AppError::Network(e.to_string()))?,
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+
+        progress.bytes += chunk.len() as u64;
+
+        // Check file size
+        if let Some(max_size) = options.max_size
+            && progress.bytes > max_size as u64
+        {
+            drop(file);
+            let _ = fs::remove_file(&part_path).await;
+            return Err(AppError::FileTooLarge(format!(
+                "File size {} exceeds maximum {max_size}",
+                progress.bytes
+            )));
+        }
+
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| AppError::Io(e.to_string()))?;
+
+        if last_emit.elapsed() >= PROGRESS_INTERVAL {
+            progress.speed = get_speed(progress.bytes - resume_from, started_at);
+            let _ = app_handle.emit(DOWNLOAD_PROGRESS_EVENT, &progress);
+            last_emit = Instant::now();
+        }
     }
 
+    file.flush()
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    drop(file);
+
+    progress.total = Some(progress.bytes);
+    progress.speed = get_speed(progress.bytes - resume_from, started_at);
+    let _ = app_handle.emit(DOWNLOAD_PROGRESS_EVENT, &progress);
+
     // Save to cache
-    fs::write(&local_path, &bytes)
+    fs::rename(&part_path, local_path)
         .await
         .map_err(|e| AppError::Io(e.to_string()))?;
 
     info!(
-        "Media downloaded successfully: {} -> {} ({} bytes)",
+        "Media downloaded successfully: {} -> {} ({} bytes, resumed from {})",
         options.mxc_uri,
         local_path.display(),
-        bytes.len()
+        progress.bytes,
+        resume_from
     );
 
     Ok(DownloadMediaResult {
         local_path: local_path.to_string_lossy().to_string(),
-        size: bytes.len() as u64,
+        size: progress.bytes,
         mime_type,
     })
 }
 
+/// Upload a local file to the media repository
+///
+/// The file is streamed from disk. With `encrypt` set it is encrypted on the
+/// fly and the returned `file` is the `EncryptedFile` to put in the event.
+#[command]
+pub async fn upload_media(
+    options: UploadMediaOptions,
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<UploadMediaResult, AppError> {
+    info!(
+        "Uploading media: {} (encrypt: {})",
+        options.path, options.encrypt
+    );
+
+    let path = PathBuf::from(&options.path);
+    let metadata = fs::metadata(&path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    if !metadata.is_file() {
+        return Err(AppError::Io(format!("Not a file: {}", options.path)));
+    }
+    let size = metadata.len();
+
+    let homeserver = state.homeserver().await.trim_end_matches('/').to_string();
+    let access_token = state.access_token().await.ok_or(AppError::TokenExpired)?;
+
+    // Enforce the server limit before sending any bytes
+    if let Some(max_upload_size) = get_max_upload_size(&state, &homeserver).await
+        && size > max_upload_size
+    {
+        return Err(AppError::MediaTooLarge(format!(
+            "File size {size} exceeds server limit {max_upload_size}"
+        )));
+    }
+
+    let mime_type = resolve_mime_type(&path, options.mime_type.clone());
+    let (width, height) = get_image_dimensions(&path, &mime_type);
+
+    let cipher = options.encrypt.then(AttachmentCipher::generate);
+
+    let mut request = state
+        .http_client
+        .post(format!("{homeserver}/_matrix/media/v3/upload"))
+        .bearer_auth(&access_token);
+
+    // Encrypted uploads must not reveal the file name or type to the server
+    if cipher.is_none() {
+        let file_name = options.file_name.clone().or_else(|| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        });
+        if let Some(file_name) = file_name {
+            request = request.query(&[("filename", file_name)]);
+        }
+    }
+
+    let upload_id = options
+        .upload_id
+        .clone()
+        .unwrap_or_else(|| options.path.clone());
+    let (response, sha256) = send_file_body(
+        request,
+        &path,
+        size,
+        &mime_type,
+        cipher.as_ref(),
+        upload_id,
+        app_handle,
+    )
+    .await?;
+
+    if !response.status().is_success() {
+        return Err(media_error(response, &options.path).await);
+    }
+
+    let upload = response
+        .json::<UploadResponse>()
+        .await
+        .map_err(|e| AppError::Network(e.to_string()))?;
+
+    let file = match (&cipher, sha256) {
+        (Some(cipher), Some(sha256)) => {
+            Some(cipher.to_encrypted_file(upload.content_uri.clone(), &sha256))
+        }
+        _ => None,
+    };
+
+    info!(
+        "Media uploaded successfully: {} -> {} ({} bytes)",
+        options.path, upload.content_uri, size
+    );
+
+    Ok(UploadMediaResult {
+        content_uri: upload.content_uri,
+        size,
+        mime_type,
+        width,
+        height,
+        file,
+    })
+}
+
+/// MIME type given by the caller, or guessed from the file extension
+pub(crate) fn resolve_mime_type(path: &Path, mime_type: Option<String>) -> String {
+    mime_type
+        .filter(|mime_type| !mime_type.is_empty())
+        .unwrap_or_else(|| {
+            mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string()
+        })
+}
+
+/// Width and height of an image file, read from its header
+pub(crate) fn get_image_dimensions(path: &Path, mime_type: &str) -> (Option<u32>, Option<u32>) {
+    if mime_type.starts_with("image/") {
+        image::image_dimensions(path).ok().unzip()
+    } else {
+        (None, None)
+    }
+}
+
+/// Stream a local file as the body of `request`, encrypting it when a cipher is given
+///
+/// Returns the response and, when encrypting, the SHA-256 of the ciphertext.
+pub(crate) async fn send_file_body(
+    request: reqwest::RequestBuilder,
+    path: &Path,
+    size: u64,
+    mime_type: &str,
+    cipher: Option<&AttachmentCipher>,
+    upload_id: String,
+    app_handle: tauri::AppHandle,
+) -> Result<(reqwest::Response, Option<Vec<u8>>), AppError> {
+    let (encryption, hash_rx) = match cipher {
+        Some(cipher) => {
+            let (hash_tx, hash_rx) = oneshot::channel();
+            (Some((cipher.encryptor(), hash_tx)), Some(hash_rx))
+        }
+        None => (None, None),
+    };
+    let content_type = if cipher.is_some() {
+        "application/octet-stream"
+    } else {
+        mime_type
+    };
+
+    let file = fs::File::open(path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    let body = upload_body(UploadBodyState {
+        file,
+        encryption,
+        progress: MediaUploadProgress {
+            upload_id,
+            path: path.to_string_lossy().to_string(),
+            bytes: 0,
+            total: size,
+            speed: 0.0,
+        },
+        app_handle,
+        started_at: Instant::now(),
+        last_emit: Instant::now(),
+    });
+
+    let response = request
+        .header(reqwest::header::CONTENT_TYPE, content_type)
+        .header(reqwest::header::CONTENT_LENGTH, size)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| AppError::Network(e.to_string()))?;
+
+    let sha256 = match hash_rx {
+        Some(hash_rx) if response.status().is_success() => Some(hash_rx.await.map_err(|_| {
+            AppError::Unexpected("Upload finished before the file was fully read".to_string())
+        })?),
+        _ => None,
+    };
+
+    Ok((response, sha256))
+}
+
+/// Response of the upload endpoint
+#[derive(Debug, Deserialize)]
+struct UploadResponse {
+    content_uri: String,
+}
+
+/// Response of the media config endpoint
+#[derive(Debug, Deserialize)]
+struct MediaConfig {
+    #[serde(rename = "m.upload.size")]
+    upload_size: Option<u64>,
+}
+
+/// `m.upload.size` of the homeserver, `None` when there is no limit or it cannot be queried
+pub(crate) async fn get_max_upload_size(state: &AppState, homeserver: &str) -> Option<u64> {
+    let url = if supports_authenticated_media(state, homeserver).await {
+        format!("{homeserver}/_matrix/client/v1/media/config")
+    } else {
+        format!("{homeserver}/_matrix/media/v3/config")
+    };
+
+    let mut request = state.http_client.get(&url);
+    if let Some(access_token) = state.access_token().await {
+        request = request.bearer_auth(access_token);
+    }
+
+    let config = async {
+        request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<MediaConfig>()
+            .await
+    }
+    .await;
+
+    match config {
+        Ok(config) => config.upload_size,
+        Err(e) => {
+            warn!("Failed to query {}: {}", url, e);
+            None
+        }
+    }
+}
+
+/// State of a streaming upload body
+struct UploadBodyState {
+    file: fs::File,
+    /// Encryptor and the channel its ciphertext hash is sent through at the end
+    encryption: Option<(AttachmentEncryptor, oneshot::Sender<Vec<u8>>)>,
+    progress: MediaUploadProgress,
+    app_handle: tauri::AppHandle,
+    started_at: Instant,
+    last_emit: Instant,
+}
+
+/// Stream a file as a request body, encrypting and reporting progress as it goes
+fn upload_body(state: UploadBodyState) -> reqwest::Body {
+    let stream = futures::stream::unfold(Some(state), |state| async move {
+        let mut state = state?;
+        let mut buffer = vec![0u8; UPLOAD_CHUNK_SIZE];
+
+        let read = match state.file.read(&mut buffer).await {
+            Ok(read) => read,
+            Err(e) => return Some((Err(e), None)),
+        };
+
+        if read == 0 {
+            if let Some((encryptor, hash_tx)) = state.encryption {
+                let _ = hash_tx.send(encryptor.finalize());
+            }
+            state.progress.speed = get_speed(state.progress.bytes, state.started_at);
+            let _ = state
+                .app_handle
+                .emit(UPLOAD_PROGRESS_EVENT, &state.progress);
+            return None;
+        }
+
+        buffer.truncate(read);
+        if let Some((encryptor, _)) = &mut state.encryption {
+            encryptor.encrypt(&mut buffer);
+        }
+
+        state.progress.bytes += read as u64;
+        if state.last_emit.elapsed() >= PROGRESS_INTERVAL {
+            state.progress.speed = get_speed(state.progress.bytes, state.started_at);
+            let _ = state
+                .app_handle
+                .emit(UPLOAD_PROGRESS_EVENT, &state.progress);
+            state.last_emit = Instant::now();
+        }
+
+        Some((Ok(bytes::Bytes::from(buffer)), Some(state)))
+    });
+
+    reqwest::Body::wrap_stream(stream)
+}
+
+/// Cancel an in-flight download; its partial file is kept for resuming
+#[command]
+pub async fn cancel_media_download(download_id: String) -> Result<bool, AppError> {
+    info!("Cancelling media download: {}", download_id);
+
+    Ok(ACTIVE_DOWNLOADS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|entry| entry.download_id == download_id)
+        .fold(false, |cancelled, entry| {
+            entry.cancel_tx.send(true).is_ok() || cancelled
+        }))
+}
+
 /// Delete cached media
 #[command]
 pub async fn delete_cached_media(
     mxc_uri: String,
     app_handle: tauri::AppHandle,
+    data: State<'_, AppData>,
 ) -> Result<(), AppError> {
     info!("Deleting cached media: {}", mxc_uri);
 
     let (server_name, media_id) = parse_mxc_uri(&mxc_uri)?;
-    let cache_dir = get_cache_dir(&app_handle).await?;
-    let local_path = get_cache_path(&cache_dir, &server_name, &media_id);
+    let login_uid = current_login_uid(&data).await;
+    let cache_dir = get_account_cache_dir(&app_handle, &login_uid).await?;
 
-    if local_path.exists() {
-        fs::remove_file(&local_path)
-            .await
-            .map_err(|e| AppError::Io(e.to_string()))?;
+    // Original file and every thumbnail variant
+    let entries =
+        im_media_cache_repository::find_by_mxc_uri(data.db_conn.as_ref(), &mxc_uri, &login_uid)
+            .await?;
+    let removed =
+        remove_cache_entries(data.db_conn.as_ref(), &cache_dir, entries, &login_uid).await?;
+    if removed.count > 0 {
+        info!(
+            "Deleted {} cached files for {} ({} bytes)",
+            removed.count, mxc_uri, removed.total_size
+        );
+    }
 
-        info!("Deleted cached media: {}", local_path.display());
+    let local_path = get_cache_path(&cache_dir, &server_name, &media_id);
+    for path in [get_partial_path(&local_path), local_path] {
+        if path.exists() {
+            fs::remove_file(&path)
+                .await
+                .map_err(|e| AppError::Io(e.to_string()))?;
+        }
     }
 
     Ok(())
 }
 
-/// Clear all media cache
+/// Clear the media cache of the active account
 #[command]
-pub async fn clear_media_cache(app_handle: tauri::AppHandle) -> Result<CacheStats, AppError> {
+pub async fn clear_media_cache(
+    app_handle: tauri::AppHandle,
+    data: State<'_, AppData>,
+) -> Result<CacheStats, AppError> {
     info!("Clearing media cache");
 
-    let cache_dir = get_cache_dir(&app_handle).await?;
-    let mut stats = CacheStats {
-        count: 0,
-        total_size: 0,
-        oldest_entry: None,
-        newest_entry: None,
-    };
-
-    if cache_dir.exists() {
-        let mut entries = fs::read_dir(&cache_dir)
-            .await
-            .map_err(|e| AppError::Io(e.to_string()))?;
+    let login_uid = current_login_uid(&data).await;
+    let cache_dir = get_account_cache_dir(&app_handle, &login_uid).await?;
+    let stats = CacheStats::from(
+        im_media_cache_repository::summary(data.db_conn.as_ref(), &login_uid).await?,
+    );
 
-        let mut oldest_ts = u64::MAX;
-        let mut newest_ts = 0;
+    let mut entries = fs::read_dir(&cache_dir)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
 
-        while let Some(entry) = entries
-            .next_entry()
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?
+    {
+        let path = entry.path();
+        // Files of in-flight downloads are still being written; their index
+        // entries are recorded once the download finishes
+        if !entry
+            .metadata()
             .await
-            .map_err(|e| AppError::Io(e.to_string()))?
+            .is_ok_and(|metadata| metadata.is_file())
+            || is_active_download(&path)
         {
-            let metadata = entry
-                .metadata()
-                .await
-                .map_err(|e| AppError::Io(e.to_string()))?;
-
-            if metadata.is_file() {
-                stats.count += 1;
-                stats.total_size += metadata.len();
-
-                let modified = metadata
-                    .modified()
-                    .ok()
-                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                    .map(|d| d.as_secs());
-
-                if let Some(ts) = modified {
-                    oldest_ts = oldest_ts.min(ts);
-                    newest_ts = newest_ts.max(ts);
-                }
-
-                // Delete the file
-                fs::remove_file(entry.path())
-                    .await
-                    .map_err(|e| AppError::Io(e.to_string()))?;
-            }
+            continue;
         }
 
-        if oldest_ts != u64::MAX {
-            stats.oldest_entry = Some(oldest_ts);
-        }
-        if newest_ts != 0 {
-            stats.newest_entry = Some(newest_ts);
+        // A file that cannot be deleted now is left unindexed and removed as
+        // an orphan when the index is reconciled at the next startup
+        if let Err(e) = fs::remove_file(&path).await {
+            warn!("Failed to delete cached file {:?}: {}", path, e);
         }
     }
 
+    // Converted images are not indexed, they live in their own directory
+    let converted_dir = cache_dir.join(CONVERTED_IMAGE_DIR);
+    if converted_dir.exists()
+        && let Err(e) = fs::remove_dir_all(&converted_dir).await
+    {
+        warn!(
+            "Failed to delete converted images {:?}: {}",
+            converted_dir, e
+        );
+    }
+
+    im_media_cache_repository::delete_all(data.db_conn.as_ref(), &login_uid).await?;
+
     info!(
         "Media cache cleared: {} files, {} bytes",
         stats.count, stats.total_size
@@ -291,94 +1788,200 @@ pub async fn clear_media_cache(app_handle: tauri::AppHandle) -> Result<CacheStat
     Ok(stats)
 }
 
-/// Get media cache statistics
+/// Get media cache statistics of the active account
+#[command]
+pub async fn get_media_cache_stats(data: State<'_, AppData>) -> Result<CacheStats, AppError> {
+    let login_uid = current_login_uid(&data).await;
+    Ok(
+        im_media_cache_repository::summary(data.db_conn.as_ref(), &login_uid)
+            .await?
+            .into(),
+    )
+}
+
+/// Remove cached media matching the given options; pinned media is kept
+/// unless `include_pinned` is set
 #[command]
-pub async fn get_media_cache_stats(app_handle: tauri::AppHandle) -> Result<CacheStats, AppError> {
-    let cache_dir = get_cache_dir(&app_handle).await?;
-    let mut stats = CacheStats {
-        count: 0,
-        total_size: 0,
-        oldest_entry: None,
-        newest_entry: None,
+pub async fn prune_media_cache(
+    options: PruneMediaCacheOptions,
+    app_handle: tauri::AppHandle,
+    data: State<'_, AppData>,
+) -> Result<CacheStats, AppError> {
+    info!("Pruning media cache: {:?}", options);
+
+    let filter = MediaCachePruneFilter {
+        accessed_before: options
+            .max_age_secs
+            .map(|secs| now_millis() - (secs as i64).saturating_mul(1000)),
+        room_id: options.room_id,
+        mime_type: options.mime_type,
+        include_pinned: options.include_pinned,
     };
 
-    if cache_dir.exists() {
-        let mut entries = fs::read_dir(&cache_dir)
-            .await
-            .map_err(|e| AppError::Io(e.to_string()))?;
+    let login_uid = current_login_uid(&data).await;
+    let cache_dir = get_account_cache_dir(&app_handle, &login_uid).await?;
+    let entries =
+        im_media_cache_repository::find_prunable(data.db_conn.as_ref(), filter, &login_uid).await?;
+    let removed =
+        remove_cache_entries(data.db_conn.as_ref(), &cache_dir, entries, &login_uid).await?;
+
+    info!(
+        "Media cache pruned: {} files, {} bytes",
+        removed.count, removed.total_size
+    );
+
+    Ok(removed)
+}
+
+/// Pin or unpin cached media; pinned media is never evicted by the quota
+#[command]
+pub async fn set_media_cache_pinned(
+    mxc_uri: String,
+    pinned: bool,
+    data: State<'_, AppData>,
+) -> Result<u64, AppError> {
+    let login_uid = current_login_uid(&data).await;
+    Ok(
+        im_media_cache_repository::set_pinned(data.db_conn.as_ref(), &mxc_uri, pinned, &login_uid)
+            .await?,
+    )
+}
+
+/// Change the active account's cache quota and evict immediately if its
+/// cache is over it
+///
+/// The quota is saved with the account's settings and survives restarts;
+/// accounts without one use the configured default.
+#[command]
+pub async fn set_media_cache_quota(
+    max_size_bytes: u64,
+    app_handle: tauri::AppHandle,
+    data: State<'_, AppData>,
+) -> Result<CacheStats, AppError> {
+    info!("Setting media cache quota: {} bytes", max_size_bytes);
+
+    let login_uid = current_login_uid(&data).await;
+    im_config_repository::set_value(
+        data.db_conn.as_ref(),
+        MEDIA_CACHE_MAX_SIZE_KEY,
+        &max_size_bytes.to_string(),
+        &login_uid,
+    )
+    .await?;
+
+    let cache_dir = get_account_cache_dir(&app_handle, &login_uid).await?;
+    enforce_cache_quota(&data, &cache_dir, None, &login_uid).await?;
+
+    Ok(
+        im_media_cache_repository::summary(data.db_conn.as_ref(), &login_uid)
+            .await?
+            .into(),
+    )
+}
+
+/// Bring the cache index in line with the cache directories: drop entries whose
+/// file is gone and delete files that were never indexed
+///
+/// Files directly under the cache root predate per-account caches and are removed.
+pub async fn reconcile_media_cache_index(
+    app_handle: &tauri::AppHandle,
+    db: &DatabaseConnection,
+) -> Result<(), AppError> {
+    let cache_root = get_cache_root(app_handle)?;
+    if !cache_root.exists() {
+        return Ok(());
+    }
+
+    // Index entries grouped by the cache directory of their account
+    let mut indexed: HashMap<String, (String, HashSet<String>)> = HashMap::new();
+    for entry in im_media_cache_repository::find_all(db).await? {
+        indexed
+            .entry(account_dir_name(&entry.login_uid))
+            .or_insert_with(|| (entry.login_uid.clone(), HashSet::new()))
+            .1
+            .insert(entry.cache_key);
+    }
 
-        let mut oldest_ts = u64::MAX;
-        let mut newest_ts = 0;
+    let mut orphans = 0;
+    let mut entries = fs::read_dir(&cache_root)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
 
-        while let Some(entry) = entries
-            .next_entry()
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?
+    {
+        let is_dir = entry
+            .metadata()
             .await
-            .map_err(|e| AppError::Io(e.to_string()))?
-        {
-            let metadata = entry
-                .metadata()
+            .map(|metadata| metadata.is_dir())
+            .unwrap_or(false);
+
+        if !is_dir {
+            fs::remove_file(entry.path())
                 .await
                 .map_err(|e| AppError::Io(e.to_string()))?;
+            orphans += 1;
+            continue;
+        }
 
-            if metadata.is_file() {
-                stats.count += 1;
-                stats.total_size += metadata.len();
-
-                let modified = metadata
-                    .modified()
-                    .ok()
-                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                    .map(|d| d.as_secs());
+        let name = entry.file_name().to_string_lossy().to_string();
+        let keys = indexed.get_mut(&name).map(|(_, keys)| keys);
+        orphans += remove_unindexed_files(&entry.path(), keys).await?;
+    }
 
-                if let Some(ts) = modified {
-                    oldest_ts = oldest_ts.min(ts);
-                    newest_ts = newest_ts.max(ts);
-                }
-            }
-        }
+    let mut missing = 0;
+    for (login_uid, keys) in indexed.into_values() {
+        missing +=
+            im_media_cache_repository::delete_entries(db, keys.into_iter().collect(), &login_uid)
+                .await?;
+    }
 
-        if oldest_ts != u64::MAX {
-            stats.oldest_entry = Some(oldest_ts);
-        }
-        if newest_ts != 0 {
-            stats.newest_entry = Some(newest_ts);
-        }
+    if orphans > 0 || missing > 0 {
+        info!(
+            "Media cache index reconciled: {} unindexed files removed, {} stale entries dropped",
+            orphans, missing
+        );
     }
 
-    Ok(stats)
+    Ok(())
 }
 
-/// Preload media files
-#[command]
-pub async fn preload_media(
-    mxc_uris: Vec<String>,
-    app_handle: tauri::AppHandle,
-    state: State<'_, AppState>,
-) -> Result<usize, AppError> {
-    info!("Preloading {} media files", mxc_uris.len());
-
-    let mut success_count = 0;
+/// Delete the files of an account's cache directory that have no index entry;
+/// matched keys are taken out of `indexed`, leaving the entries without a file
+async fn remove_unindexed_files(
+    cache_dir: &Path,
+    mut indexed: Option<&mut HashSet<String>>,
+) -> Result<u64, AppError> {
+    let mut orphans = 0;
+    let mut entries = fs::read_dir(cache_dir)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
 
-    for uri in &mxc_uris {
-        let options = DownloadMediaOptions {
-            mxc_uri: uri.clone(),
-            force: false,
-            max_size: Some(50 * 1024 * 1024), // 50MB limit for preload
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?
+    {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
         };
 
-        if download_media(options, app_handle.clone(), state.clone())
-            .await
-            .is_ok()
+        // Partial downloads are kept so they can be resumed, unless abandoned
+        if !metadata.is_file()
+            || (name.ends_with(".part") && !is_stale_partial(&metadata))
+            || indexed.as_mut().is_some_and(|keys| keys.remove(&name))
         {
-            success_count += 1;
+            continue;
         }
-    }
 
-    info!(
-        "Media preload completed: {}/{} files",
-        success_count,
-        mxc_uris.len()
-    );
+        fs::remove_file(entry.path())
+            .await
+            .map_err(|e| AppError::Io(e.to_string()))?;
+        orphans += 1;
+    }
 
-    Ok(success_count)
+    Ok(orphans)
 }
@@ -0,0 +1,417 @@
+//! Shared download scheduler for media that is fetched in the background
+//!
+//! Preloads and `hula-media://` requests from every window go through one
+//! queue: at most [`MAX_CONCURRENT_DOWNLOADS`] files are fetched at a time,
+//! higher priorities are started first, and requests for a media file that is
+//! already queued or downloading wait for that fetch instead of starting
+//! another one. Requests can be grouped into batches and cancelled together.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, command};
+use tokio::sync::oneshot;
+use tracing::info;
+
+use crate::command::media::{
+    DownloadMediaOptions, DownloadMediaResult, cancel_media_download, download_key,
+    get_or_download_media,
+};
+use crate::{AppData, error::AppError, state::AppState};
+
+/// Maximum number of media files downloaded at the same time
+const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
+/// Size limit applied to preloaded media
+const PRELOAD_MAX_SIZE: usize = 50 * 1024 * 1024;
+
+lazy_static::lazy_static! {
+    static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::default());
+}
+
+/// Download priority, visible media should use `High`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Outcome of one media file of a preload batch
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreloadStatus {
+    Success,
+    Failed,
+    Cancelled,
+}
+
+/// Per-URI entry of a preload report
+#[derive(Debug, Serialize)]
+pub struct PreloadMediaItem {
+    pub mxc_uri: String,
+    pub status: PreloadStatus,
+    /// Local file path when the media is cached
+    pub local_path: Option<String>,
+    /// Failure reason
+    pub error: Option<String>,
+}
+
+/// Result of `preload_media`
+#[derive(Debug, Serialize)]
+pub struct PreloadMediaReport {
+    /// Id the batch can be cancelled with
+    pub batch_id: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub items: Vec<PreloadMediaItem>,
+}
+
+type DownloadResult = Result<DownloadMediaResult, AppError>;
+
+/// A caller waiting for a scheduled download
+struct Waiter {
+    batch_id: Option<String>,
+    result_tx: oneshot::Sender<DownloadResult>,
+}
+
+/// A queued or running download shared by all its waiters
+struct Job {
+    /// Distinguishes a job from a later one for the same file
+    id: u64,
+    options: DownloadMediaOptions,
+    priority: DownloadPriority,
+    running: bool,
+    waiters: Vec<Waiter>,
+}
+
+/// Heap entry; entries left behind by a priority change or a cancellation are
+/// skipped when popped
+#[derive(PartialEq, Eq)]
+struct QueueEntry {
+    priority: DownloadPriority,
+    seq: u64,
+    key: String,
+}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Higher priority first, then first come first served
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Default)]
+struct Scheduler {
+    queue: BinaryHeap<QueueEntry>,
+    jobs: HashMap<String, Job>,
+    running: usize,
+    seq: u64,
+}
+
+impl Scheduler {
+    fn push(&mut self, key: String, priority: DownloadPriority) {
+        self.seq += 1;
+        self.queue.push(QueueEntry {
+            priority,
+            seq: self.seq,
+            key,
+        });
+    }
+
+    /// Pop the next queued job that should start, marking it as running
+    fn next_job(&mut self) -> Option<(String, u64, DownloadMediaOptions)> {
+        if self.running >= MAX_CONCURRENT_DOWNLOADS {
+            return None;
+        }
+
+        while let Some(entry) = self.queue.pop() {
+            let Some(job) = self.jobs.get_mut(&entry.key) else {
+                continue;
+            };
+            if job.running || job.priority != entry.priority {
+                continue;
+            }
+            job.running = true;
+            self.running += 1;
+            return Some((entry.key, job.id, job.options.clone()));
+        }
+
+        None
+    }
+}
+
+/// Whether a size limit of `current` allows everything `requested` allows
+fn covers_size(current: Option<usize>, requested: Option<usize>) -> bool {
+    match (current, requested) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(current), Some(requested)) => current >= requested,
+    }
+}
+
+/// Download media through the shared scheduler
+///
+/// Resolves once the media is cached, or with `AppError::Cancelled` when the
+/// batch it belongs to is cancelled first.
+///
+/// A request joining a queued job widens it to the larger size limit. A job
+/// that is already running can't be widened; if it fails for exceeding its
+/// own limit, the request is scheduled again with its own options.
+pub(crate) async fn schedule_download(
+    app_handle: &AppHandle,
+    options: DownloadMediaOptions,
+    priority: DownloadPriority,
+    batch_id: Option<String>,
+) -> DownloadResult {
+    let mxc_uri = options.mxc_uri.clone();
+    let key = download_key(&options);
+    let mut retried = false;
+
+    loop {
+        let (result_tx, result_rx) = oneshot::channel();
+        let joined_smaller_limit = {
+            let mut scheduler = SCHEDULER.lock().unwrap();
+            let waiter = Waiter {
+                batch_id: batch_id.clone(),
+                result_tx,
+            };
+
+            let (requeue, joined_smaller_limit) = match scheduler.jobs.get_mut(&key) {
+                Some(job) => {
+                    job.waiters.push(waiter);
+                    let smaller_limit = !covers_size(job.options.max_size, options.max_size);
+                    if !job.running {
+                        if smaller_limit {
+                            job.options.max_size = options.max_size;
+                        }
+                        if job.options.room_id.is_none() {
+                            job.options.room_id = options.room_id.clone();
+                        }
+                    }
+                    let raise = !job.running && priority > job.priority;
+                    if raise {
+                        job.priority = priority;
+                    }
+                    (raise, job.running && smaller_limit)
+                }
+                None => {
+                    let id = scheduler.seq;
+                    scheduler.jobs.insert(
+                        key.clone(),
+                        Job {
+                            id,
+                            options: options.clone(),
+                            priority,
+                            running: false,
+                            waiters: vec![waiter],
+                        },
+                    );
+                    (true, false)
+                }
+            };
+            if requeue {
+                scheduler.push(key.clone(), priority);
+            }
+            joined_smaller_limit
+        };
+
+        start_jobs(app_handle);
+
+        let result = result_rx
+            .await
+            .unwrap_or_else(|_| Err(AppError::Cancelled(mxc_uri.clone())));
+        if joined_smaller_limit && !retried && matches!(result, Err(AppError::FileTooLarge(_))) {
+            retried = true;
+            continue;
+        }
+        return result;
+    }
+}
+
+/// Start queued jobs while there are free download slots
+fn start_jobs(app_handle: &AppHandle) {
+    loop {
+        let Some((key, id, options)) = SCHEDULER.lock().unwrap().next_job() else {
+            return;
+        };
+
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            let state = app_handle.state::<AppState>();
+            let data = app_handle.state::<AppData>();
+            let result = get_or_download_media(&options, &app_handle, &state, &data).await;
+
+            let waiters = {
+                let mut scheduler = SCHEDULER.lock().unwrap();
+                scheduler.running -= 1;
+                match scheduler.jobs.get(&key) {
+                    Some(job) if job.id == id => scheduler
+                        .jobs
+                        .remove(&key)
+                        .map(|job| job.waiters)
+                        .unwrap_or_default(),
+                    // Cancelled while downloading
+                    _ => Vec::new(),
+                }
+            };
+            for waiter in waiters {
+                let _ = waiter.result_tx.send(clone_result(&result));
+            }
+
+            start_jobs(&app_handle);
+        });
+    }
+}
+
+fn clone_result(result: &DownloadResult) -> DownloadResult {
+    match result {
+        Ok(media) => Ok(media.clone()),
+        Err(e) => Err(e.clone()),
+    }
+}
+
+/// Remove the waiters of a batch, dropping queued jobs nobody waits for
+/// anymore and cancelling such jobs that are already downloading
+async fn cancel_batch(batch_id: &str) -> usize {
+    let (cancelled, stopped) = {
+        let mut scheduler = SCHEDULER.lock().unwrap();
+        let mut cancelled = 0;
+        let mut abandoned = Vec::new();
+
+        for (key, job) in scheduler.jobs.iter_mut() {
+            let before = job.waiters.len();
+            job.waiters
+                .retain(|waiter| waiter.batch_id.as_deref() != Some(batch_id));
+            cancelled += before - job.waiters.len();
+            if before > 0 && job.waiters.is_empty() {
+                abandoned.push(key.clone());
+            }
+        }
+
+        // Queued jobs are dropped; their heap entries are skipped when popped
+        let mut stopped = Vec::new();
+        for key in abandoned {
+            // Scheduled downloads are registered under their job key
+            if let Some(job) = scheduler.jobs.remove(&key)
+                && job.running
+            {
+                stopped.push(key);
+            }
+        }
+
+        (cancelled, stopped)
+    };
+
+    for download_id in stopped {
+        let _ = cancel_media_download(download_id).await;
+    }
+
+    cancelled
+}
+
+/// Preload media files in the background
+///
+/// Files are downloaded through the shared scheduler; the report lists the
+/// outcome of every URI.
+#[command]
+pub async fn preload_media(
+    mxc_uris: Vec<String>,
+    batch_id: Option<String>,
+    priority: Option<DownloadPriority>,
+    room_id: Option<String>,
+    app_handle: AppHandle,
+) -> Result<PreloadMediaReport, AppError> {
+    let batch_id = batch_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let priority = priority.unwrap_or_default();
+    info!(
+        "Preloading {} media files in batch {}",
+        mxc_uris.len(),
+        batch_id
+    );
+
+    let downloads = mxc_uris.iter().map(|mxc_uri| {
+        let options = DownloadMediaOptions {
+            mxc_uri: mxc_uri.clone(),
+            force: false,
+            max_size: Some(PRELOAD_MAX_SIZE),
+            thumbnail: None,
+            download_id: None,
+            room_id: room_id.clone(),
+        };
+        schedule_download(&app_handle, options, priority, Some(batch_id.clone()))
+    });
+    let results = futures::future::join_all(downloads).await;
+
+    let items: Vec<PreloadMediaItem> = mxc_uris
+        .into_iter()
+        .zip(results)
+        .map(|(mxc_uri, result)| match result {
+            Ok(media) => PreloadMediaItem {
+                mxc_uri,
+                status: PreloadStatus::Success,
+                local_path: Some(media.local_path),
+                error: None,
+            },
+            Err(AppError::Cancelled(_)) => PreloadMediaItem {
+                mxc_uri,
+                status: PreloadStatus::Cancelled,
+                local_path: None,
+                error: None,
+            },
+            Err(e) => PreloadMediaItem {
+                mxc_uri,
+                status: PreloadStatus::Failed,
+                local_path: None,
+                error: Some(e.to_string()),
+            },
+        })
+        .collect();
+
+    let mut report = PreloadMediaReport {
+        batch_id,
+        succeeded: 0,
+        failed: 0,
+        cancelled: 0,
+        items: Vec::new(),
+    };
+    for item in &items {
+        match item.status {
+            PreloadStatus::Success => report.succeeded += 1,
+            PreloadStatus::Failed => report.failed += 1,
+            PreloadStatus::Cancelled => report.cancelled += 1,
+        }
+    }
+    report.items = items;
+
+    info!(
+        "Media preload {} completed: {} succeeded, {} failed, {} cancelled",
+        report.batch_id, report.succeeded, report.failed, report.cancelled
+    );
+
+    Ok(report)
+}
+
+/// Cancel the media of a preload batch that has not been downloaded yet
+///
+/// Returns the number of cancelled requests. Downloads that other callers
+/// still wait for keep running.
+#[command]
+pub async fn cancel_media_preload(batch_id: String) -> Result<usize, AppError> {
+    info!("Cancelling media preload batch: {}", batch_id);
+
+    Ok(cancel_batch(&batch_id).await)
+}
@@ -0,0 +1,452 @@
+use entity::im_message;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::State;
+use tracing::{info, warn};
+
+use crate::AppData;
+use crate::pojo::common::{CursorPageParam, CursorPageResp};
+use crate::repository::im_message_fts_repository::{self, MessageSearchFilter};
+use crate::repository::im_message_repository::{self, ChatHistoryFilter, RECALL_MESSAGE_TYPE};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FromUser {
+    pub uid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loc_place: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Msg {
+    pub id: String,
+    pub room_id: String,
+    #[serde(rename = "type")]
+    pub msg_type: Option<u8>,
+    pub body: Option<Value>,
+    pub send_time: Option<i64>,
+    pub message_marks: Option<Value>,
+    /// 前端 `MessageStatusEnum` 的字符串值，保存时归一化
+    pub status: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_server_ts: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mxc_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageResp {
+    pub from_user: FromUser,
+    pub message: Msg,
+    pub send_time: Option<i64>,
+    pub time_block: Option<i64>,
+    /// 发送前的临时消息 ID，保存成功后删除
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_msg_id: Option<String>,
+}
+
+impl MessageResp {
+    fn into_model(self, login_uid: String) -> im_message::Model {
+        let now = chrono::Utc::now().timestamp_millis();
+        let msg = self.message;
+
+        let send_time = msg
+            .send_time
+            .or(self.send_time)
+            .or(msg.origin_server_ts)
+            .unwrap_or(now);
+
+        // Matrix 消息的 ID 即 event_id
+        let event_id = msg
+            .event_id
+            .or_else(|| msg.id.starts_with('$').then(|| msg.id.clone()));
+
+        let mxc_url = msg.mxc_url.or_else(|| {
+            msg.body
+                .as_ref()
+                .and_then(|body| body.get("url"))
+                .and_then(Value::as_str)
+                .filter(|url| url.starts_with("mxc://"))
+                .map(str::to_string)
+        });
+
+        im_message::Model {
+            id: msg.id,
+            uid: self.from_user.uid,
+            nickname: self.from_user.username,
+            room_id: msg.room_id,
+            send_time: Some(send_time),
+            message_type: msg.msg_type,
+            body: msg.body.map(|body| body.to_string()),
+            message_marks: msg.message_marks.map(|marks| marks.to_string()),
+            create_time: Some(now),
+            update_time: Some(now),
+            login_uid,
+            send_status: normalize_send_status(msg.status.as_ref()),
+            time_block: self.time_block,
+            event_id,
+            mxc_url,
+            sender: msg.sender,
+            origin_server_ts: msg.origin_server_ts,
+            thumbnail_path: msg.thumbnail_path,
+        }
+    }
+}
+
+impl From<im_message::Model> for MessageResp {
+    fn from(model: im_message::Model) -> Self {
+        let parse_json = |value: Option<String>| {
+            value.and_then(|value| serde_json::from_str::<Value>(&value).ok())
+        };
+
+        MessageResp {
+            from_user: FromUser {
+                uid: model.uid,
+                username: model.nickname,
+                ..Default::default()
+            },
+            message: Msg {
+                id: model.id,
+                room_id: model.room_id,
+                msg_type: model.message_type,
+                body: parse_json(model.body),
+                send_time: model.send_time,
+                message_marks: parse_json(model.message_marks),
+                status: Some(Value::String(model.send_status)),
+                event_id: model.event_id,
+                sender: model.sender,
+                origin_server_ts: model.origin_server_ts,
+                mxc_url: model.mxc_url,
+                thumbnail_path: model.thumbnail_path,
+            },
+            send_time: model.send_time,
+            time_block: model.time_block,
+            old_msg_id: None,
+        }
+    }
+}
+
+/// 将前端的消息状态归一为数据库中的 pending / success / fail
+///
+/// sent、delivered、read 都表示服务端已收到，记为 success；没有状态的消息（如从服务端同步的）
+/// 同样记为 success。无法识别的值（包括数字）记录警告后按 success 保存。
+fn normalize_send_status(status: Option<&Value>) -> String {
+    let Some(status) = status.filter(|status| !status.is_null()) else {
+        return "success".to_string();
+    };
+    match status.as_str() {
+        Some("pending" | "sending") => "pending",
+        Some("fail" | "failed") => "fail",
+        Some("success" | "sent" | "delivered" | "read") => "success",
+        _ => {
+            warn!("Unknown message status {}, saved as success", status);
+            "success"
+        }
+    }
+    .to_string()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DateRange {
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Pagination {
+    pub page: u64,
+    pub page_size: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatHistoryQueryParam {
+    pub room_id: String,
+    /// all / image / file / video / voice
+    pub message_type: Option<String>,
+    pub search_keyword: Option<String>,
+    /// asc / desc，默认 desc
+    pub sort_order: Option<String>,
+    pub date_range: Option<DateRange>,
+    pub pagination: Pagination,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatHistoryResponse {
+    pub messages: Vec<MessageResp>,
+    pub has_more: bool,
+    pub current_page: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchMessagesParam {
+    pub keyword: String,
+    pub room_id: Option<String>,
+    /// Matrix 用户 ID 或本地 uid
+    pub sender: Option<String>,
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    #[serde(default)]
+    pub message_types: Vec<u8>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchMessageResp {
+    #[serde(flatten)]
+    pub message: MessageResp,
+    /// 带 `<mark>` 高亮的摘要
+    pub snippet: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveMessageMarkReq {
+    pub msg_id: String,
+    pub mark_type: Value,
+    pub mark_count: u64,
+    /// 1 -> 确认, 2 -> 取消
+    pub act_type: u8,
+    pub uid: String,
+}
+
+/// 当前登录账号的用户 ID
+///
+/// 本地消息按账号隔离，未登录时写入或查询都会落到空账号下，直接返回错误。
+async fn current_login_uid(state: &AppData) -> Result<String, String> {
+    let login_uid = state.user_info.lock().await.uid.clone();
+    if login_uid.is_empty() {
+        return Err("当前未登录账号".to_string());
+    }
+    Ok(login_uid)
+}
+
+/// 保存单条消息到本地数据库
+#[tauri::command]
+pub async fn save_msg(data: MessageResp, state: State<'_, AppData>) -> Result<(), String> {
+    let login_uid = current_login_uid(&state).await?;
+    let old_msg_id = data.old_msg_id.clone();
+    let model = data.into_model(login_uid);
+
+    let txn = state
+        .db_conn
+        .begin()
+        .await
+        .map_err(|e| format!("开启事务失败: {e}"))?;
+    im_message_repository::save_message(&txn, model, old_msg_id.as_deref()).await?;
+    txn.commit()
+        .await
+        .map_err(|e| format!("提交事务失败: {e}"))?;
+
+    Ok(())
+}
+
+/// 按游标分页查询房间消息
+#[tauri::command]
+pub async fn page_msg(
+    room_id: String,
+    param: CursorPageParam,
+    state: State<'_, AppData>,
+) -> Result<CursorPageResp<Vec<MessageResp>>, String> {
+    let login_uid = current_login_uid(&state).await?;
+    let page = im_message_repository::cursor_page_messages(
+        state.db_conn.as_ref(),
+        &room_id,
+        param,
+        &login_uid,
+    )
+    .await?;
+
+    Ok(CursorPageResp {
+        cursor: page.cursor,
+        is_last: page.is_last,
+        list: page
+            .list
+            .map(|list| list.into_iter().map(MessageResp::from).collect()),
+        total: page.total,
+    })
+}
+
+/// 查询聊天历史记录（支持类型、关键字与时间范围筛选）
+#[tauri::command]
+pub async fn query_chat_history(
+    param: ChatHistoryQueryParam,
+    state: State<'_, AppData>,
+) -> Result<ChatHistoryResponse, String> {
+    let login_uid = current_login_uid(&state).await?;
+
+    let message_types = match param.message_type.as_deref() {
+        Some("image") => vec![3],
+        Some("file") => vec![4],
+        Some("voice") => vec![5],
+        Some("video") => vec![6],
+        _ => Vec::new(),
+    };
+
+    let (start_time, end_time) = param
+        .date_range
+        .map(|range| (range.start_time, range.end_time))
+        .unwrap_or_default();
+
+    let filter = ChatHistoryFilter {
+        room_id: param.room_id,
+        message_types,
+        keyword: param.search_keyword,
+        start_time,
+        end_time,
+        ascending: param.sort_order.as_deref() == Some("asc"),
+    };
+
+    let current_page = param.pagination.page.max(1);
+    let (messages, has_more) = im_message_repository::query_chat_history(
+        state.db_conn.as_ref(),
+        filter,
+        &login_uid,
+        current_page,
+        param.pagination.page_size,
+    )
+    .await?;
+
+    Ok(ChatHistoryResponse {
+        messages: messages.into_iter().map(MessageResp::from).collect(),
+        has_more,
+        current_page,
+    })
+}
+
+/// 全文检索本地聊天记录
+#[tauri::command]
+pub async fn search_messages(
+    param: SearchMessagesParam,
+    page: CursorPageParam,
+    state: State<'_, AppData>,
+) -> Result<CursorPageResp<Vec<SearchMessageResp>>, String> {
+    let login_uid = current_login_uid(&state).await?;
+
+    let filter = MessageSearchFilter {
+        keyword: param.keyword,
+        room_id: param.room_id,
+        sender: param.sender,
+        start_time: param.start_time,
+        end_time: param.end_time,
+        message_types: param.message_types,
+    };
+
+    let result = im_message_fts_repository::search_messages(
+        state.db_conn.as_ref(),
+        filter,
+        page,
+        &login_uid,
+    )
+    .await?;
+
+    Ok(CursorPageResp {
+        cursor: result.cursor,
+        is_last: result.is_last,
+        list: result.list.map(|hits| {
+            hits.into_iter()
+                .map(|hit| SearchMessageResp {
+                    message: MessageResp::from(hit.message),
+                    snippet: hit.snippet,
+                })
+                .collect()
+        }),
+        total: result.total,
+    })
+}
+
+/// 删除单条消息
+#[tauri::command]
+pub async fn delete_message(
+    message_id: String,
+    room_id: Option<String>,
+    state: State<'_, AppData>,
+) -> Result<(), String> {
+    let login_uid = current_login_uid(&state).await?;
+    im_message_repository::delete_message(
+        state.db_conn.as_ref(),
+        &message_id,
+        room_id.as_deref(),
+        &login_uid,
+    )
+    .await?;
+    Ok(())
+}
+
+/// 删除房间内的所有聊天记录，返回删除条数
+#[tauri::command]
+pub async fn delete_room_messages(
+    room_id: String,
+    state: State<'_, AppData>,
+) -> Result<u64, String> {
+    let login_uid = current_login_uid(&state).await?;
+    let deleted =
+        im_message_repository::delete_room_messages(state.db_conn.as_ref(), &room_id, &login_uid)
+            .await?;
+    info!("Deleted {} messages in room {}", deleted, room_id);
+    Ok(deleted)
+}
+
+/// 更新消息撤回状态
+#[tauri::command]
+pub async fn update_message_recall_status(
+    message_id: String,
+    message_type: Option<u8>,
+    message_body: String,
+    state: State<'_, AppData>,
+) -> Result<(), String> {
+    let login_uid = current_login_uid(&state).await?;
+    im_message_repository::update_message_recall_status(
+        state.db_conn.as_ref(),
+        &message_id,
+        message_type.unwrap_or(RECALL_MESSAGE_TYPE),
+        &message_body,
+        &login_uid,
+    )
+    .await?;
+    Ok(())
+}
+
+/// 保存消息标记（点赞、表情回应等）
+#[tauri::command]
+pub async fn save_message_mark(
+    data: SaveMessageMarkReq,
+    state: State<'_, AppData>,
+) -> Result<(), String> {
+    let login_uid = current_login_uid(&state).await?;
+
+    let mark_type = match &data.mark_type {
+        Value::String(mark_type) => mark_type.clone(),
+        other => other.to_string(),
+    };
+    // 只有当前用户自己的操作才改变 userMarked
+    let user_marked = (data.uid == login_uid).then_some(data.act_type == 1);
+
+    im_message_repository::update_message_mark(
+        state.db_conn.as_ref(),
+        &data.msg_id,
+        &login_uid,
+        &mark_type,
+        data.mark_count,
+        user_marked,
+    )
+    .await?;
+    Ok(())
+}
@@ -2,10 +2,21 @@ use tauri::{AppHandle, State};
 
 use crate::AppData;
 
+pub mod account_command;
 pub mod app_state_command;
+pub mod database_command;
 pub mod error_log_command;
+pub mod export_command;
+pub mod image_command;
 pub mod media;
+pub mod media_scheduler;
+pub mod message_command;
+pub mod room_member_command;
 pub mod setting_command;
+pub mod upload_queue_command;
+pub mod user_command;
+pub mod video_command;
+pub mod voice_command;
 
 // A custom task for setting the state of a setup task
 #[tauri::command]
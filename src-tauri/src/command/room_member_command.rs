@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+
+use entity::im_room_member;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, State};
+use tracing::info;
+
+use crate::AppData;
+use crate::pojo::common::{CursorPageParam, CursorPageResp};
+use crate::repository::im_room_member_repository::{self, MemberOrder};
+
+/// 房间成员变化事件
+const ROOM_MEMBERS_CHANGED_EVENT: &str = "room-members-changed";
+
+/// 前端懒加载得到的房间成员
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomMemberReq {
+    /// Matrix 用户 ID
+    pub uid: String,
+    /// 显示名称，为空时使用用户 ID
+    pub name: Option<String>,
+    pub avatar: Option<String>,
+    pub account: Option<String>,
+    /// 群昵称
+    pub my_name: Option<String>,
+    pub active_status: Option<u8>,
+    /// 角色，Matrix 中为权限等级
+    #[serde(rename = "roleId")]
+    pub group_role: Option<i64>,
+    pub loc_place: Option<String>,
+    pub last_opt_time: Option<i64>,
+    pub user_state_id: Option<String>,
+    /// join / invite / leave / ban，leave 与 ban 会从缓存中移除
+    pub membership: Option<String>,
+}
+
+impl RoomMemberReq {
+    fn is_removed(&self) -> bool {
+        matches!(self.membership.as_deref(), Some("leave" | "ban"))
+    }
+
+    /// 与已缓存的成员合并，未携带的字段保留原值
+    fn into_model(
+        self,
+        room_id: &str,
+        existing: Option<&im_room_member::Model>,
+        login_uid: &str,
+        now: i64,
+    ) -> im_room_member::Model {
+        let name = self
+            .name
+            .filter(|name| !name.is_empty())
+            .or_else(|| existing.map(|member| member.name.clone()))
+            .unwrap_or_else(|| self.uid.clone());
+
+        im_room_member::Model {
+            // 已缓存的成员沿用原主键
+            id: existing
+                .map(|member| member.id.clone())
+                .unwrap_or_else(|| im_room_member_repository::member_id(room_id, &self.uid)),
+            room_id: Some(room_id.to_string()),
+            account: self
+                .account
+                .or_else(|| existing.and_then(|member| member.account.clone())),
+            my_name: self
+                .my_name
+                .or_else(|| existing.and_then(|member| member.my_name.clone())),
+            active_status: self
+                .active_status
+                .or_else(|| existing.and_then(|member| member.active_status)),
+            group_role: self
+                .group_role
+                .or_else(|| existing.and_then(|member| member.group_role)),
+            loc_place: self
+                .loc_place
+                .or_else(|| existing.and_then(|member| member.loc_place.clone())),
+            last_opt_time: self
+                .last_opt_time
+                .or_else(|| existing.map(|member| member.last_opt_time))
+                .unwrap_or(now),
+            create_time: existing.and_then(|member| member.create_time).or(Some(now)),
+            name,
+            avatar: self
+                .avatar
+                .or_else(|| existing.and_then(|member| member.avatar.clone())),
+            user_state_id: self
+                .user_state_id
+                .or_else(|| existing.and_then(|member| member.user_state_id.clone())),
+            uid: Some(self.uid),
+            login_uid: login_uid.to_string(),
+        }
+    }
+}
+
+/// 房间成员的变化，写入缓存后通过事件通知前端
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomMembersChanged {
+    pub room_id: String,
+    pub added: Vec<im_room_member::Model>,
+    pub updated: Vec<im_room_member::Model>,
+    /// 被移除成员的用户 ID
+    pub removed: Vec<String>,
+}
+
+impl RoomMembersChanged {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// 成员分页的排序方式：name / lastOptTime，默认按名称
+fn parse_member_order(order_by: Option<&str>) -> MemberOrder {
+    match order_by {
+        Some("lastOptTime" | "last_opt_time") => MemberOrder::LastOptTime,
+        _ => MemberOrder::Name,
+    }
+}
+
+/// 写入前端懒加载的房间成员
+///
+/// `complete` 为 true 表示 `members` 是房间的完整成员列表，缓存中其余成员会被移除。
+/// 实际发生变化时发送 `room-members-changed` 事件，并返回本次的变化。
+#[tauri::command]
+pub async fn save_room_members(
+    room_id: String,
+    members: Vec<RoomMemberReq>,
+    complete: Option<bool>,
+    app_handle: AppHandle,
+    state: State<'_, AppData>,
+) -> Result<RoomMembersChanged, String> {
+    let login_uid = state.user_info.lock().await.uid.clone();
+    let complete = complete.unwrap_or(false);
+    let now = chrono::Utc::now().timestamp_millis();
+
+    let txn = state
+        .db_conn
+        .begin()
+        .await
+        .map_err(|e| format!("开启事务失败: {e}"))?;
+
+    // 完整同步时需要全部成员来找出已离开的成员
+    let existing = if complete {
+        im_room_member_repository::find_room_members(&txn, &room_id, &login_uid).await?
+    } else {
+        let uids: Vec<String> = members.iter().map(|member| member.uid.clone()).collect();
+        im_room_member_repository::find_members_by_uids(&txn, &room_id, &uids, &login_uid).await?
+    };
+    let existing: HashMap<String, im_room_member::Model> = existing
+        .into_iter()
+        .filter_map(|member| Some((member.uid.clone()?, member)))
+        .collect();
+
+    let mut changes = RoomMembersChanged {
+        room_id: room_id.clone(),
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+    for member in members {
+        if !seen.insert(member.uid.clone()) {
+            continue;
+        }
+        let cached = existing.get(&member.uid);
+        if member.is_removed() {
+            if cached.is_some() {
+                changes.removed.push(member.uid);
+            }
+            continue;
+        }
+
+        let model = member.into_model(&room_id, cached, &login_uid, now);
+        match cached {
+            None => changes.added.push(model),
+            Some(cached) if *cached != model => changes.updated.push(model),
+            Some(_) => {}
+        }
+    }
+    if complete {
+        changes
+            .removed
+            .extend(existing.into_keys().filter(|uid| !seen.contains(uid)));
+    }
+
+    let upserts = changes
+        .added
+        .iter()
+        .chain(changes.updated.iter())
+        .cloned()
+        .collect();
+    im_room_member_repository::save_members(&txn, upserts).await?;
+    im_room_member_repository::delete_members(&txn, &room_id, &changes.removed, &login_uid).await?;
+    txn.commit()
+        .await
+        .map_err(|e| format!("提交事务失败: {e}"))?;
+
+    if !changes.is_empty() {
+        info!(
+            "Room members changed in {}: {} added, {} updated, {} removed",
+            room_id,
+            changes.added.len(),
+            changes.updated.len(),
+            changes.removed.len()
+        );
+        let _ = app_handle.emit(ROOM_MEMBERS_CHANGED_EVENT, &changes);
+    }
+
+    Ok(changes)
+}
+
+/// 查询房间的全部缓存成员
+#[tauri::command]
+pub async fn get_room_members(
+    room_id: String,
+    state: State<'_, AppData>,
+) -> Result<Vec<im_room_member::Model>, String> {
+    let login_uid = state.user_info.lock().await.uid.clone();
+    Ok(
+        im_room_member_repository::find_room_members(state.db_conn.as_ref(), &room_id, &login_uid)
+            .await?,
+    )
+}
+
+/// 按游标分页查询房间成员
+///
+/// `order_by` 为 `name`（默认）或 `lastOptTime`，`keyword` 按名称、群昵称与用户 ID 过滤。
+#[tauri::command]
+pub async fn cursor_page_room_members(
+    room_id: String,
+    param: CursorPageParam,
+    order_by: Option<String>,
+    keyword: Option<String>,
+    state: State<'_, AppData>,
+) -> Result<CursorPageResp<Vec<im_room_member::Model>>, String> {
+    let login_uid = state.user_info.lock().await.uid.clone();
+    Ok(im_room_member_repository::cursor_page_members(
+        state.db_conn.as_ref(),
+        &room_id,
+        param,
+        parse_member_order(order_by.as_deref()),
+        keyword.as_deref(),
+        &login_uid,
+    )
+    .await?)
+}
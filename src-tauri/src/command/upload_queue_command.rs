@@ -0,0 +1,522 @@
+//! 异步媒体上传队列（MSC2246）
+//!
+//! 先通过 `POST /_matrix/media/v1/create` 预留 mxc URI，消息可以立即发送；
+//! 文件内容记录在 `im_upload_queue` 表中，由后台任务通过
+//! `PUT /_matrix/media/v3/upload/{serverName}/{mediaId}` 上传，失败后按指数退避重试，
+//! 应用重启后继续处理。上传结束时同步更新对应消息的 `send_status`，上传成功的队列项随即移除。
+
+use std::path::Path;
+use std::time::Duration;
+
+use entity::im_upload_queue;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::fs;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::AppData;
+use crate::command::media::{
+    get_image_dimensions, get_max_upload_size, media_error, parse_mxc_uri, resolve_mime_type,
+    send_file_body,
+};
+use crate::error::AppError;
+use crate::repository::im_message_repository;
+use crate::repository::im_upload_queue_repository::{self, STATUS_FAIL, STATUS_PENDING};
+use crate::state::AppState;
+use crate::utils::attachment_crypto::{AttachmentCipher, EncryptedFile};
+
+/// 上传状态变化时发送的事件
+const UPLOAD_STATUS_EVENT: &str = "media-upload-status";
+
+/// 最多尝试次数，超过后标记为失败
+const MAX_ATTEMPTS: i32 = 8;
+
+/// 首次重试的等待时间（毫秒），之后每次翻倍
+const BASE_RETRY_DELAY_MS: i64 = 5_000;
+
+/// 重试等待时间上限（毫秒）
+const MAX_RETRY_DELAY_MS: i64 = 10 * 60 * 1000;
+
+/// 队列中没有待处理项时的轮询间隔
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 每批取出的队列项数
+const BATCH_SIZE: u64 = 4;
+
+lazy_static::lazy_static! {
+    /// 唤醒后台上传任务
+    static ref UPLOAD_QUEUE_NOTIFY: Notify = Notify::new();
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EnqueueUploadReq {
+    pub room_id: String,
+    /// 引用该媒体的本地消息 ID
+    pub message_id: String,
+    /// 本地文件路径
+    pub path: String,
+    pub mime_type: Option<String>,
+    pub file_name: Option<String>,
+    /// 加密房间需要加密上传
+    #[serde(default)]
+    pub encrypt: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EnqueueUploadResp {
+    pub queue_id: String,
+    /// 预留的 mxc URI，可立即用于发送消息
+    pub content_uri: String,
+    pub size: u64,
+    pub mime_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// 加密上传时写入事件内容的 `EncryptedFile`
+    pub file: Option<EncryptedFile>,
+    /// 服务端回收未上传 mxc 的时间（毫秒）
+    pub unused_expires_at: Option<i64>,
+}
+
+/// `media-upload-status` 事件内容
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadStatusPayload {
+    pub queue_id: String,
+    pub room_id: String,
+    pub message_id: String,
+    pub mxc_uri: String,
+    /// pending, success, fail
+    pub status: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CreateMediaResponse {
+    content_uri: String,
+    unused_expires_at: Option<i64>,
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+async fn current_login_uid(data: &AppData) -> Result<String, AppError> {
+    let login_uid = data.user_info.lock().await.uid.clone();
+    if login_uid.is_empty() {
+        return Err(AppError::Request("No logged in user".to_string()));
+    }
+    Ok(login_uid)
+}
+
+/// 唤醒后台上传任务，例如登录或新文件入队后
+pub fn wake_upload_worker() {
+    UPLOAD_QUEUE_NOTIFY.notify_one();
+}
+
+/// 预留 mxc URI 并把文件加入上传队列
+#[tauri::command]
+pub async fn enqueue_media_upload(
+    req: EnqueueUploadReq,
+    state: State<'_, AppState>,
+    data: State<'_, AppData>,
+) -> Result<EnqueueUploadResp, AppError> {
+    info!("Enqueue media upload: {} ({})", req.path, req.message_id);
+
+    let login_uid = current_login_uid(&data).await?;
+    let path = Path::new(&req.path);
+    let metadata = fs::metadata(path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    if !metadata.is_file() {
+        return Err(AppError::Io(format!("Not a file: {}", req.path)));
+    }
+    let size = metadata.len();
+
+    let homeserver = state.homeserver().await.trim_end_matches('/').to_string();
+    let access_token = state.access_token().await.ok_or(AppError::TokenExpired)?;
+
+    // 发送消息前先检查服务端的上传大小限制
+    if let Some(max_upload_size) = get_max_upload_size(&state, &homeserver).await
+        && size > max_upload_size
+    {
+        return Err(AppError::MediaTooLarge(format!(
+            "File size {size} exceeds server limit {max_upload_size}"
+        )));
+    }
+
+    let mime_type = resolve_mime_type(path, req.mime_type.clone());
+    let (width, height) = get_image_dimensions(path, &mime_type);
+
+    // 加密上传需要在发送消息前得到密文哈希
+    let encryption = if req.encrypt {
+        let cipher = AttachmentCipher::generate();
+        let sha256 = cipher
+            .hash_encrypted_file(path)
+            .await
+            .map_err(|e| AppError::Io(e.to_string()))?;
+        Some((cipher, sha256))
+    } else {
+        None
+    };
+
+    let response = state
+        .http_client
+        .post(format!("{homeserver}/_matrix/media/v1/create"))
+        .bearer_auth(&access_token)
+        .send()
+        .await
+        .map_err(|e| AppError::Network(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(media_error(response, &req.path).await);
+    }
+    let created = response
+        .json::<CreateMediaResponse>()
+        .await
+        .map_err(|e| AppError::Network(e.to_string()))?;
+
+    let file = encryption
+        .map(|(cipher, sha256)| cipher.to_encrypted_file(created.content_uri.clone(), &sha256));
+    let encrypted_file = file
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| AppError::Unexpected(e.to_string()))?;
+
+    let now = now_millis();
+    let queue_id = uuid::Uuid::new_v4().to_string();
+    im_upload_queue_repository::insert(
+        data.db_conn.as_ref(),
+        im_upload_queue::Model {
+            id: queue_id.clone(),
+            login_uid,
+            room_id: req.room_id,
+            message_id: req.message_id,
+            mxc_uri: created.content_uri.clone(),
+            file_path: req.path,
+            file_name: req.file_name,
+            mime_type: mime_type.clone(),
+            encrypted_file,
+            status: STATUS_PENDING.to_string(),
+            attempts: 0,
+            next_attempt_at: now,
+            unused_expires_at: created.unused_expires_at,
+            last_error: None,
+            create_time: now,
+            update_time: now,
+        },
+    )
+    .await?;
+
+    wake_upload_worker();
+
+    Ok(EnqueueUploadResp {
+        queue_id,
+        content_uri: created.content_uri,
+        size,
+        mime_type,
+        width,
+        height,
+        file,
+        unused_expires_at: created.unused_expires_at,
+    })
+}
+
+/// 查询当前账号的上传队列
+#[tauri::command]
+pub async fn get_upload_queue(
+    room_id: Option<String>,
+    data: State<'_, AppData>,
+) -> Result<Vec<im_upload_queue::Model>, AppError> {
+    let login_uid = current_login_uid(&data).await?;
+    Ok(
+        im_upload_queue_repository::list(data.db_conn.as_ref(), &login_uid, room_id.as_deref())
+            .await?,
+    )
+}
+
+/// 手动重试已失败的上传
+#[tauri::command]
+pub async fn retry_media_upload(
+    queue_id: String,
+    data: State<'_, AppData>,
+) -> Result<bool, AppError> {
+    let login_uid = current_login_uid(&data).await?;
+    let db = data.db_conn.as_ref();
+
+    let Some(item) = im_upload_queue_repository::find_by_id(db, &queue_id, &login_uid).await?
+    else {
+        return Ok(false);
+    };
+
+    let requeued =
+        im_upload_queue_repository::requeue_failed(db, &queue_id, &login_uid, now_millis()).await?;
+    if requeued {
+        im_message_repository::update_message_send_status(
+            db,
+            &item.message_id,
+            &login_uid,
+            STATUS_PENDING,
+        )
+        .await?;
+        wake_upload_worker();
+    }
+
+    Ok(requeued)
+}
+
+/// 从队列中移除上传项，正在进行的上传不会被中断
+#[tauri::command]
+pub async fn remove_media_upload(
+    queue_id: String,
+    data: State<'_, AppData>,
+) -> Result<u64, AppError> {
+    let login_uid = current_login_uid(&data).await?;
+    Ok(im_upload_queue_repository::delete(data.db_conn.as_ref(), &queue_id, &login_uid).await?)
+}
+
+/// 启动后台上传任务
+pub fn start_upload_worker(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let data = app_handle.state::<AppData>();
+        match im_upload_queue_repository::reset_uploading(data.db_conn.as_ref()).await {
+            Ok(0) => {}
+            Ok(count) => info!("Resumed {} interrupted uploads", count),
+            Err(e) => warn!("Failed to reset interrupted uploads: {}", e),
+        }
+
+        loop {
+            let wait = match process_due_uploads(&app_handle).await {
+                Ok(Some(next_attempt_at)) => {
+                    let delay = (next_attempt_at - now_millis())
+                        .clamp(0, IDLE_POLL_INTERVAL.as_millis() as i64);
+                    Duration::from_millis(delay as u64)
+                }
+                Ok(None) => IDLE_POLL_INTERVAL,
+                Err(e) => {
+                    warn!("Failed to process upload queue: {}", e);
+                    IDLE_POLL_INTERVAL
+                }
+            };
+
+            tokio::select! {
+                _ = UPLOAD_QUEUE_NOTIFY.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    });
+}
+
+/// 处理所有已到期的上传，返回下一次计划上传的时间
+async fn process_due_uploads(app_handle: &AppHandle) -> Result<Option<i64>, AppError> {
+    let data = app_handle.state::<AppData>();
+    let state = app_handle.state::<AppState>();
+    let db = data.db_conn.as_ref();
+
+    let login_uid = data.user_info.lock().await.uid.clone();
+    if login_uid.is_empty() || state.access_token().await.is_none() {
+        return Ok(None);
+    }
+
+    loop {
+        let due =
+            im_upload_queue_repository::find_due(db, &login_uid, now_millis(), BATCH_SIZE).await?;
+        if due.is_empty() {
+            break;
+        }
+        for item in due {
+            process_upload(app_handle, &data, &state, item).await?;
+        }
+    }
+
+    Ok(im_upload_queue_repository::next_attempt_at(db, &login_uid).await?)
+}
+
+async fn process_upload(
+    app_handle: &AppHandle,
+    data: &AppData,
+    state: &AppState,
+    item: im_upload_queue::Model,
+) -> Result<(), AppError> {
+    let db = data.db_conn.as_ref();
+    if !im_upload_queue_repository::mark_uploading(db, &item.id, now_millis()).await? {
+        return Ok(());
+    }
+
+    let attempts = item.attempts + 1;
+    let result = upload_queued_file(app_handle, state, &item).await;
+    let now = now_millis();
+
+    let (status, error) = match result {
+        Ok(()) => {
+            im_upload_queue_repository::remove_succeeded(db, &item.id).await?;
+            info!(
+                "Queued upload finished: {} -> {}",
+                item.file_path, item.mxc_uri
+            );
+            (im_upload_queue_repository::STATUS_SUCCESS, None)
+        }
+        Err(e) => {
+            let error = e.to_string();
+            let expired = item.unused_expires_at.is_some_and(|expires| expires <= now);
+
+            if expired || attempts >= MAX_ATTEMPTS || !is_retryable(&e) {
+                warn!("Queued upload failed: {} ({})", item.file_path, error);
+                im_upload_queue_repository::mark_failed(db, &item.id, attempts, &error, now)
+                    .await?;
+                (STATUS_FAIL, Some(error))
+            } else {
+                let next_attempt_at = now + retry_delay(attempts, &e);
+                warn!(
+                    "Queued upload attempt {} failed, retrying at {}: {}",
+                    attempts, next_attempt_at, error
+                );
+                im_upload_queue_repository::schedule_retry(
+                    db,
+                    &item.id,
+                    attempts,
+                    next_attempt_at,
+                    &error,
+                    now,
+                )
+                .await?;
+                (STATUS_PENDING, Some(error))
+            }
+        }
+    };
+
+    // 重试期间消息保持 pending
+    if status != STATUS_PENDING {
+        let updated = im_message_repository::update_message_send_status(
+            db,
+            &item.message_id,
+            &item.login_uid,
+            status,
+        )
+        .await?;
+        if updated == 0 {
+            warn!(
+                "No message {} found to record upload status {}",
+                item.message_id, status
+            );
+        }
+    }
+
+    let _ = app_handle.emit(
+        UPLOAD_STATUS_EVENT,
+        UploadStatusPayload {
+            queue_id: item.id,
+            room_id: item.room_id,
+            message_id: item.message_id,
+            mxc_uri: item.mxc_uri,
+            status: status.to_string(),
+            attempts,
+            error,
+        },
+    );
+
+    Ok(())
+}
+
+/// 把队列项的文件内容上传到预留的 mxc URI
+async fn upload_queued_file(
+    app_handle: &AppHandle,
+    state: &AppState,
+    item: &im_upload_queue::Model,
+) -> Result<(), AppError> {
+    let path = Path::new(&item.file_path);
+    let size = fs::metadata(path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?
+        .len();
+
+    let encrypted_file = item
+        .encrypted_file
+        .as_deref()
+        .map(serde_json::from_str::<EncryptedFile>)
+        .transpose()
+        .map_err(|e| AppError::Decryption(e.to_string()))?;
+    let cipher = encrypted_file
+        .as_ref()
+        .map(AttachmentCipher::new)
+        .transpose()?;
+
+    let (server_name, media_id) = parse_mxc_uri(&item.mxc_uri)?;
+    let homeserver = state.homeserver().await.trim_end_matches('/').to_string();
+    let access_token = state.access_token().await.ok_or(AppError::TokenExpired)?;
+
+    let mut request = state
+        .http_client
+        .put(format!(
+            "{homeserver}/_matrix/media/v3/upload/{server_name}/{media_id}"
+        ))
+        .bearer_auth(access_token);
+    if cipher.is_none()
+        && let Some(file_name) = &item.file_name
+    {
+        request = request.query(&[("filename", file_name)]);
+    }
+
+    let (response, sha256) = send_file_body(
+        request,
+        path,
+        size,
+        &item.mime_type,
+        cipher.as_ref(),
+        item.id.clone(),
+        app_handle.clone(),
+    )
+    .await?;
+
+    // M_CANNOT_OVERWRITE_MEDIA：之前的尝试已上传成功，只是没有收到响应
+    if response.status() == reqwest::StatusCode::CONFLICT {
+        return Ok(());
+    }
+    if !response.status().is_success() {
+        return Err(media_error(response, &item.mxc_uri).await);
+    }
+
+    // 入队后文件被修改时，已发送事件中的哈希不再匹配，接收方无法解密
+    if let (Some(cipher), Some(encrypted_file), Some(sha256)) = (&cipher, &encrypted_file, sha256)
+        && cipher
+            .to_encrypted_file(item.mxc_uri.clone(), &sha256)
+            .hashes
+            != encrypted_file.hashes
+    {
+        return Err(AppError::IntegrityCheckFailed(format!(
+            "{} changed after it was queued",
+            item.file_path
+        )));
+    }
+
+    Ok(())
+}
+
+/// 文件缺失、过大、密钥错误等情况重试也不会成功
+fn is_retryable(error: &AppError) -> bool {
+    !matches!(
+        error,
+        AppError::Io(_)
+            | AppError::InvalidUri(_)
+            | AppError::FileTooLarge(_)
+            | AppError::MediaNotFound(_)
+            | AppError::MediaTooLarge(_)
+            | AppError::Decryption(_)
+            | AppError::IntegrityCheckFailed(_)
+    )
+}
+
+/// 指数退避，被限流时至少等待服务端要求的时间
+fn retry_delay(attempts: i32, error: &AppError) -> i64 {
+    let backoff = BASE_RETRY_DELAY_MS
+        .saturating_mul(1_i64 << (attempts - 1).clamp(0, 16))
+        .min(MAX_RETRY_DELAY_MS);
+
+    match error {
+        AppError::RateLimited(retry_after_ms) => backoff.max(*retry_after_ms as i64),
+        _ => backoff,
+    }
+}
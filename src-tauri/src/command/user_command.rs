@@ -0,0 +1,327 @@
+use sea_orm::ConnectionTrait;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+use tracing::{info, warn};
+
+use crate::command::upload_queue_command::wake_upload_worker;
+use crate::error::AppError;
+use crate::repository::im_user_repository;
+use crate::state::AppState;
+use crate::utils::{account_storage, secret_store};
+use crate::{AppData, UserInfo};
+
+/// 登录或刷新后得到的令牌
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateTokenReq {
+    /// Matrix 用户 ID，为空时使用当前登录用户
+    pub uid: Option<String>,
+    pub token: String,
+    pub refresh_token: Option<String>,
+    /// 登录时传入，刷新令牌时为空则沿用账号已保存的 homeserver
+    pub homeserver: Option<String>,
+}
+
+/// 返回给前端的令牌
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UserTokens {
+    pub token: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+pub(crate) fn access_token_name(uid: &str) -> String {
+    format!("{uid}:access_token")
+}
+
+pub(crate) fn refresh_token_name(uid: &str) -> String {
+    format!("{uid}:refresh_token")
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.is_empty())
+}
+
+pub(crate) async fn run_blocking<T, F>(f: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, AppError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| AppError::Unexpected(e.to_string()))?
+}
+
+/// 读取数据库列中保存的令牌，旧版本写入的明文原样返回
+pub(crate) fn load_column(value: Option<String>) -> Result<Option<String>, AppError> {
+    match non_empty(value) {
+        Some(value) if secret_store::is_reference(&value) => secret_store::load(&value),
+        value => Ok(value),
+    }
+}
+
+/// 删除数据库列引用的令牌，新引用与旧引用相同时保留
+fn delete_column(old: Option<&str>, new: Option<&str>) {
+    if let Some(old) = old.filter(|old| secret_store::is_reference(old) && Some(*old) != new) {
+        if let Err(e) = secret_store::delete(old) {
+            warn!("Failed to delete stale secret {}: {}", old, e);
+        }
+    }
+}
+
+/// 同步 Rust 侧媒体请求使用的会话，`token` 为空表示退出登录
+///
+/// `homeserver` 为空时保留当前值。启动早期 AppState 可能尚未注入，此时由之后的调用补齐。
+pub(crate) async fn sync_matrix_session(
+    app_handle: &AppHandle,
+    homeserver: Option<&str>,
+    token: Option<String>,
+) {
+    let Some(app_state) = app_handle.try_state::<AppState>() else {
+        return;
+    };
+    let logged_in = token.is_some();
+    {
+        let mut config = app_state.config.lock().await;
+        if let Some(homeserver) = homeserver.filter(|homeserver| !homeserver.is_empty()) {
+            config.homeserver = homeserver.trim_end_matches('/').to_string();
+        }
+        config.access_token = token;
+    }
+    if logged_in {
+        // 登录后继续处理之前未完成的上传
+        wake_upload_worker();
+    }
+}
+
+/// 把令牌写入安全存储，数据库中只保存引用
+pub(crate) async fn persist_tokens<C>(
+    db: &C,
+    uid: &str,
+    token: String,
+    refresh_token: Option<String>,
+) -> Result<(), AppError>
+where
+    C: ConnectionTrait,
+{
+    let existing = im_user_repository::find_user(db, uid).await?;
+    let (old_token_ref, old_refresh_ref) = existing
+        .map(|user| (user.token, user.refresh_token))
+        .unwrap_or_default();
+
+    let access_name = access_token_name(uid);
+    let refresh_name = refresh_token_name(uid);
+    let (token_ref, refresh_ref) = run_blocking(move || {
+        let token_ref = secret_store::save(&access_name, &token)?;
+        let refresh_ref = match refresh_token {
+            Some(refresh_token) => Some(secret_store::save(&refresh_name, &refresh_token)?),
+            None => None,
+        };
+        delete_column(old_token_ref.as_deref(), Some(&token_ref));
+        delete_column(old_refresh_ref.as_deref(), refresh_ref.as_deref());
+        Ok((token_ref, refresh_ref))
+    })
+    .await?;
+
+    im_user_repository::save_token_refs(db, uid, Some(token_ref), refresh_ref).await?;
+    Ok(())
+}
+
+/// 保存登录或刷新后的令牌
+///
+/// 兼容两种调用方式：`{ req: { uid, token, refreshToken } }` 与 `{ uid, token, refreshToken }`。
+/// 令牌保存在系统钥匙串中，不可用时保存在加密文件中。未知用户 ID 时只保存在内存中。
+#[tauri::command]
+pub async fn update_token(
+    req: Option<UpdateTokenReq>,
+    uid: Option<String>,
+    token: Option<String>,
+    refresh_token: Option<String>,
+    homeserver: Option<String>,
+    app_handle: AppHandle,
+    state: State<'_, AppData>,
+) -> Result<(), String> {
+    let req = match (req, token) {
+        (Some(req), _) => req,
+        (None, Some(token)) => UpdateTokenReq {
+            uid,
+            token,
+            refresh_token,
+            homeserver,
+        },
+        (None, None) => return Err("缺少令牌".to_string()),
+    };
+    let refresh_token = non_empty(req.refresh_token);
+
+    let uid = {
+        let mut user_info = state.user_info.lock().await;
+        if let Some(uid) = non_empty(req.uid) {
+            user_info.uid = uid;
+        }
+        user_info.token = req.token.clone();
+        user_info.refresh_token = refresh_token.clone().unwrap_or_default();
+        user_info.uid.clone()
+    };
+
+    let homeserver = non_empty(req.homeserver);
+    if uid.is_empty() {
+        warn!("update_token called without uid, tokens are kept in memory only");
+        sync_matrix_session(&app_handle, homeserver.as_deref(), Some(req.token)).await;
+        return Ok(());
+    }
+
+    let db = state.db_conn.as_ref();
+    let homeserver = match homeserver {
+        Some(homeserver) => {
+            im_user_repository::save_account(db, &uid, Some(homeserver.clone()), None, None)
+                .await?;
+            Some(homeserver)
+        }
+        None => im_user_repository::find_user(db, &uid)
+            .await?
+            .and_then(|user| user.homeserver),
+    };
+    sync_matrix_session(&app_handle, homeserver.as_deref(), Some(req.token.clone())).await;
+
+    account_storage::set_account(Some(&uid));
+    persist_tokens(db, &uid, req.token, refresh_token)
+        .await
+        .map_err(|e| format!("保存令牌失败: {e}"))?;
+    im_user_repository::touch_account(db, &uid).await?;
+    Ok(())
+}
+
+/// 获取当前用户的令牌
+///
+/// 内存中没有令牌时（如应用重启后），从最近一次登录的用户恢复。
+#[tauri::command]
+pub async fn get_user_tokens(
+    app_handle: AppHandle,
+    state: State<'_, AppData>,
+) -> Result<UserTokens, String> {
+    // 钥匙串访问可能很慢，读取期间不持有 user_info 的锁
+    let uid = {
+        let user_info = state.user_info.lock().await;
+        if !user_info.token.is_empty() {
+            return Ok(current_tokens(&user_info));
+        }
+        user_info.uid.clone()
+    };
+
+    let user = if uid.is_empty() {
+        im_user_repository::find_last_login_user(state.db_conn.as_ref()).await?
+    } else {
+        im_user_repository::find_user(state.db_conn.as_ref(), &uid).await?
+    };
+    let Some(user) = user else {
+        return Ok(UserTokens::default());
+    };
+
+    let (token_column, refresh_column) = (user.token, user.refresh_token);
+    let (token, refresh_token) =
+        run_blocking(move || Ok((load_column(token_column)?, load_column(refresh_column)?)))
+            .await
+            .map_err(|e| format!("读取令牌失败: {e}"))?;
+
+    if let Some(token) = &token {
+        {
+            let mut user_info = state.user_info.lock().await;
+            // 读取期间已经登录或切换了账号，以当前会话为准
+            if !user_info.token.is_empty() {
+                return Ok(current_tokens(&user_info));
+            }
+            user_info.uid = user.id.clone();
+            user_info.token = token.clone();
+            user_info.refresh_token = refresh_token.clone().unwrap_or_default();
+        }
+        account_storage::set_account(Some(&user.id));
+        sync_matrix_session(&app_handle, user.homeserver.as_deref(), Some(token.clone())).await;
+    }
+
+    Ok(UserTokens {
+        token,
+        refresh_token,
+    })
+}
+
+fn current_tokens(user_info: &UserInfo) -> UserTokens {
+    UserTokens {
+        token: Some(user_info.token.clone()),
+        refresh_token: non_empty(Some(user_info.refresh_token.clone())),
+    }
+}
+
+/// 退出登录时删除当前用户的令牌
+#[tauri::command]
+pub async fn remove_tokens(app_handle: AppHandle, state: State<'_, AppData>) -> Result<(), String> {
+    sync_matrix_session(&app_handle, None, None).await;
+    // 先清空内存中的令牌，删除钥匙串中的令牌时不持有 user_info 的锁
+    let uid = {
+        let mut user_info = state.user_info.lock().await;
+        user_info.token.clear();
+        user_info.refresh_token.clear();
+        user_info.uid.clone()
+    };
+    if uid.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(user) = im_user_repository::find_user(state.db_conn.as_ref(), &uid).await? {
+        run_blocking(move || {
+            for reference in [user.token, user.refresh_token].into_iter().flatten() {
+                if secret_store::is_reference(&reference) {
+                    secret_store::delete(&reference)?;
+                }
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| format!("删除令牌失败: {e}"))?;
+    }
+    im_user_repository::clear_token_refs(state.db_conn.as_ref(), &uid).await?;
+
+    info!("Removed tokens for {}", uid);
+    Ok(())
+}
+
+/// 把旧版本保存在数据库中的明文令牌迁移到安全存储
+///
+/// 只处理尚不是引用的列，迁移后的列即为引用，因此重复执行不会有副作用。
+/// 单个用户迁移失败时保留原值，下次启动再试。
+pub async fn migrate_plaintext_tokens<C>(db: &C) -> Result<usize, AppError>
+where
+    C: ConnectionTrait,
+{
+    let mut migrated = 0;
+    for user in im_user_repository::find_users_with_tokens(db).await? {
+        let is_plaintext =
+            |value: &Option<String>| matches!(value, Some(v) if !secret_store::is_reference(v));
+        if !is_plaintext(&user.token) && !is_plaintext(&user.refresh_token) {
+            continue;
+        }
+
+        let uid = user.id.clone();
+        let result = run_blocking(move || {
+            let migrate = |name: String, value: Option<String>| match non_empty(value) {
+                Some(value) if !secret_store::is_reference(&value) => {
+                    secret_store::save(&name, &value).map(Some)
+                }
+                value => Ok(value),
+            };
+            Ok((
+                migrate(access_token_name(&uid), user.token)?,
+                migrate(refresh_token_name(&uid), user.refresh_token)?,
+            ))
+        })
+        .await;
+
+        match result {
+            Ok((token_ref, refresh_ref)) => {
+                im_user_repository::save_token_refs(db, &user.id, token_ref, refresh_ref).await?;
+                migrated += 1;
+            }
+            Err(e) => warn!("Failed to migrate tokens of {}: {}", user.id, e),
+        }
+    }
+    Ok(migrated)
+}
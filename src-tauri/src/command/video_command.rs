@@ -0,0 +1,459 @@
+//! 发送前的视频压缩
+//!
+//! 手机拍摄的视频动辄数百 MB，超过服务端的上传限制。这里调用用户安装的 ffmpeg，
+//! 按长边与码率转码为 H.264/AAC 的 MP4，并根据 `/media/config` 返回的上传限制
+//! 计算码率上限。转码过程通过事件汇报进度，可随时取消。
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::command::image_command::{PROCESSED_FILE_MAX_AGE, remove_stale_files};
+use crate::command::media::get_max_upload_size;
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::utils::ffmpeg;
+use crate::utils::media_probe::{self, ContainerInfo};
+
+/// 转码进度事件
+const COMPRESS_PROGRESS_EVENT: &str = "video-compress-progress";
+
+/// 转码结果的保存目录，位于应用缓存目录下
+const COMPRESSED_VIDEO_DIR: &str = "compressed_videos";
+
+/// 失败时随错误返回的 ffmpeg 输出行数
+const STDERR_TAIL_LINES: usize = 20;
+
+/// 默认长边像素
+const DEFAULT_MAX_EDGE: u32 = 1280;
+
+/// 默认视频与音频码率（bit/s）
+const DEFAULT_VIDEO_BITRATE: u64 = 2_000_000;
+const DEFAULT_AUDIO_BITRATE: u64 = 128_000;
+
+/// 按上传限制计算码率时可接受的最低视频码率，再低画面已无法辨认
+const MIN_VIDEO_BITRATE: u64 = 150_000;
+
+/// 为容器开销与码率波动预留的比例
+const SIZE_SAFETY_FACTOR: f64 = 0.9;
+
+/// 按优先级排列的 H.264 编码器，libx264 之外是各平台的硬件编码器
+const H264_ENCODERS: [&str; 3] = ["libx264", "h264_videotoolbox", "h264_mf"];
+
+lazy_static::lazy_static! {
+    static ref ACTIVE_JOBS: Mutex<HashMap<String, Arc<CompressJob>>> = Mutex::new(HashMap::new());
+}
+
+/// 正在运行的转码任务
+struct CompressJob {
+    child: Mutex<Child>,
+    cancelled: AtomicBool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressVideoReq {
+    /// 本地视频路径
+    pub path: String,
+    /// 任务 id，用于进度事件与取消，默认使用文件路径
+    pub job_id: Option<String>,
+    /// 长边最大像素
+    pub max_edge: Option<u32>,
+    /// 视频码率（bit/s），超过上传限制允许的码率时自动降低
+    pub video_bitrate: Option<u64>,
+    /// 音频码率（bit/s）
+    pub audio_bitrate: Option<u64>,
+    /// 输出大小上限，默认使用服务端的上传限制
+    pub max_size: Option<u64>,
+}
+
+/// Matrix `m.video` 消息的 `info` 块
+#[derive(Serialize, Debug)]
+pub struct VideoInfo {
+    /// 时长（毫秒）
+    pub duration: Option<u64>,
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub mimetype: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressVideoResp {
+    /// 输出文件路径，未转码时为原文件
+    pub path: String,
+    pub info: VideoInfo,
+    /// 输出文件的容器信息
+    pub media: Option<ContainerInfo>,
+    /// 原文件大小
+    pub original_size: u64,
+    /// 是否转码
+    pub compressed: bool,
+}
+
+/// `video-compress-progress` 事件
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressVideoProgress {
+    pub job_id: String,
+    pub path: String,
+    /// 已转码的时长（秒）
+    pub time: f64,
+    /// 视频时长（秒）
+    pub duration: Option<f64>,
+    /// 进度 0-1，时长未知时为 `None`
+    pub progress: Option<f64>,
+}
+
+/// 转码参数
+struct TranscodeOptions {
+    max_edge: u32,
+    video_bitrate: u64,
+    audio_bitrate: u64,
+}
+
+/// 压缩待发送的视频
+///
+/// 视频已满足长边、码率与大小要求时不转码，直接返回原文件。
+#[tauri::command]
+pub async fn compress_video(
+    req: CompressVideoReq,
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<CompressVideoResp, AppError> {
+    let job_id = req.job_id.clone().unwrap_or_else(|| req.path.clone());
+    info!("Compressing video {}: {}", job_id, req.path);
+
+    let source = PathBuf::from(&req.path);
+    let original_size = fs::metadata(&source)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?
+        .len();
+    let source_info = probe(source.clone()).await;
+    let duration = source_info
+        .as_ref()
+        .and_then(|info| info.duration)
+        .filter(|&duration| duration > 0.0);
+
+    let max_size = match req.max_size {
+        Some(max_size) => Some(max_size),
+        None => {
+            let homeserver = state.homeserver().await.trim_end_matches('/').to_string();
+            get_max_upload_size(&state, &homeserver).await
+        }
+    };
+
+    let audio_bitrate = req.audio_bitrate.unwrap_or(DEFAULT_AUDIO_BITRATE);
+    let mut video_bitrate = req.video_bitrate.unwrap_or(DEFAULT_VIDEO_BITRATE);
+    if let (Some(max_size), Some(duration)) = (max_size, duration) {
+        let budget = (max_size as f64 * 8.0 * SIZE_SAFETY_FACTOR / duration) as u64;
+        let budget = budget.saturating_sub(audio_bitrate);
+        if budget < MIN_VIDEO_BITRATE {
+            return Err(AppError::MediaTooLarge(format!(
+                "Video of {duration:.0}s cannot fit into {max_size} bytes"
+            )));
+        }
+        video_bitrate = video_bitrate.min(budget);
+    }
+    let options = TranscodeOptions {
+        max_edge: req.max_edge.unwrap_or(DEFAULT_MAX_EDGE).max(2),
+        video_bitrate,
+        audio_bitrate,
+    };
+
+    if let Some(info) = &source_info
+        && is_acceptable(info, original_size, max_size, &options)
+    {
+        info!("Video {} already fits, skipping compression", job_id);
+        return Ok(CompressVideoResp {
+            path: req.path,
+            info: video_info(source_info.as_ref(), original_size),
+            media: source_info,
+            original_size,
+            compressed: false,
+        });
+    }
+
+    let output_dir = get_output_dir(&app_handle).await?;
+    let output_path = output_dir.join(format!("{}.mp4", uuid::Uuid::new_v4()));
+
+    let progress = CompressVideoProgress {
+        job_id: job_id.clone(),
+        path: req.path.clone(),
+        time: 0.0,
+        duration,
+        progress: duration.map(|_| 0.0),
+    };
+    let result = {
+        let app_handle = app_handle.clone();
+        let output_path = output_path.clone();
+        tokio::task::spawn_blocking(move || {
+            transcode(&source, &output_path, &options, progress, &app_handle)
+        })
+        .await
+        .map_err(|e| AppError::Unexpected(e.to_string()))?
+    };
+    if let Err(e) = result {
+        let _ = fs::remove_file(&output_path).await;
+        return Err(e);
+    }
+
+    let size = fs::metadata(&output_path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?
+        .len();
+    if let Some(max_size) = max_size
+        && size > max_size
+    {
+        let _ = fs::remove_file(&output_path).await;
+        return Err(AppError::MediaTooLarge(format!(
+            "Compressed video size {size} exceeds server limit {max_size}"
+        )));
+    }
+
+    let media = probe(output_path.clone()).await;
+    info!(
+        "Video {} compressed: {} -> {} bytes",
+        job_id, original_size, size
+    );
+
+    Ok(CompressVideoResp {
+        path: output_path.to_string_lossy().to_string(),
+        info: video_info(media.as_ref(), size),
+        media,
+        original_size,
+        compressed: true,
+    })
+}
+
+/// 取消转码，返回任务是否存在
+#[tauri::command]
+pub async fn cancel_video_compression(job_id: String) -> Result<bool, AppError> {
+    info!("Cancelling video compression: {}", job_id);
+
+    let Some(job) = ACTIVE_JOBS.lock().unwrap().get(&job_id).cloned() else {
+        return Ok(false);
+    };
+    job.cancelled.store(true, Ordering::SeqCst);
+    if let Err(e) = job.child.lock().unwrap().kill() {
+        warn!("Failed to stop ffmpeg for {}: {}", job_id, e);
+    }
+    Ok(true)
+}
+
+/// 获取输出目录，并顺带清理过期的转码结果
+async fn get_output_dir(app_handle: &AppHandle) -> Result<PathBuf, AppError> {
+    let output_dir = app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| AppError::Io(e.to_string()))?
+        .join(COMPRESSED_VIDEO_DIR);
+
+    fs::create_dir_all(&output_dir)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    if let Err(e) = remove_stale_files(&output_dir, PROCESSED_FILE_MAX_AGE).await {
+        warn!("Failed to clean up compressed videos: {}", e);
+    }
+
+    Ok(output_dir)
+}
+
+/// 读取容器信息，原生解析失败时尝试 ffprobe
+async fn probe(path: PathBuf) -> Option<ContainerInfo> {
+    tokio::task::spawn_blocking(move || match media_probe::probe_file(&path) {
+        Ok(Some(info)) => Some(info),
+        _ => ffmpeg::probe(&path)
+            .inspect_err(|e| warn!("Failed to probe {}: {}", path.display(), e))
+            .ok(),
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// 原视频是否已满足要求：H.264/AAC 的 MP4，且长边、码率与大小都不超出
+fn is_acceptable(
+    info: &ContainerInfo,
+    size: u64,
+    max_size: Option<u64>,
+    options: &TranscodeOptions,
+) -> bool {
+    let Some(video) = &info.video else {
+        return false;
+    };
+    let audio_ok = info.audio.as_ref().is_none_or(|audio| audio.codec == "aac");
+    let bitrate_ok = info
+        .bitrate
+        .is_some_and(|bitrate| bitrate <= options.video_bitrate + options.audio_bitrate);
+
+    info.container == "mp4"
+        && video.codec == "h264"
+        && audio_ok
+        && video.width.max(video.height) <= options.max_edge
+        && bitrate_ok
+        && max_size.is_none_or(|max_size| size <= max_size)
+}
+
+fn video_info(media: Option<&ContainerInfo>, size: u64) -> VideoInfo {
+    let video = media.and_then(|media| media.video.as_ref());
+    // 旋转 90°/270° 的视频显示时宽高互换
+    let (w, h) = match video {
+        Some(video) if video.rotation % 180 == 90 => (Some(video.height), Some(video.width)),
+        Some(video) => (Some(video.width), Some(video.height)),
+        None => (None, None),
+    };
+    VideoInfo {
+        duration: media
+            .and_then(|media| media.duration)
+            .map(|duration| (duration * 1000.0) as u64),
+        w,
+        h,
+        mimetype: "video/mp4".to_string(),
+        size,
+    }
+}
+
+/// 运行 ffmpeg 转码，任务注册在 `ACTIVE_JOBS` 中以便取消
+fn transcode(
+    source: &Path,
+    output: &Path,
+    options: &TranscodeOptions,
+    mut progress: CompressVideoProgress,
+    app_handle: &AppHandle,
+) -> Result<(), AppError> {
+    let ffmpeg_path = ffmpeg::ffmpeg_path()
+        .ok_or_else(|| AppError::Unexpected("ffmpeg not found".to_string()))?;
+    let encoder = H264_ENCODERS
+        .into_iter()
+        .find(|encoder| ffmpeg::has_encoder(encoder))
+        .ok_or_else(|| AppError::Unexpected("ffmpeg has no H.264 encoder".to_string()))?;
+
+    let max_edge = options.max_edge;
+    let scale = format!(
+        "scale=w='min({max_edge},iw)':h='min({max_edge},ih)':force_original_aspect_ratio=decrease:force_divisible_by=2"
+    );
+    let video_bitrate = options.video_bitrate.to_string();
+    let buffer_size = (options.video_bitrate * 2).to_string();
+    let audio_bitrate = options.audio_bitrate.to_string();
+
+    let mut command = ffmpeg::command(ffmpeg_path);
+    command.args(["-y", "-v", "error", "-i"]).arg(source).args([
+        "-vf",
+        scale.as_str(),
+        "-c:v",
+        encoder,
+    ]);
+    if encoder == "libx264" {
+        command.args(["-preset", "veryfast"]);
+    }
+    command
+        .args([
+            "-b:v",
+            video_bitrate.as_str(),
+            "-maxrate",
+            video_bitrate.as_str(),
+        ])
+        .args(["-bufsize", buffer_size.as_str(), "-pix_fmt", "yuv420p"])
+        .args(["-c:a", "aac", "-b:a", audio_bitrate.as_str()])
+        // moov 放到文件开头，接收方无需下载完整文件即可播放
+        .args(["-movflags", "+faststart", "-progress", "pipe:1", "-nostats"])
+        .arg(output)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| AppError::Io(format!("Failed to start ffmpeg: {e}")))?;
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let job = Arc::new(CompressJob {
+        child: Mutex::new(child),
+        cancelled: AtomicBool::new(false),
+    });
+    if let Some(previous) = ACTIVE_JOBS
+        .lock()
+        .unwrap()
+        .insert(progress.job_id.clone(), job.clone())
+    {
+        // 同一 id 的旧任务不再可取消，直接停止
+        previous.cancelled.store(true, Ordering::SeqCst);
+        let _ = previous.child.lock().unwrap().kill();
+    }
+
+    // stderr 需要与 stdout 同时读取，否则管道写满后 ffmpeg 会阻塞，只保留最后几行
+    let stderr_reader = stderr.map(|stderr| {
+        std::thread::spawn(move || {
+            let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+            for line in BufReader::new(stderr).split(b'\n').map_while(Result::ok) {
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(String::from_utf8_lossy(&line).trim_end().to_string());
+            }
+            Vec::from(tail).join("\n")
+        })
+    });
+
+    // `-progress` 每隔约 0.5 秒输出一组 key=value，以 progress= 结尾
+    if let Some(stdout) = stdout {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            // 旧版本的 out_time_ms 实际单位同样是微秒
+            let out_time = line
+                .strip_prefix("out_time_us=")
+                .or_else(|| line.strip_prefix("out_time_ms="));
+            if let Some(out_time) = out_time
+                && let Ok(micros) = out_time.trim().parse::<u64>()
+            {
+                progress.time = micros as f64 / 1_000_000.0;
+            } else if line.starts_with("progress=") {
+                progress.progress = progress
+                    .duration
+                    .map(|duration| (progress.time / duration).clamp(0.0, 1.0));
+                let _ = app_handle.emit(COMPRESS_PROGRESS_EVENT, &progress);
+            }
+        }
+    }
+
+    let error_output = stderr_reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    let status = job.child.lock().unwrap().wait();
+
+    {
+        let mut active_jobs = ACTIVE_JOBS.lock().unwrap();
+        if active_jobs
+            .get(&progress.job_id)
+            .is_some_and(|active| Arc::ptr_eq(active, &job))
+        {
+            active_jobs.remove(&progress.job_id);
+        }
+    }
+
+    if job.cancelled.load(Ordering::SeqCst) {
+        info!("Video compression cancelled: {}", progress.job_id);
+        return Err(AppError::Cancelled(progress.job_id));
+    }
+    let status = status.map_err(|e| AppError::Io(e.to_string()))?;
+    if !status.success() {
+        return Err(AppError::Unexpected(format!(
+            "ffmpeg exited with {status}: {}",
+            error_output.trim()
+        )));
+    }
+
+    progress.time = progress.duration.unwrap_or(progress.time);
+    progress.progress = Some(1.0);
+    let _ = app_handle.emit(COMPRESS_PROGRESS_EVENT, &progress);
+    Ok(())
+}
@@ -0,0 +1,445 @@
+//! 语音消息编码
+//!
+//! 把录音转码为 Ogg/Opus，并生成 MSC3245/MSC3246 语音消息所需的时长与波形，
+//! 其他客户端据此显示语音条而不是普通文件。录音使用 rodio 解码，
+//! 重采样为 48kHz 单声道后逐帧编码。
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use audiopus::coder::Encoder;
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use rodio::Source;
+use rodio::source::UniformSourceIterator;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::command::image_command::{PROCESSED_FILE_MAX_AGE, remove_stale_files};
+use crate::error::AppError;
+
+/// 编码结果的保存目录，位于应用缓存目录下
+const VOICE_MESSAGE_DIR: &str = "voice_messages";
+
+/// Opus 编码采样率
+const OPUS_SAMPLE_RATE: u32 = 48_000;
+
+/// 每帧 20ms
+const OPUS_FRAME_SIZE: usize = 960;
+
+/// 单个 Opus 数据包的最大长度
+const OPUS_MAX_PACKET_SIZE: usize = 4000;
+
+/// 默认码率，语音 24kbit/s 已足够清晰
+const DEFAULT_VOICE_BITRATE: u32 = 24_000;
+
+/// 波形的采样点数，MSC3246 建议 30-120 个
+const WAVEFORM_LENGTH: usize = 100;
+
+/// 波形的最大值，MSC3246 规定取值范围为 0-1024
+const WAVEFORM_MAX: f32 = 1024.0;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeVoiceReq {
+    /// 录音文件路径
+    pub path: String,
+    /// Opus 码率（bit/s）
+    pub bitrate: Option<u32>,
+}
+
+/// Matrix `m.audio` 消息的 `info` 块
+#[derive(Serialize, Debug)]
+pub struct AudioInfo {
+    /// 时长（毫秒）
+    pub duration: u64,
+    pub mimetype: String,
+    pub size: u64,
+}
+
+/// MSC1767 扩展事件中的音频内容
+#[derive(Serialize, Debug)]
+pub struct VoiceAudioContent {
+    /// 时长（毫秒）
+    pub duration: u64,
+    /// 归一化后的波形，取值范围 0-1024
+    pub waveform: Vec<u16>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeVoiceResp {
+    /// 编码后的文件路径，用于上传
+    pub path: String,
+    pub info: AudioInfo,
+    /// 消息内容的 `org.matrix.msc1767.audio` 字段；
+    /// 发送时还需附带空对象 `org.matrix.msc3245.voice`
+    #[serde(rename = "org.matrix.msc1767.audio")]
+    pub audio: VoiceAudioContent,
+}
+
+/// 编码结果
+struct EncodedVoice {
+    data: Vec<u8>,
+    /// 时长（毫秒）
+    duration: u64,
+    waveform: Vec<u16>,
+}
+
+/// 把录音转码为语音消息
+#[tauri::command]
+pub async fn encode_voice_message(
+    req: EncodeVoiceReq,
+    app_handle: AppHandle,
+) -> Result<EncodeVoiceResp, AppError> {
+    info!("Encoding voice message: {}", req.path);
+
+    let source = PathBuf::from(&req.path);
+    let bitrate = req.bitrate.unwrap_or(DEFAULT_VOICE_BITRATE);
+    let encoded = tokio::task::spawn_blocking(move || encode_voice(&source, bitrate))
+        .await
+        .map_err(|e| AppError::Unexpected(e.to_string()))??;
+
+    let output_dir = get_output_dir(&app_handle).await?;
+    let output_path = output_dir.join(format!("{}.ogg", uuid::Uuid::new_v4()));
+    fs::write(&output_path, &encoded.data)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    info!(
+        "Voice message encoded: {} ms, {} bytes",
+        encoded.duration,
+        encoded.data.len()
+    );
+
+    Ok(EncodeVoiceResp {
+        path: output_path.to_string_lossy().to_string(),
+        info: AudioInfo {
+            duration: encoded.duration,
+            mimetype: "audio/ogg".to_string(),
+            size: encoded.data.len() as u64,
+        },
+        audio: VoiceAudioContent {
+            duration: encoded.duration,
+            waveform: encoded.waveform,
+        },
+    })
+}
+
+/// 获取输出目录，并顺带清理过期的编码结果
+async fn get_output_dir(app_handle: &AppHandle) -> Result<PathBuf, AppError> {
+    let output_dir = app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| AppError::Io(e.to_string()))?
+        .join(VOICE_MESSAGE_DIR);
+
+    fs::create_dir_all(&output_dir)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    if let Err(e) = remove_stale_files(&output_dir, PROCESSED_FILE_MAX_AGE).await {
+        warn!("Failed to clean up voice messages: {}", e);
+    }
+
+    Ok(output_dir)
+}
+
+fn opus_error(e: audiopus::Error) -> AppError {
+    AppError::Unexpected(format!("Opus encoding failed: {e}"))
+}
+
+fn encode_voice(path: &Path, bitrate: u32) -> Result<EncodedVoice, AppError> {
+    let file = File::open(path).map_err(|e| AppError::Io(e.to_string()))?;
+    let decoder = rodio::Decoder::new(BufReader::new(file))
+        .map_err(|e| AppError::Request(format!("Unsupported audio file: {e}")))?;
+    let channels = decoder.channels().max(1);
+    let input_sample_rate = decoder.sample_rate();
+    let mut samples = UniformSourceIterator::new(decoder, channels, OPUS_SAMPLE_RATE);
+
+    let mut encoder =
+        Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Voip).map_err(opus_error)?;
+    encoder
+        .set_bitrate(Bitrate::BitsPerSecond(bitrate.clamp(6_000, 128_000) as i32))
+        .map_err(opus_error)?;
+    // 解码器需要丢弃的编码延迟，写入 OpusHead
+    let pre_skip = encoder.lookahead().map_err(opus_error)? as u64;
+
+    let serial = rand::random::<u32>();
+    let mut writer = PacketWriter::new(Vec::new());
+    let write_error = |e: std::io::Error| AppError::Io(e.to_string());
+    writer
+        .write_packet(
+            opus_head(pre_skip as u16, input_sample_rate).into_boxed_slice(),
+            serial,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )
+        .map_err(write_error)?;
+    writer
+        .write_packet(
+            opus_tags().into_boxed_slice(),
+            serial,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )
+        .map_err(write_error)?;
+
+    let mut frame = Vec::with_capacity(OPUS_FRAME_SIZE);
+    let mut packet = [0u8; OPUS_MAX_PACKET_SIZE];
+    // 每帧的均方根音量，用于生成波形
+    let mut levels = Vec::new();
+    let mut total_samples = 0u64;
+    let mut encoded_samples = 0u64;
+    let mut input_done = false;
+    // 数据包延后一帧写入，以便给最后一个数据包标记流结束
+    let mut pending: Option<Box<[u8]>> = None;
+
+    loop {
+        frame.clear();
+        while !input_done && frame.len() < OPUS_FRAME_SIZE {
+            // 多声道取平均混为单声道
+            let mut sum = 0.0;
+            let mut count = 0;
+            for sample in samples.by_ref().take(channels as usize) {
+                sum += sample;
+                count += 1;
+            }
+            if count == 0 {
+                input_done = true;
+            } else {
+                frame.push(sum / count as f32);
+            }
+        }
+
+        if !frame.is_empty() {
+            total_samples += frame.len() as u64;
+            levels.push((frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt());
+        } else if encoded_samples >= pre_skip + total_samples {
+            break;
+        }
+        // 输入结束后继续编码静音，直到编码器延迟的部分全部输出
+        frame.resize(OPUS_FRAME_SIZE, 0.0);
+
+        let len = encoder
+            .encode_float(&frame, &mut packet)
+            .map_err(opus_error)?;
+        if let Some(previous) = pending.replace(Box::from(&packet[..len])) {
+            writer
+                .write_packet(
+                    previous,
+                    serial,
+                    PacketWriteEndInfo::NormalPacket,
+                    encoded_samples,
+                )
+                .map_err(write_error)?;
+        }
+        encoded_samples += OPUS_FRAME_SIZE as u64;
+    }
+
+    let Some(last) = pending.filter(|_| total_samples > 0) else {
+        return Err(AppError::Request("Recording is empty".to_string()));
+    };
+    // 最后一页的 granule position 决定播放器截掉的补零部分
+    writer
+        .write_packet(
+            last,
+            serial,
+            PacketWriteEndInfo::EndStream,
+            pre_skip + total_samples,
+        )
+        .map_err(write_error)?;
+
+    Ok(EncodedVoice {
+        data: writer.into_inner(),
+        duration: total_samples * 1000 / OPUS_SAMPLE_RATE as u64,
+        waveform: compute_waveform(&levels),
+    })
+}
+
+/// Opus 识别头，见 RFC 7845
+fn opus_head(pre_skip: u16, input_sample_rate: u32) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    // 版本、声道数
+    head.extend_from_slice(&[1, 1]);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&input_sample_rate.to_le_bytes());
+    // 输出增益、声道映射
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+/// Opus 注释头
+fn opus_tags() -> Vec<u8> {
+    let vendor = concat!("HuLa ", env!("CARGO_PKG_VERSION"));
+    let mut tags = Vec::with_capacity(16 + vendor.len());
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor.as_bytes());
+    // 没有用户注释
+    tags.extend_from_slice(&0u32.to_le_bytes());
+    tags
+}
+
+/// 把每帧的音量合并为固定长度的波形，并按最大值归一到 0-1024
+fn compute_waveform(levels: &[f32]) -> Vec<u16> {
+    if levels.is_empty() {
+        return Vec::new();
+    }
+
+    let length = WAVEFORM_LENGTH.min(levels.len());
+    let buckets: Vec<f32> = (0..length)
+        .map(|i| {
+            let start = i * levels.len() / length;
+            let end = ((i + 1) * levels.len() / length).max(start + 1);
+            let bucket = &levels[start..end];
+            bucket.iter().sum::<f32>() / bucket.len() as f32
+        })
+        .collect();
+
+    let max = buckets.iter().copied().fold(0.0f32, f32::max);
+    if max <= f32::EPSILON {
+        return vec![0; length];
+    }
+    buckets
+        .iter()
+        .map(|level| (level / max * WAVEFORM_MAX).round() as u16)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use ogg::reading::PacketReader;
+
+    /// 写入单声道 16 位 PCM 的 WAV 文件
+    fn write_wav(name: &str, sample_rate: u32, samples: &[i16]) -> PathBuf {
+        let data_len = (samples.len() * 2) as u32;
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM、单声道
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let path = std::env::temp_dir().join(format!("voice-test-{}-{name}", std::process::id()));
+        std::fs::write(&path, wav).unwrap();
+        path
+    }
+
+    #[test]
+    fn writes_opus_head() {
+        let head = opus_head(312, 44_100);
+        assert_eq!(head.len(), 19);
+        assert_eq!(&head[..8], b"OpusHead");
+        assert_eq!(head[8..10], [1, 1]);
+        assert_eq!(u16::from_le_bytes([head[10], head[11]]), 312);
+        assert_eq!(u32::from_le_bytes(head[12..16].try_into().unwrap()), 44_100);
+        assert_eq!(head[16..], [0, 0, 0]);
+    }
+
+    #[test]
+    fn writes_opus_tags() {
+        let tags = opus_tags();
+        assert_eq!(&tags[..8], b"OpusTags");
+        let vendor_len = u32::from_le_bytes(tags[8..12].try_into().unwrap()) as usize;
+        assert!(tags[12..12 + vendor_len].starts_with(b"HuLa "));
+        assert_eq!(tags[12 + vendor_len..], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn granule_positions_cover_input_and_pre_skip() {
+        // 0.5 秒 440Hz 正弦波，采样率与 Opus 一致，避免重采样改变样本数
+        let total_samples = 24_000u64;
+        let samples: Vec<i16> = (0..total_samples)
+            .map(|i| {
+                let t = i as f32 / OPUS_SAMPLE_RATE as f32;
+                ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0) as i16
+            })
+            .collect();
+        let path = write_wav("sine.wav", OPUS_SAMPLE_RATE, &samples);
+        let encoded = encode_voice(&path, DEFAULT_VOICE_BITRATE);
+        std::fs::remove_file(&path).unwrap();
+        let encoded = encoded.unwrap();
+
+        assert_eq!(encoded.duration, 500);
+        assert_eq!(encoded.waveform.len(), 25);
+        assert_eq!(encoded.waveform.iter().max(), Some(&1024));
+
+        let mut reader = PacketReader::new(Cursor::new(encoded.data));
+        let head = reader.read_packet_expected().unwrap();
+        assert_eq!(&head.data[..8], b"OpusHead");
+        assert_eq!(head.absgp_page(), 0);
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as u64;
+        assert_eq!(
+            u32::from_le_bytes(head.data[12..16].try_into().unwrap()),
+            OPUS_SAMPLE_RATE
+        );
+        let tags = reader.read_packet_expected().unwrap();
+        assert_eq!(&tags.data[..8], b"OpusTags");
+        assert_eq!(tags.absgp_page(), 0);
+
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet().unwrap() {
+            packets.push(packet);
+        }
+        // 输入之后补足编码延迟，每个数据包 20ms
+        let expected_granule = pre_skip + total_samples;
+        assert_eq!(
+            packets.len() as u64,
+            expected_granule.div_ceil(OPUS_FRAME_SIZE as u64)
+        );
+
+        let last = packets.last().unwrap();
+        assert!(last.last_in_stream());
+        assert_eq!(last.absgp_page(), expected_granule);
+        // 页的 granule position 单调递增，且不超过已编码的样本数
+        let mut previous = 0;
+        for (index, packet) in packets.iter().enumerate() {
+            assert!(packet.absgp_page() >= previous);
+            if packet.last_in_page() {
+                assert!(packet.absgp_page() <= (index as u64 + 1) * OPUS_FRAME_SIZE as u64);
+            }
+            previous = packet.absgp_page();
+        }
+    }
+
+    #[test]
+    fn rejects_empty_recording() {
+        let path = write_wav("empty.wav", OPUS_SAMPLE_RATE, &[]);
+        let result = encode_voice(&path, DEFAULT_VOICE_BITRATE);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn normalizes_waveform() {
+        assert!(compute_waveform(&[]).is_empty());
+        assert_eq!(compute_waveform(&[0.0; 3]), [0, 0, 0]);
+        assert_eq!(compute_waveform(&[0.1, 0.2, 0.4]), [256, 512, 1024]);
+
+        // 超过波形长度时按区间取平均
+        let levels: Vec<f32> = (0..WAVEFORM_LENGTH * 2).map(|i| (i / 2) as f32).collect();
+        let waveform = compute_waveform(&levels);
+        assert_eq!(waveform.len(), WAVEFORM_LENGTH);
+        assert_eq!(waveform[0], 0);
+        assert_eq!(waveform[WAVEFORM_LENGTH - 1], 1024);
+    }
+}
@@ -1,6 +1,8 @@
 use mime_guess::from_path;
 use serde::Serialize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use crate::utils::media_probe::{self, ContainerInfo};
 
 #[derive(Serialize)]
 pub struct FileMeta {
@@ -9,6 +11,8 @@ pub struct FileMeta {
     file_type: String,
     mime_type: String,
     exists: bool,
+    /// 音视频文件的容器信息
+    media: Option<ContainerInfo>,
 }
 
 #[tauri::command]
@@ -35,6 +39,13 @@ pub async fn get_files_meta(files_path: Vec<String>) -> Result<Vec<FileMeta>, St
 
         let exists = if is_url { false } else { path_buf.exists() };
 
+        let media =
+            if exists && (mime_type.starts_with("video/") || mime_type.starts_with("audio/")) {
+                probe(path_buf.clone()).await.ok()
+            } else {
+                None
+            };
+
         let stored_path = if is_url {
             original_path.clone()
         } else {
@@ -47,8 +58,29 @@ pub async fn get_files_meta(files_path: Vec<String>) -> Result<Vec<FileMeta>, St
             file_type,
             mime_type,
             exists,
+            media,
         });
     }
 
     Ok(files_meta)
 }
+
+async fn probe(path: PathBuf) -> Result<ContainerInfo, String> {
+    tokio::task::spawn_blocking(move || probe_file(&path))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn probe_file(path: &Path) -> Result<ContainerInfo, String> {
+    media_probe::probe_file(path)
+        .map_err(|e| format!("读取媒体文件失败: {e}"))?
+        .ok_or_else(|| "不支持的媒体格式".to_string())
+}
+
+/// 解析音视频文件的时长、分辨率、旋转角度、编码与码率
+///
+/// 支持 MP4/MOV、WebM/Matroska、Ogg 与 MP3，直接解析容器，不依赖外部程序。
+#[tauri::command]
+pub async fn probe_media(path: String) -> Result<ContainerInfo, String> {
+    probe(PathBuf::from(path)).await
+}
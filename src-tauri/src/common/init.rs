@@ -27,9 +27,17 @@ fn build_log_plugin<R: Runtime>() -> TauriPlugin<R> {
         .targets([
             Target::new(TargetKind::Stdout),
             Target::new(TargetKind::Webview),
+            // 公共日志只记录未登录期间的内容，登录后的记录写入账号日志
             Target::new(TargetKind::LogDir {
                 file_name: Some("logs".to_string()),
-            }),
+            })
+            .filter(|_| !crate::utils::account_storage::has_account_log()),
+            // 当前账号的日志，切换账号时切换文件
+            Target::new(TargetKind::Dispatch(
+                tauri_plugin_log::fern::Dispatch::new().chain(
+                    tauri_plugin_log::fern::Output::call(crate::utils::account_storage::write),
+                ),
+            )),
         ])
         .with_colors(ColoredLevelConfig {
             error: Color::Red,
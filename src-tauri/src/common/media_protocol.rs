@@ -0,0 +1,292 @@
+//! `hula-media://` 自定义协议
+//!
+//! 前端可以直接把 `hula-media://{serverName}/{mediaId}` 作为 `<img>`、`<video>` 的地址，
+//! 命中媒体缓存时直接读取本地文件，未命中时通过下载调度器下载到缓存。Windows 与 Android 上
+//! webview 使用 `http://hula-media.localhost/{serverName}/{mediaId}` 的形式。
+//!
+//! 查询参数：
+//! - `width`、`height`、`method`：请求服务端缩略图，`method` 为 `crop` 或 `scale`
+//! - `roomId`：记录到缓存索引中，便于按房间清理
+
+use std::path::Path;
+
+use http::header::{
+    ACCEPT_RANGES, ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, RANGE, RETRY_AFTER,
+};
+use http::{Method, Request, Response, StatusCode, Uri};
+use tauri::{AppHandle, Manager, UriSchemeContext, UriSchemeResponder, Wry};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use tracing::warn;
+
+use crate::AppData;
+use crate::command::media::{DownloadMediaOptions, ThumbnailOptions};
+use crate::command::media_scheduler::{DownloadPriority, schedule_download};
+use crate::error::AppError;
+use crate::state::AppState;
+
+/// 协议名称
+pub const MEDIA_SCHEME: &str = "hula-media";
+
+/// 单次 Range 响应的最大字节数，`<video>` 会按需继续请求后续区间
+const MAX_RANGE_LENGTH: u64 = 4 * 1024 * 1024;
+
+/// 请求的字节区间
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    /// 闭区间 `[start, end]`
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+}
+
+/// 处理 `hula-media://` 请求，在异步任务中读取或下载媒体后响应
+pub fn handle_media_request(
+    ctx: UriSchemeContext<'_, Wry>,
+    request: Request<Vec<u8>>,
+    responder: UriSchemeResponder,
+) {
+    let app_handle = ctx.app_handle().clone();
+    tauri::async_runtime::spawn(async move {
+        let response = match serve_media(&app_handle, &request).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to serve {}: {}", request.uri(), e);
+                error_response(&e)
+            }
+        };
+        responder.respond(response);
+    });
+}
+
+async fn serve_media(
+    app_handle: &AppHandle,
+    request: &Request<Vec<u8>>,
+) -> Result<Response<Vec<u8>>, AppError> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    // 应用数据初始化完成前无法访问缓存索引
+    if app_handle.try_state::<AppState>().is_none() || app_handle.try_state::<AppData>().is_none() {
+        return Ok(unavailable_response());
+    }
+
+    let options = parse_media_request(request.uri())?;
+
+    // 页面正在显示的媒体，优先于预加载；同一媒体的并发请求共用一次下载
+    let media = schedule_download(app_handle, options, DownloadPriority::High, None).await?;
+
+    let path = Path::new(&media.local_path);
+    let size = media.size;
+    let range = request
+        .headers()
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_range(value, size))
+        .or_else(|| first_chunk(size, &media.mime_type));
+
+    let builder = Response::builder()
+        .header(CONTENT_TYPE, &media.mime_type)
+        .header(ACCEPT_RANGES, "bytes")
+        .header(CACHE_CONTROL, "max-age=31536000, immutable")
+        .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+    let head = request.method() == Method::HEAD;
+
+    let response = match range {
+        Some(ByteRange::Unsatisfiable) => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(CONTENT_RANGE, format!("bytes */{size}"))
+            .body(Vec::new()),
+        Some(ByteRange::Satisfiable(start, end)) => {
+            let length = end - start + 1;
+            let body = if head {
+                Vec::new()
+            } else {
+                read_range(path, start, length).await?
+            };
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {start}-{end}/{size}"))
+                .header(CONTENT_LENGTH, length)
+                .body(body)
+        }
+        None => {
+            let body = if head {
+                Vec::new()
+            } else {
+                fs::read(path)
+                    .await
+                    .map_err(|e| AppError::Io(e.to_string()))?
+            };
+            builder
+                .status(StatusCode::OK)
+                .header(CONTENT_LENGTH, size)
+                .body(body)
+        }
+    };
+
+    response.map_err(|e| AppError::Unexpected(e.to_string()))
+}
+
+/// 从请求地址解析 mxc URI 与缩略图参数
+fn parse_media_request(uri: &Uri) -> Result<DownloadMediaOptions, AppError> {
+    let mut segments = uri.path().split('/').filter(|segment| !segment.is_empty());
+
+    // `hula-media://{serverName}/{mediaId}` 或 `http://hula-media.localhost/{serverName}/{mediaId}`
+    let server_name = match uri.host() {
+        Some(host) if host != "localhost" && host != format!("{MEDIA_SCHEME}.localhost") => {
+            match uri.port_u16() {
+                Some(port) => Some(format!("{host}:{port}")),
+                None => Some(host.to_string()),
+            }
+        }
+        _ => segments.next().map(str::to_string),
+    };
+    let media_id = segments.next();
+
+    let (Some(server_name), Some(media_id), None) = (server_name, media_id, segments.next()) else {
+        return Err(AppError::InvalidUri(uri.to_string()));
+    };
+
+    let mut width = None;
+    let mut height = None;
+    let mut method = None;
+    let mut room_id = None;
+    for (key, value) in url::form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes()) {
+        match key.as_ref() {
+            "width" => width = value.parse::<u32>().ok(),
+            "height" => height = value.parse::<u32>().ok(),
+            "method" => method = Some(value.into_owned()),
+            "roomId" => room_id = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+
+    let thumbnail = match (width, height) {
+        (Some(width), Some(height)) => Some(ThumbnailOptions {
+            width,
+            height,
+            method,
+        }),
+        _ => None,
+    };
+
+    Ok(DownloadMediaOptions {
+        mxc_uri: format!("mxc://{server_name}/{media_id}"),
+        force: false,
+        max_size: None,
+        thumbnail,
+        download_id: None,
+        room_id,
+    })
+}
+
+/// 解析 `Range` 请求头
+///
+/// 只支持单个 `bytes` 区间，多区间或格式错误时返回 `None`，按完整内容响应。
+/// 返回的区间长度不超过 [`MAX_RANGE_LENGTH`]。
+fn parse_range(value: &str, size: u64) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let (start, end) = if start.is_empty() {
+        // 后缀区间 `bytes=-N`：最后 N 个字节
+        let suffix = end.parse::<u64>().ok()?;
+        if suffix == 0 || size == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        (size.saturating_sub(suffix), size - 1)
+    } else {
+        let start = start.parse::<u64>().ok()?;
+        if start >= size {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        let end = if end.is_empty() {
+            size - 1
+        } else {
+            end.parse::<u64>().ok()?.min(size - 1)
+        };
+        if end < start {
+            return None;
+        }
+        (start, end)
+    };
+
+    Some(ByteRange::Satisfiable(
+        start,
+        end.min(start + MAX_RANGE_LENGTH - 1),
+    ))
+}
+
+/// 没有 Range 头的大音视频只返回第一段，避免把整个文件读入内存
+///
+/// `<video>`、`<audio>` 收到 206 后会按 `Accept-Ranges` 继续请求后续区间；
+/// 其他类型（图片、`fetch()` 或下载的文件）不一定会继续请求，仍以 200 返回完整内容。
+fn first_chunk(size: u64, mime_type: &str) -> Option<ByteRange> {
+    let streamable = mime_type.starts_with("video/") || mime_type.starts_with("audio/");
+    (size > MAX_RANGE_LENGTH && streamable)
+        .then_some(ByteRange::Satisfiable(0, MAX_RANGE_LENGTH - 1))
+}
+
+async fn read_range(path: &Path, start: u64, length: u64) -> Result<Vec<u8>, AppError> {
+    let mut file = fs::File::open(path)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    let mut body = Vec::with_capacity(length as usize);
+    file.take(length)
+        .read_to_end(&mut body)
+        .await
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    Ok(body)
+}
+
+fn empty_response(status: StatusCode) -> Response<Vec<u8>> {
+    let mut response = Response::new(Vec::new());
+    *response.status_mut() = status;
+    response
+}
+
+fn unavailable_response() -> Response<Vec<u8>> {
+    let mut response = empty_response(StatusCode::SERVICE_UNAVAILABLE);
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, http::HeaderValue::from_static("1"));
+    response
+}
+
+fn error_response(error: &AppError) -> Response<Vec<u8>> {
+    let status = match error {
+        AppError::InvalidUri(_) => StatusCode::BAD_REQUEST,
+        AppError::TokenExpired => StatusCode::UNAUTHORIZED,
+        AppError::MediaNotFound(_) => StatusCode::NOT_FOUND,
+        AppError::FileTooLarge(_) | AppError::MediaTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        AppError::Io(_) | AppError::Database(_) | AppError::Unexpected(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        _ => StatusCode::BAD_GATEWAY,
+    };
+
+    let mut response = Response::new(error.to_string().into_bytes());
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        http::HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_ORIGIN,
+        http::HeaderValue::from_static("*"),
+    );
+    response
+}
@@ -1,2 +1,3 @@
 pub mod files_meta;
 pub mod init;
+pub mod media_protocol;
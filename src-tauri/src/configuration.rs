@@ -1,4 +1,5 @@
 use crate::error::CommonError;
+use crate::utils::db_cipher;
 use sea_orm::{ConnectOptions, Database, DatabaseConnection};
 use std::path::PathBuf;
 use std::time::Duration;
@@ -13,12 +14,23 @@ pub struct Settings {
     pub youdao: Youdao,
     pub tencent: Tencent,
     pub ice_server: IceServer,
+    #[serde(default)]
+    pub media_cache: MediaCacheSettings,
+    #[serde(default)]
+    pub image_upload: ImageUploadSettings,
 }
 
 // 数据库配置设置
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
 pub struct DatabaseSettings {
     pub sqlite_file: String,
+    /// 是否使用 SQLCipher 加密数据库，密钥保存在系统钥匙串中
+    #[serde(default = "default_encrypted")]
+    pub encrypted: bool,
+}
+
+fn default_encrypted() -> bool {
+    true
 }
 
 // 后端服务配置设置
@@ -48,6 +60,39 @@ pub struct Tencent {
     pub map_key: String,
 }
 
+// 媒体缓存配置
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct MediaCacheSettings {
+    /// 每个账号的缓存总大小上限（字节），超出后按最近最少使用淘汰
+    pub max_size_bytes: u64,
+}
+
+impl Default for MediaCacheSettings {
+    fn default() -> Self {
+        Self {
+            max_size_bytes: 2 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+// 发送图片时的压缩配置
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct ImageUploadSettings {
+    /// 长边最大像素，超出时等比缩小
+    pub max_edge: u32,
+    /// JPEG 编码质量（1-100）
+    pub quality: u8,
+}
+
+impl Default for ImageUploadSettings {
+    fn default() -> Self {
+        Self {
+            max_edge: 2560,
+            quality: 82,
+        }
+    }
+}
+
 // 应用程序运行环境枚举
 #[derive(Debug)]
 pub enum Environment {
@@ -56,10 +101,40 @@ pub enum Environment {
 }
 
 impl DatabaseSettings {
+    /// 数据库文件路径
+    /// 桌面端开发环境使用项目根目录，其他情况使用应用数据目录
+    pub fn database_path(&self, app_handle: &AppHandle) -> Result<PathBuf, CommonError> {
+        if cfg!(debug_assertions) && cfg!(desktop) {
+            // 桌面端开发环境：使用项目根目录
+            let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            path.push("db.sqlite");
+            return Ok(path);
+        }
+
+        // SQLite 无法连接 asset://localhost/ 这样的虚拟协议，必须使用真实文件系统路径
+        match app_handle.path().app_data_dir() {
+            Ok(app_data_dir) => {
+                if let Err(create_err) = std::fs::create_dir_all(&app_data_dir) {
+                    tracing::warn!("Failed to create app_data_dir: {}", create_err);
+                }
+                let db_path = app_data_dir.join("db.sqlite");
+                info!("Mobile: Using app_data_dir database path: {:?}", db_path);
+                Ok(db_path)
+            }
+            Err(e) => {
+                let error_msg = format!("Mobile: Failed to get app_data_dir: {e}");
+                tracing::error!("{}", error_msg);
+                Err(CommonError::RequestError(error_msg))
+            }
+        }
+    }
+
     /// 创建数据库连接
     /// 根据不同的运行环境（桌面开发、移动端、桌面生产）选择合适的数据库路径
     /// 并配置数据库连接选项，返回数据库连接实例
     ///
+    /// 开启加密时先取得数据库密钥，旧版本的明文数据库会在此时加密。
+    ///
     /// # 参数
     /// * `app_handle` - Tauri应用句柄，用于获取应用路径
     ///
@@ -70,30 +145,7 @@ impl DatabaseSettings {
         &self,
         app_handle: &AppHandle,
     ) -> Result<DatabaseConnection, CommonError> {
-        // 数据库路径配置：
-        let db_path = if cfg!(debug_assertions) && cfg!(desktop) {
-            // 桌面端开发环境：使用项目根目录
-            let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-            path.push("db.sqlite");
-            path
-        } else {
-            // SQLite 无法连接 asset://localhost/ 这样的虚拟协议，必须使用真实文件系统路径
-            match app_handle.path().app_data_dir() {
-                Ok(app_data_dir) => {
-                    if let Err(create_err) = std::fs::create_dir_all(&app_data_dir) {
-                        tracing::warn!("Failed to create app_data_dir: {}", create_err);
-                    }
-                    let db_path = app_data_dir.join("db.sqlite");
-                    info!("Mobile: Using app_data_dir database path: {:?}", db_path);
-                    db_path
-                }
-                Err(e) => {
-                    let error_msg = format!("Mobile: Failed to get app_data_dir: {e}");
-                    tracing::error!("{}", error_msg);
-                    return Err(CommonError::RequestError(error_msg));
-                }
-            }
-        };
+        let db_path = self.database_path(app_handle)?;
         info!("Database path: {:?}", db_path);
         let db_url = format!("sqlite:{}?mode=rwc", db_path.display());
 
@@ -109,6 +161,16 @@ impl DatabaseSettings {
             .sqlx_logging(cfg!(debug_assertions))
             .sqlx_logging_level(tracing::log::LevelFilter::Info);
 
+        if self.encrypted {
+            let key = db_cipher::prepare(&db_path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to prepare database key: {e}"))?;
+            // SQLCipher 要求 key 是连接后执行的第一条语句，sqlx 会把它排在其他 PRAGMA 之前
+            opt.map_sqlx_sqlite_opts(move |options| {
+                options.pragma("key", db_cipher::key_literal(&key))
+            });
+        }
+
         let db: DatabaseConnection = Database::connect(opt)
             .await
             .map_err(|e| anyhow::anyhow!("Database connection failed: {e}"))?;
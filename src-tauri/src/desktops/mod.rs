@@ -2,6 +2,7 @@ pub mod app_event;
 pub mod common_cmd;
 pub mod directory_scanner;
 pub mod init;
+pub mod screen_capture;
 pub mod tray;
 pub mod video_thumbnail;
 pub mod window_payload;
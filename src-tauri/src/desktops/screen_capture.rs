@@ -0,0 +1,516 @@
+//! 多显示器截图
+//!
+//! 显示器坐标使用系统的虚拟桌面坐标：macOS 为点（point），Windows 与 Linux 为物理像素。
+//! 截图区域可以跨越多个显示器，每个显示器分别截取相交部分后按最高像素密度拼接，
+//! 因此 Hi-DPI 与普通显示器混用时截图不会模糊或错位。
+//!
+//! 另提供屏幕共享的来源列表（屏幕与顶层窗口），供前端在开始采集前选择。
+//! screenshots 不支持窗口，窗口的枚举与缩略图使用 xcap。
+
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use screenshots::Screen;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::fs;
+use tracing::warn;
+
+use crate::command::image_command::remove_stale_files;
+
+/// 截图文件的保存目录，位于应用缓存目录下
+const SCREENSHOT_DIR: &str = "screenshots";
+
+/// 截图文件的保留时间，截图窗口关闭后即可清理
+const SCREENSHOT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 默认 JPEG 编码质量
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// 来源缩略图的默认最大边长
+const SOURCE_THUMBNAIL_SIZE: u32 = 320;
+
+/// 来源缩略图的 JPEG 编码质量
+const SOURCE_THUMBNAIL_QUALITY: u8 = 70;
+
+/// 显示器信息
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorInfo {
+    pub id: u32,
+    /// 在虚拟桌面中的位置与大小
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// 系统缩放比例
+    pub scale_factor: f32,
+    /// 截图的实际像素大小
+    pub physical_width: u32,
+    pub physical_height: u32,
+    /// 旋转角度
+    pub rotation: f32,
+    pub is_primary: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureRegionReq {
+    /// 指定显示器时坐标相对于该显示器，否则为虚拟桌面坐标
+    pub monitor_id: Option<u32>,
+    /// 截图区域，不指定时截取整个显示器或整个虚拟桌面
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// 输出格式：png 或 jpeg，默认 png
+    pub format: Option<String>,
+    /// JPEG 编码质量（1-100）
+    pub quality: Option<u8>,
+    /// 写入临时文件并返回路径，否则返回 data URL
+    #[serde(default)]
+    pub save_to_file: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureRegionResp {
+    /// 截图文件路径，`save_to_file` 为 true 时返回
+    pub path: Option<String>,
+    /// data URL 形式的截图，`save_to_file` 为 false 时返回
+    pub data: Option<String>,
+    pub mimetype: String,
+    /// 截图的像素大小
+    pub width: u32,
+    pub height: u32,
+    /// 截图像素与虚拟桌面坐标的比例
+    pub scale_factor: f32,
+}
+
+/// 虚拟桌面中的矩形
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn right(&self) -> i32 {
+        self.x + self.width as i32
+    }
+
+    fn bottom(&self) -> i32 {
+        self.y + self.height as i32
+    }
+
+    fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        (right > x && bottom > y).then(|| Rect {
+            x,
+            y,
+            width: (right - x) as u32,
+            height: (bottom - y) as u32,
+        })
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.right().max(other.right()) - x) as u32,
+            height: (self.bottom().max(other.bottom()) - y) as u32,
+        }
+    }
+}
+
+fn screen_rect(screen: &Screen) -> Rect {
+    let info = &screen.display_info;
+    Rect {
+        x: info.x,
+        y: info.y,
+        width: info.width,
+        height: info.height,
+    }
+}
+
+/// 截图像素与虚拟桌面坐标的比例
+///
+/// macOS 的坐标单位为点，截图为物理像素；Windows 与 Linux 的坐标本身就是物理像素。
+fn pixel_ratio(screen: &Screen) -> f32 {
+    if cfg!(target_os = "macos") {
+        screen.display_info.scale_factor.max(1.0)
+    } else {
+        1.0
+    }
+}
+
+/// 获取所有显示器
+#[tauri::command]
+pub fn get_monitors() -> Result<Vec<MonitorInfo>, String> {
+    let screens = Screen::all().map_err(|e| format!("获取屏幕信息失败: {e}"))?;
+    Ok(screens
+        .iter()
+        .map(|screen| {
+            let info = &screen.display_info;
+            let ratio = pixel_ratio(screen);
+            MonitorInfo {
+                id: info.id,
+                x: info.x,
+                y: info.y,
+                width: info.width,
+                height: info.height,
+                scale_factor: info.scale_factor,
+                physical_width: (info.width as f32 * ratio).round() as u32,
+                physical_height: (info.height as f32 * ratio).round() as u32,
+                rotation: info.rotation,
+                is_primary: info.is_primary,
+            }
+        })
+        .collect())
+}
+
+/// 屏幕共享的来源类型
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureSourceKind {
+    Screen,
+    Window,
+}
+
+/// 屏幕共享的来源
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureSource {
+    /// `screen:<显示器 id>` 或 `window:<窗口 id>`
+    pub id: String,
+    pub kind: CaptureSourceKind,
+    /// 窗口标题；屏幕为显示器名称
+    pub title: String,
+    /// 窗口所属的应用名称，屏幕为空
+    pub app_name: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub is_primary: bool,
+    /// data URL 形式的 JPEG 缩略图，截取失败时为空
+    pub thumbnail: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetCaptureSourcesReq {
+    /// 是否列出窗口，默认列出
+    pub include_windows: Option<bool>,
+    /// 缩略图最大边长，为 0 时不生成缩略图
+    pub thumbnail_size: Option<u32>,
+}
+
+/// 列出可共享的屏幕与顶层窗口
+///
+/// 最小化、无标题或尺寸为 0 的窗口以及本应用自身的窗口不会列出。
+#[tauri::command]
+pub async fn get_capture_sources(
+    req: Option<GetCaptureSourcesReq>,
+) -> Result<Vec<CaptureSource>, String> {
+    let req = req.unwrap_or_default();
+    let include_windows = req.include_windows.unwrap_or(true);
+    let thumbnail_size = req.thumbnail_size.unwrap_or(SOURCE_THUMBNAIL_SIZE);
+
+    tokio::task::spawn_blocking(move || {
+        let mut sources = list_screens(thumbnail_size)?;
+        if include_windows {
+            // 窗口列表获取失败（如 Wayland 不允许枚举窗口）时仍返回屏幕
+            match list_windows(thumbnail_size) {
+                Ok(windows) => sources.extend(windows),
+                Err(e) => warn!("Failed to list windows for screen sharing: {}", e),
+            }
+        }
+        Ok(sources)
+    })
+    .await
+    .map_err(|e| format!("获取共享来源失败: {e}"))?
+}
+
+fn list_screens(thumbnail_size: u32) -> Result<Vec<CaptureSource>, String> {
+    let screens = Screen::all().map_err(|e| format!("获取屏幕信息失败: {e}"))?;
+    Ok(screens
+        .iter()
+        .enumerate()
+        .map(|(index, screen)| {
+            let info = &screen.display_info;
+            let thumbnail = (thumbnail_size > 0)
+                .then(|| screen.capture())
+                .and_then(|captured| {
+                    captured
+                        .inspect_err(|e| warn!("Failed to capture screen {}: {}", info.id, e))
+                        .ok()
+                })
+                .and_then(|captured| {
+                    let (width, height) = captured.dimensions();
+                    RgbaImage::from_raw(width, height, captured.into_raw())
+                })
+                .and_then(|image| encode_thumbnail(&image, thumbnail_size));
+            CaptureSource {
+                id: format!("screen:{}", info.id),
+                kind: CaptureSourceKind::Screen,
+                title: format!("屏幕 {}", index + 1),
+                app_name: None,
+                x: info.x,
+                y: info.y,
+                width: info.width,
+                height: info.height,
+                is_primary: info.is_primary,
+                thumbnail,
+            }
+        })
+        .collect())
+}
+
+fn list_windows(thumbnail_size: u32) -> Result<Vec<CaptureSource>, String> {
+    let current_pid = std::process::id();
+    let windows = xcap::Window::all().map_err(|e| e.to_string())?;
+
+    let mut sources = Vec::new();
+    for window in windows {
+        let (Ok(id), Ok(title), Ok(width), Ok(height)) =
+            (window.id(), window.title(), window.width(), window.height())
+        else {
+            continue;
+        };
+        if title.trim().is_empty()
+            || width == 0
+            || height == 0
+            || window.is_minimized().unwrap_or(false)
+            || window.pid().is_ok_and(|pid| pid == current_pid)
+        {
+            continue;
+        }
+
+        let thumbnail = (thumbnail_size > 0)
+            .then(|| window.capture_image())
+            .and_then(|captured| {
+                captured
+                    .inspect_err(|e| warn!("Failed to capture window {}: {}", id, e))
+                    .ok()
+            })
+            .and_then(|image| encode_thumbnail(&image, thumbnail_size));
+        sources.push(CaptureSource {
+            id: format!("window:{id}"),
+            kind: CaptureSourceKind::Window,
+            title,
+            app_name: window.app_name().ok().filter(|name| !name.is_empty()),
+            x: window.x().unwrap_or_default(),
+            y: window.y().unwrap_or_default(),
+            width,
+            height,
+            is_primary: false,
+            thumbnail,
+        });
+    }
+    Ok(sources)
+}
+
+/// 缩小为来源缩略图并编码为 JPEG data URL
+fn encode_thumbnail(image: &RgbaImage, max_size: u32) -> Option<String> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    // 等比缩小，不放大
+    let scale = (max_size as f32 / width.max(height) as f32).min(1.0);
+    let thumbnail = imageops::thumbnail(
+        image,
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    );
+    encode(&thumbnail, ImageFormat::Jpeg, SOURCE_THUMBNAIL_QUALITY)
+        .inspect_err(|e| warn!("Failed to encode source thumbnail: {}", e))
+        .ok()
+        .map(|data| {
+            format!(
+                "data:image/jpeg;base64,{}",
+                general_purpose::STANDARD.encode(data)
+            )
+        })
+}
+
+/// 截取虚拟桌面中的区域，并编码为 PNG 或 JPEG
+#[tauri::command]
+pub async fn capture_region(
+    req: CaptureRegionReq,
+    app_handle: AppHandle,
+) -> Result<CaptureRegionResp, String> {
+    let format = match req
+        .format
+        .as_deref()
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        None | Some("png") => ImageFormat::Png,
+        Some("jpeg") | Some("jpg") => ImageFormat::Jpeg,
+        Some(format) => return Err(format!("不支持的截图格式: {format}")),
+    };
+    let quality = req.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+    let monitor_id = req.monitor_id;
+    let region = (req.x, req.y, req.width, req.height);
+
+    let (image, scale_factor, encoded) = tokio::task::spawn_blocking(move || {
+        let (image, scale_factor) = capture(monitor_id, region)?;
+        let encoded = encode(&image, format, quality)?;
+        Ok::<_, String>((image, scale_factor, encoded))
+    })
+    .await
+    .map_err(|e| format!("截图失败: {e}"))??;
+
+    let mimetype = format.to_mime_type().to_string();
+    let (path, data) = if req.save_to_file {
+        let output_dir = get_output_dir(&app_handle).await?;
+        let extension = if format == ImageFormat::Png {
+            "png"
+        } else {
+            "jpg"
+        };
+        let output_path = output_dir.join(format!("{}.{extension}", uuid::Uuid::new_v4()));
+        fs::write(&output_path, &encoded)
+            .await
+            .map_err(|e| format!("保存截图失败: {e}"))?;
+        (Some(output_path.to_string_lossy().to_string()), None)
+    } else {
+        let data = format!(
+            "data:{mimetype};base64,{}",
+            general_purpose::STANDARD.encode(&encoded)
+        );
+        (None, Some(data))
+    };
+
+    Ok(CaptureRegionResp {
+        path,
+        data,
+        mimetype,
+        width: image.width(),
+        height: image.height(),
+        scale_factor,
+    })
+}
+
+/// 截取区域，返回拼接后的截图与像素比例
+fn capture(
+    monitor_id: Option<u32>,
+    (x, y, width, height): (Option<i32>, Option<i32>, Option<u32>, Option<u32>),
+) -> Result<(RgbaImage, f32), String> {
+    let screens = Screen::all().map_err(|e| format!("获取屏幕信息失败: {e}"))?;
+
+    // 默认区域为指定的显示器或整个虚拟桌面
+    let bounds = match monitor_id {
+        Some(id) => screens
+            .iter()
+            .find(|screen| screen.display_info.id == id)
+            .map(screen_rect)
+            .ok_or_else(|| format!("未找到显示器: {id}"))?,
+        None => screens
+            .iter()
+            .map(screen_rect)
+            .reduce(|bounds, rect| bounds.union(&rect))
+            .ok_or_else(|| "未找到显示器".to_string())?,
+    };
+    let origin = if monitor_id.is_some() {
+        (bounds.x, bounds.y)
+    } else {
+        (0, 0)
+    };
+    let region = Rect {
+        x: x.map_or(bounds.x, |x| origin.0 + x),
+        y: y.map_or(bounds.y, |y| origin.1 + y),
+        width: width.unwrap_or(bounds.width),
+        height: height.unwrap_or(bounds.height),
+    };
+    if region.width == 0 || region.height == 0 {
+        return Err("截图区域为空".to_string());
+    }
+
+    let parts: Vec<(&Screen, Rect)> = screens
+        .iter()
+        .filter_map(|screen| {
+            screen_rect(screen)
+                .intersect(&region)
+                .map(|part| (screen, part))
+        })
+        .collect();
+    if parts.is_empty() {
+        return Err("截图区域不在任何显示器内".to_string());
+    }
+
+    // 按最高像素密度输出，低密度显示器的部分放大后拼接
+    let ratio = parts
+        .iter()
+        .map(|(screen, _)| pixel_ratio(screen))
+        .fold(1.0f32, f32::max);
+    let scaled = |value: u32| (value as f32 * ratio).round().max(1.0) as u32;
+    let mut canvas = RgbaImage::new(scaled(region.width), scaled(region.height));
+
+    for (screen, part) in parts {
+        let rect = screen_rect(screen);
+        let captured = screen
+            .capture_area(part.x - rect.x, part.y - rect.y, part.width, part.height)
+            .map_err(|e| format!("截图失败: {e}"))?;
+        let (captured_width, captured_height) = captured.dimensions();
+        let captured = RgbaImage::from_raw(captured_width, captured_height, captured.into_raw())
+            .ok_or_else(|| "截图数据无效".to_string())?;
+
+        let (target_width, target_height) = (scaled(part.width), scaled(part.height));
+        let captured = if captured.dimensions() == (target_width, target_height) {
+            captured
+        } else {
+            imageops::resize(&captured, target_width, target_height, FilterType::Triangle)
+        };
+        let offset_x = ((part.x - region.x) as f32 * ratio).round() as i64;
+        let offset_y = ((part.y - region.y) as f32 * ratio).round() as i64;
+        imageops::replace(&mut canvas, &captured, offset_x, offset_y);
+    }
+
+    Ok((canvas, ratio))
+}
+
+fn encode(image: &RgbaImage, format: ImageFormat, quality: u8) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let result = if format == ImageFormat::Jpeg {
+        DynamicImage::ImageRgba8(image.clone())
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, quality))
+    } else {
+        image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+    };
+    result.map_err(|e| format!("截图编码失败: {e}"))?;
+    Ok(data)
+}
+
+/// 获取截图目录，并顺带清理过期的截图
+async fn get_output_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let output_dir = app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| format!("获取缓存目录失败: {e}"))?
+        .join(SCREENSHOT_DIR);
+
+    fs::create_dir_all(&output_dir)
+        .await
+        .map_err(|e| format!("创建截图目录失败: {e}"))?;
+    if let Err(e) = remove_stale_files(&output_dir, SCREENSHOT_MAX_AGE).await {
+        warn!("Failed to clean up screenshots: {}", e);
+    }
+
+    Ok(output_dir)
+}
@@ -1,23 +1,47 @@
 use base64::{Engine as _, engine::general_purpose};
-use image::ImageFormat;
 #[cfg(target_os = "macos")]
 use image::ImageReader;
+use image::{DynamicImage, ImageFormat};
 use serde::Serialize;
 use std::path::Path;
 #[cfg(target_os = "macos")]
 use std::process::Command;
 use tauri::Result as TauriResult;
+use tracing::warn;
+
+use crate::utils::{ffmpeg, media_probe};
+
+/// 缩略图最大边长
+const THUMBNAIL_SIZE: u32 = 300;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct VideoThumbnailInfo {
     pub thumbnail_base64: String,
     pub width: u32,
     pub height: u32,
-    #[cfg(target_os = "macos")]
+    /// 视频时长（秒），无法获取时为 0
     pub duration: f64,
+    /// 视频编码，如 h264、hevc、vp9
+    pub codec: Option<String>,
+    /// 视频显示时需要顺时针旋转的角度，缩略图已按此角度摆正
+    pub rotation: u32,
+}
+
+/// 视频元数据
+#[derive(Debug, Default)]
+struct VideoMetadata {
+    duration: Option<f64>,
+    codec: Option<String>,
+    rotation: u32,
 }
 
 /// 生成视频缩略图
+///
+/// 优先使用 ffmpeg 截取 `target_time` 处的帧；没有 ffmpeg 时尝试在容器中直接取出
+/// MJPEG 关键帧或封面图；都不可用时才退回系统缩略图。
+///
+/// 封面图与系统缩略图无法指定时间，指定了 `target_time` 时不会用它们代替，
+/// 而是返回错误。
 pub async fn generate_video_thumbnail(
     video_path: &str,
     target_time: Option<f64>,
@@ -31,36 +55,151 @@ pub async fn generate_video_thumbnail(
         )));
     }
 
-    // 根据平台选择不同的实现
+    let path = path.to_path_buf();
+    let (mut metadata, frame) = tokio::task::spawn_blocking(move || {
+        let metadata = probe_video(&path);
+        let time = resolve_time(target_time, metadata.duration);
+        let frame = extract_frame(&path, time, metadata.rotation, target_time.is_none());
+        (metadata, frame)
+    })
+    .await
+    .map_err(|e| tauri::Error::Io(std::io::Error::other(format!("生成缩略图任务失败: {e}"))))?;
+
+    let image = match (frame, target_time) {
+        (Some(image), _) => image,
+        (None, Some(time)) => {
+            return Err(tauri::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("无法截取视频第 {time} 秒的画面，系统缩略图不支持指定时间: {video_path}"),
+            )));
+        }
+        (None, None) => generate_system_thumbnail(video_path).await?,
+    };
+
     #[cfg(target_os = "macos")]
-    {
-        generate_thumbnail_macos(video_path, target_time).await
+    if metadata.duration.is_none() {
+        metadata.duration = get_video_duration_macos(video_path).await;
     }
 
-    #[cfg(target_os = "windows")]
+    let width = image.width();
+    let height = image.height();
+
+    // 转换为 RGB 格式（去除透明度通道），然后转换为 JPEG
+    let mut jpeg_data = Vec::new();
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(&mut std::io::Cursor::new(&mut jpeg_data), ImageFormat::Jpeg)
+        .map_err(|e| tauri::Error::Io(std::io::Error::other(format!("转换为 JPEG 失败: {e}"))))?;
+
+    Ok(VideoThumbnailInfo {
+        thumbnail_base64: general_purpose::STANDARD.encode(&jpeg_data),
+        width,
+        height,
+        duration: metadata.duration.unwrap_or(0.0),
+        codec: metadata.codec.take(),
+        rotation: metadata.rotation,
+    })
+}
+
+/// 读取时长、编码与旋转角度，优先使用 ffprobe，否则直接解析容器
+fn probe_video(path: &Path) -> VideoMetadata {
+    let info = match ffmpeg::ffprobe_path() {
+        Some(_) => ffmpeg::probe(path).map(Some).or_else(|e| {
+            warn!("ffprobe 读取视频信息失败: {}", e);
+            media_probe::probe_file(path)
+        }),
+        None => media_probe::probe_file(path),
+    };
+
+    match info {
+        Ok(Some(info)) => VideoMetadata {
+            duration: info.duration,
+            codec: info.video.as_ref().map(|video| video.codec.clone()),
+            rotation: info.video.map_or(0, |video| video.rotation),
+        },
+        Ok(None) => VideoMetadata::default(),
+        Err(e) => {
+            warn!("解析视频容器失败: {}", e);
+            VideoMetadata::default()
+        }
+    }
+}
+
+/// 截取时间：指定的时间限制在视频时长内，未指定时取第 1 秒（短视频取中间）
+fn resolve_time(target_time: Option<f64>, duration: Option<f64>) -> f64 {
+    let time =
+        target_time.unwrap_or_else(|| duration.map_or(1.0, |duration| (duration / 2.0).min(1.0)));
+    let time = if time.is_finite() { time.max(0.0) } else { 0.0 };
+    match duration {
+        // 最后一帧之后 ffmpeg 不会输出画面
+        Some(duration) if duration > 0.0 => time.min((duration - 0.1).max(0.0)),
+        _ => time,
+    }
+}
+
+/// 截取 `time` 处的帧，无法截取时返回 `None`；`allow_cover_art` 为 true 时可用封面图代替
+fn extract_frame(
+    path: &Path,
+    time: f64,
+    rotation: u32,
+    allow_cover_art: bool,
+) -> Option<DynamicImage> {
+    if ffmpeg::ffmpeg_path().is_some() {
+        let frame = ffmpeg::extract_frame(path, time, THUMBNAIL_SIZE)
+            .and_then(|data| image::load_from_memory(&data).map_err(std::io::Error::other));
+        match frame {
+            Ok(image) => return Some(image),
+            Err(e) => warn!("ffmpeg 截取视频帧失败: {}", e),
+        }
+    }
+
+    let still = match media_probe::extract_still_image(path, time) {
+        Ok(still) => still.filter(|still| still.is_frame || allow_cover_art)?,
+        Err(e) => {
+            warn!("读取视频关键帧失败: {}", e);
+            return None;
+        }
+    };
+    let image = match image::load_from_memory(&still.data) {
+        Ok(image) => image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE),
+        Err(e) => {
+            warn!("解码视频关键帧失败: {}", e);
+            return None;
+        }
+    };
+    if !still.is_frame {
+        return Some(image);
+    }
+    Some(match rotation {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image,
+    })
+}
+
+/// 系统缩略图，无法指定截取时间
+async fn generate_system_thumbnail(video_path: &str) -> TauriResult<DynamicImage> {
+    #[cfg(target_os = "macos")]
     {
-        generate_thumbnail_windows(video_path, target_time).await
+        generate_thumbnail_macos(video_path).await
     }
 
-    #[cfg(target_os = "linux")]
+    #[cfg(target_os = "windows")]
     {
-        generate_thumbnail_linux(video_path, target_time).await
+        generate_thumbnail_windows(video_path).await
     }
 
-    #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
     {
         Err(tauri::Error::Io(std::io::Error::new(
             std::io::ErrorKind::Unsupported,
-            "当前平台暂不支持视频缩略图生成",
+            format!("未找到 ffmpeg，且无法直接解码该视频: {video_path}"),
         )))
     }
 }
 
 #[cfg(target_os = "macos")]
-async fn generate_thumbnail_macos(
-    video_path: &str,
-    _target_time: Option<f64>,
-) -> TauriResult<VideoThumbnailInfo> {
+async fn generate_thumbnail_macos(video_path: &str) -> TauriResult<DynamicImage> {
     use std::fs;
 
     // 检查视频文件是否存在
@@ -146,33 +285,10 @@ async fn generate_thumbnail_macos(
         .decode()
         .map_err(|e| tauri::Error::Io(std::io::Error::other(format!("解码图像失败: {e}"))))?;
 
-    let width = img.width();
-    let height = img.height();
-
-    // 转换为 RGB 格式（去除透明度通道），然后转换为 JPEG
-    let rgb_img = img.to_rgb8();
-    let mut jpeg_data = Vec::new();
-
-    image::DynamicImage::ImageRgb8(rgb_img)
-        .write_to(&mut std::io::Cursor::new(&mut jpeg_data), ImageFormat::Jpeg)
-        .map_err(|e| tauri::Error::Io(std::io::Error::other(format!("转换为 JPEG 失败: {e}"))))?;
-
-    // 转换为 base64
-    let base64_string = general_purpose::STANDARD.encode(&jpeg_data);
-
     // 清理临时文件
     let _ = tokio::fs::remove_file(&thumbnail_path).await;
 
-    // 获取视频时长（使用 mdls 命令）
-    let duration = get_video_duration_macos(video_path).await.unwrap_or(0.0);
-
-    Ok(VideoThumbnailInfo {
-        thumbnail_base64: base64_string,
-        width,
-        height,
-        #[cfg(target_os = "macos")]
-        duration,
-    })
+    Ok(img)
 }
 
 #[cfg(target_os = "macos")]
@@ -299,10 +415,7 @@ unsafe fn convert_hbitmap_to_image_data(
 }
 
 #[cfg(target_os = "windows")]
-async fn generate_thumbnail_windows(
-    video_path: &str,
-    _target_time: Option<f64>,
-) -> TauriResult<VideoThumbnailInfo> {
+async fn generate_thumbnail_windows(video_path: &str) -> TauriResult<DynamicImage> {
     use windows::{Win32::Foundation::*, Win32::System::Com::*, Win32::UI::Shell::*, core::*};
 
     // 检查视频文件是否存在
@@ -368,35 +481,10 @@ async fn generate_thumbnail_windows(
 
     let (width, height, image_data) = result?;
 
-    // 创建图像并转换为 JPEG
     let img = image::RgbImage::from_raw(width, height, image_data)
         .ok_or_else(|| tauri::Error::Io(std::io::Error::other("创建图像失败")))?;
 
-    let mut jpeg_data = Vec::new();
-    image::DynamicImage::ImageRgb8(img)
-        .write_to(&mut std::io::Cursor::new(&mut jpeg_data), ImageFormat::Jpeg)
-        .map_err(|e| tauri::Error::Io(std::io::Error::other(format!("转换为 JPEG 失败: {}", e))))?;
-
-    // 转换为 base64
-    let base64_string = general_purpose::STANDARD.encode(&jpeg_data);
-
-    Ok(VideoThumbnailInfo {
-        thumbnail_base64: base64_string,
-        width,
-        height,
-    })
-}
-
-#[cfg(target_os = "linux")]
-async fn generate_thumbnail_linux(
-    _video_path: &str,
-    _target_time: Option<f64>,
-) -> TauriResult<VideoThumbnailInfo> {
-    // Linux 实现可以使用 gstreamer 或其他方案
-    Err(tauri::Error::Io(std::io::Error::new(
-        std::io::ErrorKind::Unsupported,
-        "Linux 平台暂不支持视频缩略图生成",
-    )))
+    Ok(DynamicImage::ImageRgb8(img))
 }
 
 /// Tauri 命令：生成视频缩略图
@@ -1,4 +1,4 @@
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Debug, Clone)]
 pub enum AppError {
     #[error("IO error: {0}")]
     Io(String),
@@ -14,6 +14,18 @@ pub enum AppError {
     Request(String),
     #[error("Token expired")]
     TokenExpired,
+    #[error("Media not found: {0}")]
+    MediaNotFound(String),
+    #[error("Media too large: {0}")]
+    MediaTooLarge(String),
+    #[error("Rate limited, retry after {0} ms")]
+    RateLimited(u64),
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+    #[error("Decryption error: {0}")]
+    Decryption(String),
+    #[error("Integrity check failed: {0}")]
+    IntegrityCheckFailed(String),
     #[error("Unexpected error: {0}")]
     Unexpected(String),
 }
@@ -46,6 +58,17 @@ impl From<sea_orm::DbErr> for AppError {
     }
 }
 
+impl From<CommonError> for AppError {
+    fn from(err: CommonError) -> Self {
+        match err {
+            CommonError::DatabaseError(err) => AppError::Database(err.to_string()),
+            CommonError::RequestError(msg) => AppError::Request(msg),
+            CommonError::TokenExpired => AppError::TokenExpired,
+            CommonError::UnexpectedError(err) => AppError::Unexpected(err.to_string()),
+        }
+    }
+}
+
 impl From<anyhow::Error> for AppError {
     fn from(err: anyhow::Error) -> Self {
         AppError::Unexpected(err.to_string())
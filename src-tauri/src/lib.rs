@@ -1,7 +1,7 @@
 // 桌面端依赖
 #[cfg(desktop)]
 mod desktops;
-use crate::common::files_meta::get_files_meta;
+use crate::common::files_meta::{get_files_meta, probe_media};
 #[cfg(desktop)]
 use common::init::CustomInit;
 #[cfg(target_os = "windows")]
@@ -16,6 +16,8 @@ use common_cmd::{
 #[cfg(target_os = "macos")]
 use desktops::app_event;
 #[cfg(desktop)]
+use desktops::screen_capture::{capture_region, get_capture_sources, get_monitors};
+#[cfg(desktop)]
 use desktops::window_payload::{get_window_payload, push_window_payload};
 #[cfg(desktop)]
 use desktops::{common_cmd, directory_scanner, init, tray, video_thumbnail::get_video_thumbnail};
@@ -31,6 +33,7 @@ pub mod common;
 pub mod configuration;
 pub mod error;
 pub mod pojo;
+pub mod repository;
 pub mod state;
 pub mod utils;
 mod vo;
@@ -39,6 +42,7 @@ mod webview_helper;
 
 use crate::command::app_state_command::is_app_state_ready;
 use crate::command::setting_command::{get_settings, update_settings};
+use crate::command::upload_queue_command::start_upload_worker;
 use crate::configuration::{Settings, get_configuration};
 use crate::error::CommonError;
 use sea_orm::DatabaseConnection;
@@ -54,6 +58,7 @@ use mobiles::splash;
 
 #[derive(Debug)]
 pub struct AppData {
+    pub db_conn: Arc<DatabaseConnection>,
     user_info: Arc<Mutex<UserInfo>>,
     pub config: Arc<Mutex<Settings>>,
     frontend_task: Mutex<bool>,
@@ -62,9 +67,17 @@ pub struct AppData {
 
 pub(crate) static APP_STATE_READY: AtomicBool = AtomicBool::new(false);
 
+use crate::command::image_command::{
+    convert_image, decode_blurhash, encode_blurhash, prepare_image_for_upload,
+};
 use crate::command::media::{
-    clear_media_cache, delete_cached_media, download_media, get_media_cache_stats, preload_media,
+    cancel_media_download, clear_media_cache, delete_cached_media, download_encrypted_media,
+    download_media, get_media_cache_stats, get_media_thumbnail, prune_media_cache,
+    set_media_cache_pinned, set_media_cache_quota, upload_media,
 };
+use crate::command::media_scheduler::{cancel_media_preload, preload_media};
+use crate::command::video_command::{cancel_video_compression, compress_video};
+use crate::command::voice_command::encode_voice_message;
 use crate::state::AppState;
 
 #[cfg(desktop)]
@@ -102,6 +115,10 @@ fn setup_desktop() -> Result<(), CommonError> {
             common_setup(app.handle().clone())?;
             Ok(())
         })
+        .register_asynchronous_uri_scheme_protocol(
+            common::media_protocol::MEDIA_SCHEME,
+            common::media_protocol::handle_media_request,
+        )
         .invoke_handler(get_invoke_handlers())
         .build(tauri::generate_context!())
         .map_err(|e| CommonError::RequestError(format!("Failed to build tauri application: {e}")))?
@@ -127,6 +144,7 @@ async fn initialize_app_data(
     ),
     CommonError,
 > {
+    use crate::repository::im_message_fts_repository;
     use migration::{Migrator, MigratorTrait};
     use tracing::info;
 
@@ -136,6 +154,20 @@ async fn initialize_app_data(
             anyhow::anyhow!("Failed to load configuration: {e}")
         })?));
 
+    // 令牌等敏感信息优先保存在系统钥匙串中，不可用时保存在应用数据目录的加密文件中
+    let secret_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| anyhow::anyhow!("Failed to get app data dir: {e}"))?;
+    utils::secret_store::init(&app_handle.config().identifier, secret_dir);
+
+    // 每个账号的日志写入日志目录下的独立文件
+    let log_dir = app_handle
+        .path()
+        .app_log_dir()
+        .map_err(|e| anyhow::anyhow!("Failed to get app log dir: {e}"))?;
+    utils::account_storage::init_log_root(log_dir);
+
     // 初始化数据库连接
     let db: Arc<DatabaseConnection> = Arc::new(
         configuration
@@ -156,6 +188,34 @@ async fn initialize_app_data(
         }
     }
 
+    // 旧版本的令牌以明文保存在数据库中，迁移到安全存储
+    match command::user_command::migrate_plaintext_tokens(db.as_ref()).await {
+        Ok(0) => {}
+        Ok(count) => info!("Migrated plaintext tokens of {} users", count),
+        Err(e) => tracing::warn!("Failed to migrate plaintext tokens: {}", e),
+    }
+
+    // 后台补齐聊天记录全文索引，不阻塞启动
+    let fts_db = db.clone();
+    tauri::async_runtime::spawn(async move {
+        match im_message_fts_repository::index_missing_messages(fts_db.as_ref()).await {
+            Ok(0) => {}
+            Ok(count) => info!("Indexed {} messages for full-text search", count),
+            Err(e) => tracing::warn!("Failed to build message search index: {}", e),
+        }
+    });
+
+    // 清理媒体缓存索引与缓存目录中不一致的部分
+    let cache_db = db.clone();
+    let cache_app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) =
+            command::media::reconcile_media_cache_index(&cache_app_handle, cache_db.as_ref()).await
+        {
+            tracing::warn!("Failed to reconcile media cache index: {}", e);
+        }
+    });
+
     // 创建用户信息
     let user_info = UserInfo {
         token: Default::default(),
@@ -302,6 +362,10 @@ fn setup_mobile() {
             tracing::info!("Mobile application setup completed successfully");
             Ok(())
         })
+        .register_asynchronous_uri_scheme_protocol(
+            common::media_protocol::MEDIA_SCHEME,
+            common::media_protocol::handle_media_request,
+        )
         .invoke_handler(get_invoke_handlers())
         .run(tauri::generate_context!())
     {
@@ -323,9 +387,10 @@ fn common_setup(app_handle: AppHandle) -> Result<(), Box<dyn std::error::Error>>
 
     // 异步初始化应用数据，避免阻塞主线程
     match tauri::async_runtime::block_on(initialize_app_data(app_handle.clone())) {
-        Ok((_db, user_info, settings)) => {
+        Ok((db, user_info, settings)) => {
             // 使用 manage 方法在运行时添加状态
             app_handle.manage(AppData {
+                db_conn: db,
                 user_info: user_info.clone(),
                 config: settings,
                 frontend_task: Mutex::new(false),
@@ -335,6 +400,7 @@ fn common_setup(app_handle: AppHandle) -> Result<(), Box<dyn std::error::Error>>
 
             // 添加应用状态
             app_handle.manage(AppState::new());
+            start_upload_worker(app_handle.clone());
 
             APP_STATE_READY.store(true, Ordering::SeqCst);
             if let Err(e) = app_handle.emit("app-state-ready", ()) {
@@ -355,9 +421,25 @@ fn common_setup(app_handle: AppHandle) -> Result<(), Box<dyn std::error::Error>>
 // 公共的命令处理器函数
 fn get_invoke_handlers() -> impl Fn(tauri::ipc::Invoke<tauri::Wry>) -> bool + Send + Sync + 'static
 {
+    use crate::command::account_command::{
+        add_account, list_accounts, remove_account, switch_account,
+    };
+    use crate::command::database_command::{export_plaintext, rekey};
     use crate::command::error_log_command::{clear_error_log, read_error_log, save_error_log};
+    use crate::command::export_command::export_room_history;
+    use crate::command::message_command::{
+        delete_message, delete_room_messages, page_msg, query_chat_history, save_message_mark,
+        save_msg, search_messages, update_message_recall_status,
+    };
+    use crate::command::room_member_command::{
+        cursor_page_room_members, get_room_members, save_room_members,
+    };
     #[cfg(mobile)]
     use crate::command::set_complete;
+    use crate::command::upload_queue_command::{
+        enqueue_media_upload, get_upload_queue, remove_media_upload, retry_media_upload,
+    };
+    use crate::command::user_command::{get_user_tokens, remove_tokens, update_token};
     #[cfg(desktop)]
     use crate::desktops::common_cmd::set_badge_count;
     #[cfg(target_os = "ios")]
@@ -376,6 +458,12 @@ fn get_invoke_handlers() -> impl Fn(tauri::ipc::Invoke<tauri::Wry>) -> bool + Se
         #[cfg(desktop)]
         screenshot,
         #[cfg(desktop)]
+        get_monitors,
+        #[cfg(desktop)]
+        capture_region,
+        #[cfg(desktop)]
+        get_capture_sources,
+        #[cfg(desktop)]
         audio,
         #[cfg(desktop)]
         set_height,
@@ -394,6 +482,7 @@ fn get_invoke_handlers() -> impl Fn(tauri::ipc::Invoke<tauri::Wry>) -> bool + Se
         #[cfg(desktop)]
         get_window_payload,
         get_files_meta,
+        probe_media,
         #[cfg(desktop)]
         get_directory_usage_info_with_progress,
         #[cfg(desktop)]
@@ -407,10 +496,55 @@ fn get_invoke_handlers() -> impl Fn(tauri::ipc::Invoke<tauri::Wry>) -> bool + Se
         update_settings,
         // 媒体相关命令
         download_media,
+        download_encrypted_media,
+        get_media_thumbnail,
+        prepare_image_for_upload,
+        encode_blurhash,
+        convert_image,
+        decode_blurhash,
+        encode_voice_message,
+        compress_video,
+        cancel_video_compression,
+        cancel_media_download,
+        upload_media,
         delete_cached_media,
         clear_media_cache,
         get_media_cache_stats,
+        prune_media_cache,
+        set_media_cache_pinned,
+        set_media_cache_quota,
         preload_media,
+        cancel_media_preload,
+        enqueue_media_upload,
+        get_upload_queue,
+        retry_media_upload,
+        remove_media_upload,
+        // 消息相关命令
+        save_msg,
+        page_msg,
+        query_chat_history,
+        search_messages,
+        delete_message,
+        delete_room_messages,
+        update_message_recall_status,
+        save_message_mark,
+        export_room_history,
+        // 房间成员相关命令
+        save_room_members,
+        get_room_members,
+        cursor_page_room_members,
+        // 用户令牌相关命令
+        update_token,
+        get_user_tokens,
+        remove_tokens,
+        // 账号相关命令
+        list_accounts,
+        add_account,
+        switch_account,
+        remove_account,
+        // 数据库加密相关命令
+        rekey,
+        export_plaintext,
         #[cfg(mobile)]
         set_complete,
         #[cfg(mobile)]
@@ -0,0 +1,78 @@
+use entity::im_config;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, QueryFilter,
+    QueryOrder, Set,
+};
+
+use crate::error::CommonError;
+
+/// 账号的媒体缓存上限（字节），未设置时使用配置文件中的默认值
+pub const MEDIA_CACHE_MAX_SIZE_KEY: &str = "media_cache_max_size_bytes";
+
+async fn find_entry<C>(
+    db: &C,
+    config_key: &str,
+    login_uid: &str,
+) -> Result<Option<im_config::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_config::Entity::find()
+        .filter(im_config::Column::ConfigKey.eq(config_key))
+        .filter(im_config::Column::LoginUid.eq(login_uid))
+        .one(db)
+        .await?)
+}
+
+/// 读取账号的配置项
+pub async fn get_value<C>(
+    db: &C,
+    config_key: &str,
+    login_uid: &str,
+) -> Result<Option<String>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(find_entry(db, config_key, login_uid)
+        .await?
+        .and_then(|entry| entry.config_value))
+}
+
+/// 保存账号的配置项，已存在时覆盖
+pub async fn set_value<C>(
+    db: &C,
+    config_key: &str,
+    value: &str,
+    login_uid: &str,
+) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    if let Some(entry) = find_entry(db, config_key, login_uid).await? {
+        let mut active = entry.into_active_model();
+        active.config_value = Set(Some(value.to_string()));
+        active.update(db).await?;
+        return Ok(());
+    }
+
+    // im_config 的 id 不是自增列，取该账号下最大的 id 加一
+    let id = im_config::Entity::find()
+        .filter(im_config::Column::LoginUid.eq(login_uid))
+        .order_by_desc(im_config::Column::Id)
+        .one(db)
+        .await?
+        .map_or(1, |entry| entry.id + 1);
+    im_config::Entity::insert(
+        im_config::Model {
+            id,
+            config_key: config_key.to_string(),
+            config_value: Some(value.to_string()),
+            login_uid: login_uid.to_string(),
+        }
+        .into_active_model(),
+    )
+    .exec_without_returning(db)
+    .await?;
+
+    Ok(())
+}
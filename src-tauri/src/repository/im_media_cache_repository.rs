@@ -0,0 +1,272 @@
+use entity::im_media_cache;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder,
+    QuerySelect,
+};
+
+use crate::error::CommonError;
+
+/// 媒体缓存汇总信息
+#[derive(Debug, Default)]
+pub struct MediaCacheSummary {
+    pub count: u64,
+    pub total_size: u64,
+    /// 最早写入时间（毫秒）
+    pub oldest_created_at: Option<i64>,
+    /// 最晚写入时间（毫秒）
+    pub newest_created_at: Option<i64>,
+}
+
+/// 缓存清理条件，各条件之间为 AND 关系
+#[derive(Debug, Default)]
+pub struct MediaCachePruneFilter {
+    /// 最后访问时间早于该时间戳（毫秒）
+    pub accessed_before: Option<i64>,
+    pub room_id: Option<String>,
+    /// MIME 类型或前缀，如 `image/`、`video/mp4`
+    pub mime_type: Option<String>,
+    /// 是否同时清理固定的缓存
+    pub include_pinned: bool,
+}
+
+/// 写入或更新缓存条目，按主键 (cache_key, login_uid) upsert
+///
+/// 条目已存在时保留原有的写入时间、固定状态；`room_id` 为空时不覆盖已有值。
+pub async fn upsert_entry<C>(db: &C, entry: im_media_cache::Model) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    let mut update_columns = vec![
+        im_media_cache::Column::MxcUri,
+        im_media_cache::Column::Size,
+        im_media_cache::Column::MimeType,
+        im_media_cache::Column::LastAccessedAt,
+    ];
+    if entry.room_id.is_some() {
+        update_columns.push(im_media_cache::Column::RoomId);
+    }
+
+    im_media_cache::Entity::insert(entry.into_active_model())
+        .on_conflict(
+            OnConflict::columns([
+                im_media_cache::Column::CacheKey,
+                im_media_cache::Column::LoginUid,
+            ])
+            .update_columns(update_columns)
+            .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+
+    Ok(())
+}
+
+/// 查询单个缓存条目
+pub async fn find_entry<C>(
+    db: &C,
+    cache_key: &str,
+    login_uid: &str,
+) -> Result<Option<im_media_cache::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(
+        im_media_cache::Entity::find_by_id((cache_key.to_string(), login_uid.to_string()))
+            .one(db)
+            .await?,
+    )
+}
+
+/// 更新最后访问时间
+pub async fn touch_entry<C>(
+    db: &C,
+    cache_key: &str,
+    accessed_at: i64,
+    login_uid: &str,
+) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    im_media_cache::Entity::update_many()
+        .col_expr(
+            im_media_cache::Column::LastAccessedAt,
+            Expr::value(accessed_at),
+        )
+        .filter(im_media_cache::Column::CacheKey.eq(cache_key))
+        .filter(im_media_cache::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// 查询某个 mxc URI 的所有缓存条目（原图及缩略图）
+pub async fn find_by_mxc_uri<C>(
+    db: &C,
+    mxc_uri: &str,
+    login_uid: &str,
+) -> Result<Vec<im_media_cache::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_media_cache::Entity::find()
+        .filter(im_media_cache::Column::MxcUri.eq(mxc_uri))
+        .filter(im_media_cache::Column::LoginUid.eq(login_uid))
+        .all(db)
+        .await?)
+}
+
+/// 查询全部账号的缓存条目
+pub async fn find_all<C>(db: &C) -> Result<Vec<im_media_cache::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_media_cache::Entity::find().all(db).await?)
+}
+
+/// 按条件查询待清理的缓存条目
+pub async fn find_prunable<C>(
+    db: &C,
+    filter: MediaCachePruneFilter,
+    login_uid: &str,
+) -> Result<Vec<im_media_cache::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let mut query =
+        im_media_cache::Entity::find().filter(im_media_cache::Column::LoginUid.eq(login_uid));
+
+    if let Some(accessed_before) = filter.accessed_before {
+        query = query.filter(im_media_cache::Column::LastAccessedAt.lt(accessed_before));
+    }
+    if let Some(room_id) = filter.room_id {
+        query = query.filter(im_media_cache::Column::RoomId.eq(room_id));
+    }
+    if let Some(mime_type) = filter.mime_type.filter(|t| !t.is_empty()) {
+        query = query.filter(im_media_cache::Column::MimeType.starts_with(mime_type));
+    }
+    if !filter.include_pinned {
+        query = query.filter(im_media_cache::Column::Pinned.eq(false));
+    }
+
+    Ok(query.all(db).await?)
+}
+
+/// 按 LRU 顺序选出需要淘汰的条目，使账号的缓存总大小不超过 `max_size`
+///
+/// 固定的条目与 `keep` 指定的条目（通常是刚下载的文件）不会被选中。
+pub async fn find_eviction_candidates<C>(
+    db: &C,
+    max_size: u64,
+    keep: Option<&str>,
+    login_uid: &str,
+) -> Result<Vec<im_media_cache::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let total_size = summary(db, login_uid).await?.total_size;
+    if total_size <= max_size {
+        return Ok(Vec::new());
+    }
+
+    let mut query = im_media_cache::Entity::find()
+        .filter(im_media_cache::Column::LoginUid.eq(login_uid))
+        .filter(im_media_cache::Column::Pinned.eq(false))
+        .order_by_asc(im_media_cache::Column::LastAccessedAt);
+    if let Some(keep) = keep {
+        query = query.filter(im_media_cache::Column::CacheKey.ne(keep));
+    }
+
+    let mut excess = total_size - max_size;
+    let mut candidates = Vec::new();
+    for entry in query.all(db).await? {
+        if excess == 0 {
+            break;
+        }
+        excess = excess.saturating_sub(entry.size.max(0) as u64);
+        candidates.push(entry);
+    }
+
+    Ok(candidates)
+}
+
+/// 设置某个 mxc URI 的固定状态，返回受影响的条目数
+pub async fn set_pinned<C>(
+    db: &C,
+    mxc_uri: &str,
+    pinned: bool,
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_media_cache::Entity::update_many()
+        .col_expr(im_media_cache::Column::Pinned, Expr::value(pinned))
+        .filter(im_media_cache::Column::MxcUri.eq(mxc_uri))
+        .filter(im_media_cache::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+/// 删除指定的缓存条目
+pub async fn delete_entries<C>(
+    db: &C,
+    cache_keys: Vec<String>,
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    if cache_keys.is_empty() {
+        return Ok(0);
+    }
+
+    let result = im_media_cache::Entity::delete_many()
+        .filter(im_media_cache::Column::CacheKey.is_in(cache_keys))
+        .filter(im_media_cache::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+/// 删除账号的全部缓存条目
+pub async fn delete_all<C>(db: &C, login_uid: &str) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_media_cache::Entity::delete_many()
+        .filter(im_media_cache::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+/// 汇总账号的缓存条目数、总大小与写入时间范围
+pub async fn summary<C>(db: &C, login_uid: &str) -> Result<MediaCacheSummary, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let row = im_media_cache::Entity::find()
+        .filter(im_media_cache::Column::LoginUid.eq(login_uid))
+        .select_only()
+        .column_as(im_media_cache::Column::CacheKey.count(), "count")
+        .column_as(im_media_cache::Column::Size.sum(), "total_size")
+        .column_as(im_media_cache::Column::CreatedAt.min(), "oldest")
+        .column_as(im_media_cache::Column::CreatedAt.max(), "newest")
+        .into_tuple::<(i64, Option<i64>, Option<i64>, Option<i64>)>()
+        .one(db)
+        .await?;
+
+    Ok(row
+        .map(|(count, total_size, oldest, newest)| MediaCacheSummary {
+            count: count.max(0) as u64,
+            total_size: total_size.unwrap_or_default().max(0) as u64,
+            oldest_created_at: oldest,
+            newest_created_at: newest,
+        })
+        .unwrap_or_default())
+}
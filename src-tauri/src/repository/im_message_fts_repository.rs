@@ -0,0 +1,313 @@
+use entity::im_message;
+use sea_orm::{ConnectionTrait, DbBackend, EntityTrait, Statement, TransactionTrait, Value};
+
+use crate::error::CommonError;
+use crate::pojo::common::{CursorPageParam, CursorPageResp};
+use crate::repository::im_message_repository::{RECALL_MESSAGE_TYPE, build_cursor, parse_cursor};
+use crate::utils::fts_tokenizer::{
+    build_match_query, highlight_snippet, highlight_terms, tokenize_for_index,
+};
+
+/// 摘要中命中位置前后保留的字符数
+const SNIPPET_CONTEXT: usize = 20;
+
+/// 全文检索条件
+#[derive(Debug, Default)]
+pub struct MessageSearchFilter {
+    pub keyword: String,
+    pub room_id: Option<String>,
+    /// Matrix 用户 ID 或本地 uid
+    pub sender: Option<String>,
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    /// 为空表示不限制消息类型
+    pub message_types: Vec<u8>,
+}
+
+/// 全文检索命中结果
+#[derive(Debug)]
+pub struct MessageSearchHit {
+    pub message: im_message::Model,
+    /// 带 `<mark>` 高亮的摘要
+    pub snippet: String,
+}
+
+/// 从消息体 JSON 中提取可检索的文本
+pub fn extract_searchable_text(body: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(serde_json::Value::String(text)) => text,
+        Ok(value) => ["content", "body", "text", "fileName", "name"]
+            .iter()
+            .filter_map(|key| value.get(key).and_then(serde_json::Value::as_str))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Err(_) => body.to_string(),
+    }
+}
+
+/// 启动时补齐索引每批处理的消息数
+const INDEX_BATCH_SIZE: u64 = 500;
+
+/// 重新索引满足条件的消息，返回 (扫描的条数, 写入索引的条数)
+///
+/// 每条消息都会在 im_message_fts_doc 中留下记录，没有可检索文本的消息只记录不写索引。
+async fn reindex_where<C>(
+    db: &C,
+    condition: &str,
+    values: Vec<Value>,
+) -> Result<(u64, u64), CommonError>
+where
+    C: ConnectionTrait,
+{
+    let rows = db
+        .query_all(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            format!("SELECT id, login_uid, body, message_type FROM im_message WHERE {condition}"),
+            values,
+        ))
+        .await?;
+
+    let scanned = rows.len() as u64;
+    let mut indexed = 0;
+    for row in rows {
+        let id: String = row.try_get("", "id")?;
+        let login_uid: String = row.try_get("", "login_uid")?;
+        let body: Option<String> = row.try_get("", "body")?;
+        let message_type: Option<i32> = row.try_get("", "message_type")?;
+
+        db.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "INSERT INTO im_message_fts_doc (message_id, login_uid) VALUES (?, ?) \
+             ON CONFLICT (message_id, login_uid) DO NOTHING",
+            [id.clone().into(), login_uid.clone().into()],
+        ))
+        .await?;
+        let doc_id: i64 = db
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "SELECT doc_id FROM im_message_fts_doc WHERE message_id = ? AND login_uid = ?",
+                [id.into(), login_uid.into()],
+            ))
+            .await?
+            .map(|row| row.try_get("", "doc_id"))
+            .transpose()?
+            .unwrap_or_default();
+
+        db.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "DELETE FROM im_message_fts WHERE rowid = ?",
+            [doc_id.into()],
+        ))
+        .await?;
+
+        // 撤回的消息不再参与检索
+        if message_type == Some(i32::from(RECALL_MESSAGE_TYPE)) {
+            continue;
+        }
+
+        let tokens = tokenize_for_index(&extract_searchable_text(body.as_deref().unwrap_or("")));
+        if tokens.is_empty() {
+            continue;
+        }
+
+        db.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "INSERT INTO im_message_fts (rowid, tokens) VALUES (?, ?)",
+            [doc_id.into(), tokens.into()],
+        ))
+        .await?;
+        indexed += 1;
+    }
+
+    Ok((scanned, indexed))
+}
+
+/// 索引刚保存的消息，定位方式与 upsert 的冲突键一致
+pub async fn index_message<C>(db: &C, message: &im_message::Model) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let (_, indexed) = match &message.event_id {
+        Some(event_id) => {
+            reindex_where(
+                db,
+                "login_uid = ? AND room_id = ? AND event_id = ?",
+                vec![
+                    message.login_uid.clone().into(),
+                    message.room_id.clone().into(),
+                    event_id.clone().into(),
+                ],
+            )
+            .await?
+        }
+        None => {
+            reindex_where(
+                db,
+                "id = ? AND login_uid = ?",
+                vec![message.id.clone().into(), message.login_uid.clone().into()],
+            )
+            .await?
+        }
+    };
+    Ok(indexed)
+}
+
+/// 按消息 ID 或 event_id 重新索引
+pub async fn index_message_by_id<C>(
+    db: &C,
+    message_id: &str,
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let (_, indexed) = reindex_where(
+        db,
+        "(id = ? OR event_id = ?) AND login_uid = ?",
+        vec![message_id.into(), message_id.into(), login_uid.into()],
+    )
+    .await?;
+    Ok(indexed)
+}
+
+/// 补齐尚未建立索引的消息（升级后首次启动或索引重建时使用）
+///
+/// 分批在事务中处理，已处理的消息都会留下记录，因此每条消息只会扫描一次。
+pub async fn index_missing_messages<C>(db: &C) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait + TransactionTrait,
+{
+    let mut indexed = 0;
+    loop {
+        let txn = db.begin().await?;
+        let (scanned, count) = reindex_where(
+            &txn,
+            "NOT EXISTS (SELECT 1 FROM im_message_fts_doc d \
+             WHERE d.message_id = im_message.id AND d.login_uid = im_message.login_uid) \
+             LIMIT ?",
+            vec![INDEX_BATCH_SIZE.into()],
+        )
+        .await?;
+        txn.commit().await?;
+
+        indexed += count;
+        if scanned < INDEX_BATCH_SIZE {
+            return Ok(indexed);
+        }
+    }
+}
+
+/// 全文检索聊天记录，按发送时间倒序游标分页
+pub async fn search_messages<C>(
+    db: &C,
+    filter: MessageSearchFilter,
+    param: CursorPageParam,
+    login_uid: &str,
+) -> Result<CursorPageResp<Vec<MessageSearchHit>>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let Some(match_query) = build_match_query(&filter.keyword) else {
+        return Ok(CursorPageResp {
+            cursor: String::new(),
+            is_last: true,
+            list: Some(Vec::new()),
+            total: 0,
+        });
+    };
+
+    let mut conditions = vec!["im_message_fts MATCH ?", "m.login_uid = ?"];
+    let mut values: Vec<Value> = vec![match_query.into(), login_uid.into()];
+
+    if let Some(room_id) = filter.room_id {
+        conditions.push("m.room_id = ?");
+        values.push(room_id.into());
+    }
+    if let Some(sender) = filter.sender {
+        conditions.push("(m.sender = ? OR m.uid = ?)");
+        values.push(sender.clone().into());
+        values.push(sender.into());
+    }
+    if let Some(start_time) = filter.start_time {
+        conditions.push("m.send_time >= ?");
+        values.push(start_time.into());
+    }
+    if let Some(end_time) = filter.end_time {
+        conditions.push("m.send_time <= ?");
+        values.push(end_time.into());
+    }
+    let type_condition = format!(
+        "m.message_type IN ({})",
+        vec!["?"; filter.message_types.len()].join(", ")
+    );
+    if !filter.message_types.is_empty() {
+        conditions.push(&type_condition);
+        values.extend(filter.message_types.iter().map(|t| Value::from(*t)));
+    }
+
+    let from_sql = "FROM im_message_fts \
+         JOIN im_message_fts_doc d ON d.doc_id = im_message_fts.rowid \
+         JOIN im_message m ON m.id = d.message_id AND m.login_uid = d.login_uid";
+
+    let total: i64 = db
+        .query_one(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            format!(
+                "SELECT COUNT(*) AS total {from_sql} WHERE {}",
+                conditions.join(" AND ")
+            ),
+            values.clone(),
+        ))
+        .await?
+        .map(|row| row.try_get("", "total"))
+        .transpose()?
+        .unwrap_or_default();
+
+    if let Some((send_time, id)) = parse_cursor(&param.cursor) {
+        conditions.push("(m.send_time < ? OR (m.send_time = ? AND m.id < ?))");
+        values.push(send_time.into());
+        values.push(send_time.into());
+        values.push(id.into());
+    }
+
+    let page_size = u64::from(param.page_size.max(1));
+    values.push((page_size + 1).into());
+
+    let mut list = im_message::Entity::find()
+        .from_raw_sql(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            format!(
+                "SELECT m.* {from_sql} WHERE {} ORDER BY m.send_time DESC, m.id DESC LIMIT ?",
+                conditions.join(" AND ")
+            ),
+            values,
+        ))
+        .all(db)
+        .await?;
+
+    let is_last = list.len() as u64 <= page_size;
+    list.truncate(page_size as usize);
+
+    let cursor = if is_last {
+        String::new()
+    } else {
+        list.last().map(build_cursor).unwrap_or_default()
+    };
+
+    let terms = highlight_terms(&filter.keyword);
+    let hits = list
+        .into_iter()
+        .map(|message| {
+            let text = extract_searchable_text(message.body.as_deref().unwrap_or(""));
+            let snippet = highlight_snippet(&text, &terms, SNIPPET_CONTEXT);
+            MessageSearchHit { message, snippet }
+        })
+        .collect();
+
+    Ok(CursorPageResp {
+        cursor,
+        is_last,
+        list: Some(hits),
+        total: total as u64,
+    })
+}
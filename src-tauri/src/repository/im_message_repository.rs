@@ -0,0 +1,441 @@
+use entity::im_message;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ColumnTrait, Condition, ConnectionTrait, EntityTrait, IntoActiveModel, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect, Select,
+};
+use serde_json::{Map, Value, json};
+
+use crate::error::CommonError;
+use crate::pojo::common::{CursorPageParam, CursorPageResp};
+use crate::repository::{im_message_fts_repository, im_upload_queue_repository};
+
+/// 撤回消息的类型值，与前端 `MsgEnum.RECALL` 保持一致
+pub const RECALL_MESSAGE_TYPE: u8 = 2;
+
+/// 聊天记录查询条件
+#[derive(Debug, Default)]
+pub struct ChatHistoryFilter {
+    pub room_id: String,
+    /// 为空表示不限制消息类型
+    pub message_types: Vec<u8>,
+    pub keyword: Option<String>,
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub ascending: bool,
+}
+
+/// 保存单条消息
+///
+/// 已拿到 `event_id` 的消息按 `uniq_room_event` (login_uid, room_id, event_id) 索引 upsert，
+/// 尚未回执的本地消息按主键 (id, login_uid) upsert。
+/// `old_msg_id` 为发送前的临时消息 ID，保存时会一并移除。
+pub async fn save_message<C>(
+    db: &C,
+    message: im_message::Model,
+    old_msg_id: Option<&str>,
+) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    if let Some(old_msg_id) = old_msg_id.filter(|id| *id != message.id) {
+        im_message::Entity::delete_many()
+            .filter(im_message::Column::Id.eq(old_msg_id))
+            .filter(im_message::Column::LoginUid.eq(&message.login_uid))
+            .exec(db)
+            .await?;
+        // 上传队列仍引用临时消息 ID，上传结束时需要更新替换后的消息
+        im_upload_queue_repository::update_message_id(
+            db,
+            old_msg_id,
+            &message.id,
+            &message.login_uid,
+        )
+        .await?;
+    }
+
+    let conflict_columns = if message.event_id.is_some() {
+        // 本地待发送记录拿到 event_id 后改为按事件去重，先移除旧的待发送记录
+        im_message::Entity::delete_many()
+            .filter(im_message::Column::Id.eq(&message.id))
+            .filter(im_message::Column::LoginUid.eq(&message.login_uid))
+            .filter(im_message::Column::EventId.is_null())
+            .exec(db)
+            .await?;
+        vec![
+            im_message::Column::LoginUid,
+            im_message::Column::RoomId,
+            im_message::Column::EventId,
+        ]
+    } else {
+        vec![im_message::Column::Id, im_message::Column::LoginUid]
+    };
+
+    let mut update_columns = vec![
+        im_message::Column::Uid,
+        im_message::Column::Nickname,
+        im_message::Column::SendTime,
+        im_message::Column::MessageType,
+        im_message::Column::Body,
+        im_message::Column::UpdateTime,
+        im_message::Column::SendStatus,
+        im_message::Column::TimeBlock,
+        im_message::Column::MxcUrl,
+        im_message::Column::Sender,
+        im_message::Column::OriginServerTs,
+    ];
+    // 消息标记由 save_message_mark 单独维护，未携带时不覆盖
+    if message.message_marks.is_some() {
+        update_columns.push(im_message::Column::MessageMarks);
+    }
+    // 缩略图路径由 get_media_thumbnail 写入，未携带时不覆盖
+    if message.thumbnail_path.is_some() {
+        update_columns.push(im_message::Column::ThumbnailPath);
+    }
+
+    im_message::Entity::insert(message.clone().into_active_model())
+        .on_conflict(
+            OnConflict::columns(conflict_columns)
+                .update_columns(update_columns)
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+
+    im_message_fts_repository::index_message(db, &message).await?;
+
+    Ok(())
+}
+
+/// 按游标分页查询房间消息
+///
+/// 游标格式为 `{send_time}_{id}`，按发送时间倒序翻页，返回的列表按时间正序排列。
+pub async fn cursor_page_messages<C>(
+    db: &C,
+    room_id: &str,
+    param: CursorPageParam,
+    login_uid: &str,
+) -> Result<CursorPageResp<Vec<im_message::Model>>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let base = im_message::Entity::find()
+        .filter(im_message::Column::RoomId.eq(room_id))
+        .filter(im_message::Column::LoginUid.eq(login_uid));
+
+    let total = base.clone().count(db).await?;
+
+    let mut query = base
+        .order_by_desc(im_message::Column::SendTime)
+        .order_by_desc(im_message::Column::Id);
+
+    if let Some((send_time, id)) = parse_cursor(&param.cursor) {
+        query = query.filter(
+            Condition::any()
+                .add(im_message::Column::SendTime.lt(send_time))
+                .add(
+                    Condition::all()
+                        .add(im_message::Column::SendTime.eq(send_time))
+                        .add(im_message::Column::Id.lt(id)),
+                ),
+        );
+    }
+
+    let page_size = u64::from(param.page_size.max(1));
+    let mut list = query.limit(page_size + 1).all(db).await?;
+
+    let is_last = list.len() as u64 <= page_size;
+    list.truncate(page_size as usize);
+
+    let cursor = if is_last {
+        String::new()
+    } else {
+        list.last().map(build_cursor).unwrap_or_default()
+    };
+
+    list.reverse();
+
+    Ok(CursorPageResp {
+        cursor,
+        is_last,
+        list: Some(list),
+        total,
+    })
+}
+
+fn chat_history_select(filter: &ChatHistoryFilter, login_uid: &str) -> Select<im_message::Entity> {
+    let mut query = im_message::Entity::find()
+        .filter(im_message::Column::RoomId.eq(&filter.room_id))
+        .filter(im_message::Column::LoginUid.eq(login_uid));
+
+    if !filter.message_types.is_empty() {
+        query = query.filter(im_message::Column::MessageType.is_in(filter.message_types.clone()));
+    }
+    if let Some(keyword) = filter
+        .keyword
+        .as_deref()
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+    {
+        query = query.filter(im_message::Column::Body.contains(keyword));
+    }
+    if let Some(start_time) = filter.start_time {
+        query = query.filter(im_message::Column::SendTime.gte(start_time));
+    }
+    if let Some(end_time) = filter.end_time {
+        query = query.filter(im_message::Column::SendTime.lte(end_time));
+    }
+    query
+}
+
+/// 统计符合条件的聊天记录条数
+pub async fn count_chat_history<C>(
+    db: &C,
+    filter: &ChatHistoryFilter,
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(chat_history_select(filter, login_uid).count(db).await?)
+}
+
+/// 按条件分页查询聊天记录，返回 (消息列表, 是否还有更多)
+pub async fn query_chat_history<C>(
+    db: &C,
+    filter: ChatHistoryFilter,
+    login_uid: &str,
+    page: u64,
+    page_size: u64,
+) -> Result<(Vec<im_message::Model>, bool), CommonError>
+where
+    C: ConnectionTrait,
+{
+    let query = chat_history_select(&filter, login_uid);
+    let query = if filter.ascending {
+        query
+            .order_by_asc(im_message::Column::SendTime)
+            .order_by_asc(im_message::Column::Id)
+    } else {
+        query
+            .order_by_desc(im_message::Column::SendTime)
+            .order_by_desc(im_message::Column::Id)
+    };
+
+    let page_size = page_size.max(1);
+    let mut list = query
+        .offset(page.saturating_sub(1) * page_size)
+        .limit(page_size + 1)
+        .all(db)
+        .await?;
+
+    let has_more = list.len() as u64 > page_size;
+    list.truncate(page_size as usize);
+
+    Ok((list, has_more))
+}
+
+/// 更新消息撤回/删除(redaction)状态，`message_id` 可以是本地消息 ID 或 Matrix event_id
+pub async fn update_message_recall_status<C>(
+    db: &C,
+    message_id: &str,
+    message_type: u8,
+    message_body: &str,
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let body = json!({ "content": message_body }).to_string();
+
+    let result = im_message::Entity::update_many()
+        .col_expr(im_message::Column::MessageType, Expr::value(message_type))
+        .col_expr(im_message::Column::Body, Expr::value(body))
+        .col_expr(
+            im_message::Column::UpdateTime,
+            Expr::value(chrono::Utc::now().timestamp_millis()),
+        )
+        .filter(
+            Condition::any()
+                .add(im_message::Column::Id.eq(message_id))
+                .add(im_message::Column::EventId.eq(message_id)),
+        )
+        .filter(im_message::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    im_message_fts_repository::index_message_by_id(db, message_id, login_uid).await?;
+
+    Ok(result.rows_affected)
+}
+
+/// 更新消息发送状态，`message_id` 可以是本地消息 ID 或 Matrix event_id
+pub async fn update_message_send_status<C>(
+    db: &C,
+    message_id: &str,
+    login_uid: &str,
+    send_status: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_message::Entity::update_many()
+        .col_expr(im_message::Column::SendStatus, Expr::value(send_status))
+        .col_expr(
+            im_message::Column::UpdateTime,
+            Expr::value(chrono::Utc::now().timestamp_millis()),
+        )
+        .filter(
+            Condition::any()
+                .add(im_message::Column::Id.eq(message_id))
+                .add(im_message::Column::EventId.eq(message_id)),
+        )
+        .filter(im_message::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+/// 记录消息的缩略图路径，`message_id` 可以是本地消息 ID 或 Matrix event_id
+pub async fn update_message_thumbnail_path<C>(
+    db: &C,
+    message_id: &str,
+    login_uid: &str,
+    thumbnail_path: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_message::Entity::update_many()
+        .col_expr(
+            im_message::Column::ThumbnailPath,
+            Expr::value(thumbnail_path),
+        )
+        .filter(
+            Condition::any()
+                .add(im_message::Column::Id.eq(message_id))
+                .add(im_message::Column::EventId.eq(message_id)),
+        )
+        .filter(im_message::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+/// 更新消息的某个标记计数
+///
+/// `user_marked` 为 `None` 时保留当前用户原有的标记状态
+pub async fn update_message_mark<C>(
+    db: &C,
+    message_id: &str,
+    login_uid: &str,
+    mark_type: &str,
+    mark_count: u64,
+    user_marked: Option<bool>,
+) -> Result<bool, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let Some(message) = im_message::Entity::find()
+        .filter(
+            Condition::any()
+                .add(im_message::Column::Id.eq(message_id))
+                .add(im_message::Column::EventId.eq(message_id)),
+        )
+        .filter(im_message::Column::LoginUid.eq(login_uid))
+        .one(db)
+        .await?
+    else {
+        return Ok(false);
+    };
+
+    let mut marks = message
+        .message_marks
+        .as_deref()
+        .and_then(|marks| serde_json::from_str::<Map<String, Value>>(marks).ok())
+        .unwrap_or_default();
+
+    let previous_marked = marks
+        .get(mark_type)
+        .and_then(|mark| mark.get("userMarked"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    marks.insert(
+        mark_type.to_string(),
+        json!({
+            "count": mark_count,
+            "userMarked": user_marked.unwrap_or(previous_marked),
+        }),
+    );
+
+    im_message::Entity::update_many()
+        .col_expr(
+            im_message::Column::MessageMarks,
+            Expr::value(Value::Object(marks).to_string()),
+        )
+        .col_expr(
+            im_message::Column::UpdateTime,
+            Expr::value(chrono::Utc::now().timestamp_millis()),
+        )
+        .filter(im_message::Column::Id.eq(&message.id))
+        .filter(im_message::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(true)
+}
+
+/// 删除单条消息
+pub async fn delete_message<C>(
+    db: &C,
+    message_id: &str,
+    room_id: Option<&str>,
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let mut delete = im_message::Entity::delete_many()
+        .filter(
+            Condition::any()
+                .add(im_message::Column::Id.eq(message_id))
+                .add(im_message::Column::EventId.eq(message_id)),
+        )
+        .filter(im_message::Column::LoginUid.eq(login_uid));
+
+    if let Some(room_id) = room_id {
+        delete = delete.filter(im_message::Column::RoomId.eq(room_id));
+    }
+
+    Ok(delete.exec(db).await?.rows_affected)
+}
+
+/// 删除房间内当前账号的所有消息
+pub async fn delete_room_messages<C>(
+    db: &C,
+    room_id: &str,
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_message::Entity::delete_many()
+        .filter(im_message::Column::RoomId.eq(room_id))
+        .filter(im_message::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+pub(crate) fn build_cursor(message: &im_message::Model) -> String {
+    format!("{}_{}", message.send_time.unwrap_or_default(), message.id)
+}
+
+pub(crate) fn parse_cursor(cursor: &str) -> Option<(i64, &str)> {
+    let (send_time, id) = cursor.split_once('_')?;
+    Some((send_time.parse().ok()?, id))
+}
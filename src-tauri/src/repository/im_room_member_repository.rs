@@ -0,0 +1,229 @@
+use entity::im_room_member;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ColumnTrait, Condition, ConnectionTrait, EntityTrait, IntoActiveModel, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect,
+};
+
+use crate::error::CommonError;
+use crate::pojo::common::{CursorPageParam, CursorPageResp};
+
+/// 单条 SQL 中的最大行数，避免超出 SQLite 的参数数量限制
+const BATCH_SIZE: usize = 200;
+
+/// 名称游标中名称与 ID 的分隔符，Matrix ID 中不会出现该字符
+const NAME_CURSOR_SEPARATOR: char = '\u{1f}';
+
+/// 成员分页的排序方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemberOrder {
+    /// 按名称正序
+    #[default]
+    Name,
+    /// 按最后活跃时间倒序
+    LastOptTime,
+}
+
+/// 成员在缓存中的主键
+///
+/// 同一用户可以在多个房间中，主键由房间 ID 与用户 ID 组成。
+pub fn member_id(room_id: &str, uid: &str) -> String {
+    format!("{room_id}_{uid}")
+}
+
+/// 查询房间的全部成员，按名称排序
+pub async fn find_room_members<C>(
+    db: &C,
+    room_id: &str,
+    login_uid: &str,
+) -> Result<Vec<im_room_member::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_room_member::Entity::find()
+        .filter(im_room_member::Column::RoomId.eq(room_id))
+        .filter(im_room_member::Column::LoginUid.eq(login_uid))
+        .order_by_asc(im_room_member::Column::Name)
+        .order_by_asc(im_room_member::Column::Id)
+        .all(db)
+        .await?)
+}
+
+/// 按用户 ID 查询房间成员
+pub async fn find_members_by_uids<C>(
+    db: &C,
+    room_id: &str,
+    uids: &[String],
+    login_uid: &str,
+) -> Result<Vec<im_room_member::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let mut members = Vec::with_capacity(uids.len());
+    for chunk in uids.chunks(BATCH_SIZE) {
+        members.extend(
+            im_room_member::Entity::find()
+                .filter(im_room_member::Column::RoomId.eq(room_id))
+                .filter(im_room_member::Column::LoginUid.eq(login_uid))
+                .filter(im_room_member::Column::Uid.is_in(chunk.iter().cloned()))
+                .all(db)
+                .await?,
+        );
+    }
+    Ok(members)
+}
+
+/// 批量写入或更新成员，按主键 (id, login_uid) upsert
+pub async fn save_members<C>(db: &C, members: Vec<im_room_member::Model>) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    for chunk in members.chunks(BATCH_SIZE) {
+        im_room_member::Entity::insert_many(
+            chunk
+                .iter()
+                .cloned()
+                .map(IntoActiveModel::into_active_model),
+        )
+        .on_conflict(
+            OnConflict::columns([im_room_member::Column::Id, im_room_member::Column::LoginUid])
+                .update_columns([
+                    im_room_member::Column::RoomId,
+                    im_room_member::Column::Uid,
+                    im_room_member::Column::Account,
+                    im_room_member::Column::MyName,
+                    im_room_member::Column::ActiveStatus,
+                    im_room_member::Column::GroupRole,
+                    im_room_member::Column::LocPlace,
+                    im_room_member::Column::LastOptTime,
+                    im_room_member::Column::Name,
+                    im_room_member::Column::Avatar,
+                    im_room_member::Column::UserStateId,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+    }
+    Ok(())
+}
+
+/// 删除房间中的指定成员，返回删除条数
+pub async fn delete_members<C>(
+    db: &C,
+    room_id: &str,
+    uids: &[String],
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let mut deleted = 0;
+    for chunk in uids.chunks(BATCH_SIZE) {
+        deleted += im_room_member::Entity::delete_many()
+            .filter(im_room_member::Column::RoomId.eq(room_id))
+            .filter(im_room_member::Column::LoginUid.eq(login_uid))
+            .filter(im_room_member::Column::Uid.is_in(chunk.iter().cloned()))
+            .exec(db)
+            .await?
+            .rows_affected;
+    }
+    Ok(deleted)
+}
+
+/// 按游标分页查询房间成员
+///
+/// 按名称排序时游标为 `{name}\u{1f}{id}`，按最后活跃时间排序时为 `{last_opt_time}_{id}`。
+/// `keyword` 匹配名称、群昵称与用户 ID。
+pub async fn cursor_page_members<C>(
+    db: &C,
+    room_id: &str,
+    param: CursorPageParam,
+    order: MemberOrder,
+    keyword: Option<&str>,
+    login_uid: &str,
+) -> Result<CursorPageResp<Vec<im_room_member::Model>>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let mut base = im_room_member::Entity::find()
+        .filter(im_room_member::Column::RoomId.eq(room_id))
+        .filter(im_room_member::Column::LoginUid.eq(login_uid));
+    if let Some(keyword) = keyword.map(str::trim).filter(|keyword| !keyword.is_empty()) {
+        base = base.filter(
+            Condition::any()
+                .add(im_room_member::Column::Name.contains(keyword))
+                .add(im_room_member::Column::MyName.contains(keyword))
+                .add(im_room_member::Column::Uid.contains(keyword)),
+        );
+    }
+
+    let total = base.clone().count(db).await?;
+
+    let query = match order {
+        MemberOrder::Name => {
+            let query = base
+                .order_by_asc(im_room_member::Column::Name)
+                .order_by_asc(im_room_member::Column::Id);
+            match param.cursor.rsplit_once(NAME_CURSOR_SEPARATOR) {
+                Some((name, id)) => query.filter(
+                    Condition::any()
+                        .add(im_room_member::Column::Name.gt(name))
+                        .add(
+                            Condition::all()
+                                .add(im_room_member::Column::Name.eq(name))
+                                .add(im_room_member::Column::Id.gt(id)),
+                        ),
+                ),
+                None => query,
+            }
+        }
+        MemberOrder::LastOptTime => {
+            let query = base
+                .order_by_desc(im_room_member::Column::LastOptTime)
+                .order_by_desc(im_room_member::Column::Id);
+            match parse_opt_time_cursor(&param.cursor) {
+                Some((last_opt_time, id)) => query.filter(
+                    Condition::any()
+                        .add(im_room_member::Column::LastOptTime.lt(last_opt_time))
+                        .add(
+                            Condition::all()
+                                .add(im_room_member::Column::LastOptTime.eq(last_opt_time))
+                                .add(im_room_member::Column::Id.lt(id)),
+                        ),
+                ),
+                None => query,
+            }
+        }
+    };
+
+    let page_size = u64::from(param.page_size.max(1));
+    let mut list = query.limit(page_size + 1).all(db).await?;
+
+    let is_last = list.len() as u64 <= page_size;
+    list.truncate(page_size as usize);
+
+    let cursor = match list.last() {
+        Some(member) if !is_last => build_cursor(member, order),
+        _ => String::new(),
+    };
+
+    Ok(CursorPageResp {
+        cursor,
+        is_last,
+        list: Some(list),
+        total,
+    })
+}
+
+fn build_cursor(member: &im_room_member::Model, order: MemberOrder) -> String {
+    match order {
+        MemberOrder::Name => format!("{}{NAME_CURSOR_SEPARATOR}{}", member.name, member.id),
+        MemberOrder::LastOptTime => format!("{}_{}", member.last_opt_time, member.id),
+    }
+}
+
+fn parse_opt_time_cursor(cursor: &str) -> Option<(i64, &str)> {
+    let (last_opt_time, id) = cursor.split_once('_')?;
+    Some((last_opt_time.parse().ok()?, id))
+}
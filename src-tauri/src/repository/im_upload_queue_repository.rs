@@ -0,0 +1,254 @@
+use entity::im_upload_queue;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder,
+    QuerySelect,
+};
+
+use crate::error::CommonError;
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_UPLOADING: &str = "uploading";
+pub const STATUS_SUCCESS: &str = "success";
+pub const STATUS_FAIL: &str = "fail";
+
+/// 加入上传队列
+pub async fn insert<C>(db: &C, item: im_upload_queue::Model) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    im_upload_queue::Entity::insert(item.into_active_model())
+        .exec_without_returning(db)
+        .await?;
+
+    Ok(())
+}
+
+/// 查询单个队列项
+pub async fn find_by_id<C>(
+    db: &C,
+    id: &str,
+    login_uid: &str,
+) -> Result<Option<im_upload_queue::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_upload_queue::Entity::find_by_id(id)
+        .filter(im_upload_queue::Column::LoginUid.eq(login_uid))
+        .one(db)
+        .await?)
+}
+
+/// 查询当前账号的上传队列，`room_id` 为空时返回全部房间
+pub async fn list<C>(
+    db: &C,
+    login_uid: &str,
+    room_id: Option<&str>,
+) -> Result<Vec<im_upload_queue::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let mut query = im_upload_queue::Entity::find()
+        .filter(im_upload_queue::Column::LoginUid.eq(login_uid))
+        .order_by_asc(im_upload_queue::Column::CreateTime);
+
+    if let Some(room_id) = room_id {
+        query = query.filter(im_upload_queue::Column::RoomId.eq(room_id));
+    }
+
+    Ok(query.all(db).await?)
+}
+
+/// 查询已到重试时间的待上传项，按计划时间先后排序
+pub async fn find_due<C>(
+    db: &C,
+    login_uid: &str,
+    now: i64,
+    limit: u64,
+) -> Result<Vec<im_upload_queue::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_upload_queue::Entity::find()
+        .filter(im_upload_queue::Column::LoginUid.eq(login_uid))
+        .filter(im_upload_queue::Column::Status.eq(STATUS_PENDING))
+        .filter(im_upload_queue::Column::NextAttemptAt.lte(now))
+        .order_by_asc(im_upload_queue::Column::NextAttemptAt)
+        .limit(limit)
+        .all(db)
+        .await?)
+}
+
+/// 最近一次计划上传的时间
+pub async fn next_attempt_at<C>(db: &C, login_uid: &str) -> Result<Option<i64>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_upload_queue::Entity::find()
+        .select_only()
+        .column_as(
+            im_upload_queue::Column::NextAttemptAt.min(),
+            "next_attempt_at",
+        )
+        .filter(im_upload_queue::Column::LoginUid.eq(login_uid))
+        .filter(im_upload_queue::Column::Status.eq(STATUS_PENDING))
+        .into_tuple::<Option<i64>>()
+        .one(db)
+        .await?
+        .flatten())
+}
+
+/// 将待上传项标记为上传中，返回是否抢占成功
+pub async fn mark_uploading<C>(db: &C, id: &str, now: i64) -> Result<bool, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_upload_queue::Entity::update_many()
+        .col_expr(
+            im_upload_queue::Column::Status,
+            Expr::value(STATUS_UPLOADING),
+        )
+        .col_expr(im_upload_queue::Column::UpdateTime, Expr::value(now))
+        .filter(im_upload_queue::Column::Id.eq(id))
+        .filter(im_upload_queue::Column::Status.eq(STATUS_PENDING))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected == 1)
+}
+
+/// 上传成功后移除队列项，结果已通过事件通知前端
+pub async fn remove_succeeded<C>(db: &C, id: &str) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    im_upload_queue::Entity::delete_by_id(id).exec(db).await?;
+
+    Ok(())
+}
+
+/// 本地消息被服务端回显替换后，队列项改为引用新的消息 ID
+pub async fn update_message_id<C>(
+    db: &C,
+    old_message_id: &str,
+    new_message_id: &str,
+    login_uid: &str,
+) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_upload_queue::Entity::update_many()
+        .col_expr(
+            im_upload_queue::Column::MessageId,
+            Expr::value(new_message_id),
+        )
+        .filter(im_upload_queue::Column::MessageId.eq(old_message_id))
+        .filter(im_upload_queue::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+/// 记录失败并安排下一次重试
+pub async fn schedule_retry<C>(
+    db: &C,
+    id: &str,
+    attempts: i32,
+    next_attempt_at: i64,
+    error: &str,
+    now: i64,
+) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    im_upload_queue::Entity::update_many()
+        .col_expr(im_upload_queue::Column::Status, Expr::value(STATUS_PENDING))
+        .col_expr(im_upload_queue::Column::Attempts, Expr::value(attempts))
+        .col_expr(
+            im_upload_queue::Column::NextAttemptAt,
+            Expr::value(next_attempt_at),
+        )
+        .col_expr(im_upload_queue::Column::LastError, Expr::value(error))
+        .col_expr(im_upload_queue::Column::UpdateTime, Expr::value(now))
+        .filter(im_upload_queue::Column::Id.eq(id))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// 标记上传最终失败
+pub async fn mark_failed<C>(
+    db: &C,
+    id: &str,
+    attempts: i32,
+    error: &str,
+    now: i64,
+) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    im_upload_queue::Entity::update_many()
+        .col_expr(im_upload_queue::Column::Status, Expr::value(STATUS_FAIL))
+        .col_expr(im_upload_queue::Column::Attempts, Expr::value(attempts))
+        .col_expr(im_upload_queue::Column::LastError, Expr::value(error))
+        .col_expr(im_upload_queue::Column::UpdateTime, Expr::value(now))
+        .filter(im_upload_queue::Column::Id.eq(id))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// 重新排队失败的上传，返回是否找到对应的失败项
+pub async fn requeue_failed<C>(
+    db: &C,
+    id: &str,
+    login_uid: &str,
+    now: i64,
+) -> Result<bool, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_upload_queue::Entity::update_many()
+        .col_expr(im_upload_queue::Column::Status, Expr::value(STATUS_PENDING))
+        .col_expr(im_upload_queue::Column::Attempts, Expr::value(0))
+        .col_expr(im_upload_queue::Column::NextAttemptAt, Expr::value(now))
+        .col_expr(im_upload_queue::Column::UpdateTime, Expr::value(now))
+        .filter(im_upload_queue::Column::Id.eq(id))
+        .filter(im_upload_queue::Column::LoginUid.eq(login_uid))
+        .filter(im_upload_queue::Column::Status.eq(STATUS_FAIL))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected == 1)
+}
+
+/// 启动时把上次异常退出时仍在上传的项恢复为待上传
+pub async fn reset_uploading<C>(db: &C) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_upload_queue::Entity::update_many()
+        .col_expr(im_upload_queue::Column::Status, Expr::value(STATUS_PENDING))
+        .filter(im_upload_queue::Column::Status.eq(STATUS_UPLOADING))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+/// 删除队列项
+pub async fn delete<C>(db: &C, id: &str, login_uid: &str) -> Result<u64, CommonError>
+where
+    C: ConnectionTrait,
+{
+    let result = im_upload_queue::Entity::delete_many()
+        .filter(im_upload_queue::Column::Id.eq(id))
+        .filter(im_upload_queue::Column::LoginUid.eq(login_uid))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
@@ -0,0 +1,227 @@
+use entity::{
+    im_config, im_contact, im_media_cache, im_message, im_room, im_room_member, im_upload_queue,
+    im_user,
+};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ActiveValue, ColumnTrait, Condition, ConnectionTrait, EntityTrait, QueryFilter, QueryOrder,
+};
+
+use crate::error::CommonError;
+
+/// 保存用户的令牌引用，用户不存在时创建
+///
+/// 令牌本身保存在系统钥匙串或加密文件中，这里只写入引用。
+pub async fn save_token_refs<C>(
+    db: &C,
+    uid: &str,
+    token_ref: Option<String>,
+    refresh_token_ref: Option<String>,
+) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    let now = chrono::Utc::now().timestamp_millis();
+    let user = im_user::ActiveModel {
+        id: ActiveValue::Set(uid.to_string()),
+        token: ActiveValue::Set(token_ref),
+        refresh_token: ActiveValue::Set(refresh_token_ref),
+        create_time: ActiveValue::Set(Some(now)),
+        update_time: ActiveValue::Set(Some(now)),
+        is_init: ActiveValue::Set(false),
+        ..Default::default()
+    };
+
+    im_user::Entity::insert(user)
+        .on_conflict(
+            OnConflict::column(im_user::Column::Id)
+                .update_columns([
+                    im_user::Column::Token,
+                    im_user::Column::RefreshToken,
+                    im_user::Column::UpdateTime,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+
+    Ok(())
+}
+
+/// 查询用户
+pub async fn find_user<C>(db: &C, uid: &str) -> Result<Option<im_user::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_user::Entity::find_by_id(uid).one(db).await?)
+}
+
+/// 查询最近使用的已登录用户，用于重启后恢复登录状态
+pub async fn find_last_login_user<C>(db: &C) -> Result<Option<im_user::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_user::Entity::find()
+        .filter(im_user::Column::Token.is_not_null())
+        .order_by_desc(im_user::Column::LastActiveTime)
+        .order_by_desc(im_user::Column::UpdateTime)
+        .one(db)
+        .await?)
+}
+
+/// 查询本机的全部账号，按最后使用时间倒序
+///
+/// 保存过令牌或 homeserver 的用户即为本机账号。
+pub async fn find_accounts<C>(db: &C) -> Result<Vec<im_user::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_user::Entity::find()
+        .filter(
+            Condition::any()
+                .add(im_user::Column::Token.is_not_null())
+                .add(im_user::Column::Homeserver.is_not_null()),
+        )
+        .order_by_desc(im_user::Column::LastActiveTime)
+        .order_by_desc(im_user::Column::UpdateTime)
+        .all(db)
+        .await?)
+}
+
+/// 保存账号资料，用户不存在时创建；为空的字段不覆盖已有值
+pub async fn save_account<C>(
+    db: &C,
+    uid: &str,
+    homeserver: Option<String>,
+    name: Option<String>,
+    avatar: Option<String>,
+) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut update_columns = vec![im_user::Column::UpdateTime];
+    if homeserver.is_some() {
+        update_columns.push(im_user::Column::Homeserver);
+    }
+    if name.is_some() {
+        update_columns.push(im_user::Column::Name);
+    }
+    if avatar.is_some() {
+        update_columns.push(im_user::Column::Avatar);
+    }
+
+    let user = im_user::ActiveModel {
+        id: ActiveValue::Set(uid.to_string()),
+        homeserver: ActiveValue::Set(homeserver),
+        name: ActiveValue::Set(name),
+        avatar: ActiveValue::Set(avatar),
+        create_time: ActiveValue::Set(Some(now)),
+        update_time: ActiveValue::Set(Some(now)),
+        is_init: ActiveValue::Set(false),
+        ..Default::default()
+    };
+
+    im_user::Entity::insert(user)
+        .on_conflict(
+            OnConflict::column(im_user::Column::Id)
+                .update_columns(update_columns)
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+
+    Ok(())
+}
+
+/// 记录账号的最后使用时间
+pub async fn touch_account<C>(db: &C, uid: &str) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    im_user::Entity::update_many()
+        .col_expr(
+            im_user::Column::LastActiveTime,
+            Expr::value(chrono::Utc::now().timestamp_millis()),
+        )
+        .filter(im_user::Column::Id.eq(uid))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// 删除账号在所有 im_* 表中的数据，全文索引由 im_message 的删除触发器同步清理
+pub async fn delete_account_data<C>(db: &C, uid: &str) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    im_message::Entity::delete_many()
+        .filter(im_message::Column::LoginUid.eq(uid))
+        .exec(db)
+        .await?;
+    im_room::Entity::delete_many()
+        .filter(im_room::Column::LoginUid.eq(uid))
+        .exec(db)
+        .await?;
+    im_room_member::Entity::delete_many()
+        .filter(im_room_member::Column::LoginUid.eq(uid))
+        .exec(db)
+        .await?;
+    im_contact::Entity::delete_many()
+        .filter(im_contact::Column::LoginUid.eq(uid))
+        .exec(db)
+        .await?;
+    im_config::Entity::delete_many()
+        .filter(im_config::Column::LoginUid.eq(uid))
+        .exec(db)
+        .await?;
+    im_upload_queue::Entity::delete_many()
+        .filter(im_upload_queue::Column::LoginUid.eq(uid))
+        .exec(db)
+        .await?;
+    im_media_cache::Entity::delete_many()
+        .filter(im_media_cache::Column::LoginUid.eq(uid))
+        .exec(db)
+        .await?;
+    im_user::Entity::delete_by_id(uid).exec(db).await?;
+
+    Ok(())
+}
+
+/// 查询令牌列不为空的用户，用于把旧版本的明文令牌迁移到安全存储
+pub async fn find_users_with_tokens<C>(db: &C) -> Result<Vec<im_user::Model>, CommonError>
+where
+    C: ConnectionTrait,
+{
+    Ok(im_user::Entity::find()
+        .filter(
+            Condition::any()
+                .add(im_user::Column::Token.is_not_null())
+                .add(im_user::Column::RefreshToken.is_not_null()),
+        )
+        .all(db)
+        .await?)
+}
+
+/// 清空用户的令牌引用
+pub async fn clear_token_refs<C>(db: &C, uid: &str) -> Result<(), CommonError>
+where
+    C: ConnectionTrait,
+{
+    im_user::Entity::update_many()
+        .col_expr(im_user::Column::Token, Expr::value(Option::<String>::None))
+        .col_expr(
+            im_user::Column::RefreshToken,
+            Expr::value(Option::<String>::None),
+        )
+        .col_expr(
+            im_user::Column::UpdateTime,
+            Expr::value(chrono::Utc::now().timestamp_millis()),
+        )
+        .filter(im_user::Column::Id.eq(uid))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,7 @@
+pub mod im_config_repository;
+pub mod im_media_cache_repository;
+pub mod im_message_fts_repository;
+pub mod im_message_repository;
+pub mod im_room_member_repository;
+pub mod im_upload_queue_repository;
+pub mod im_user_repository;
@@ -1,6 +1,7 @@
 //! Application state management
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -13,6 +14,9 @@ pub struct AppConfig {
     pub websocket_url: String,
     /// Application version
     pub version: String,
+    /// Access token of the logged-in Matrix session
+    #[serde(skip_serializing)]
+    pub access_token: Option<String>,
 }
 
 impl Default for AppConfig {
@@ -21,6 +25,7 @@ impl Default for AppConfig {
             homeserver: "https://matrix.org".to_string(),
             websocket_url: "ws://localhost:8080".to_string(),
             version: "1.0.0".to_string(),
+            access_token: None,
         }
     }
 }
@@ -31,6 +36,8 @@ pub struct AppState {
     pub http_client: reqwest::Client,
     /// Application configuration
     pub config: Arc<Mutex<AppConfig>>,
+    /// Whether each homeserver advertises authenticated media (Matrix v1.11)
+    pub authenticated_media: Mutex<HashMap<String, bool>>,
 }
 
 impl Default for AppState {
@@ -46,6 +53,7 @@ impl AppState {
         Self {
             http_client: reqwest::Client::new(),
             config: Arc::new(Mutex::new(AppConfig::default())),
+            authenticated_media: Mutex::new(HashMap::new()),
         }
     }
 
@@ -53,4 +61,9 @@ impl AppState {
     pub async fn homeserver(&self) -> String {
         self.config.lock().await.homeserver.clone()
     }
+
+    /// Get the current access token
+    pub async fn access_token(&self) -> Option<String> {
+        self.config.lock().await.access_token.clone()
+    }
 }
@@ -0,0 +1,104 @@
+//! 每个账号独立的本地文件：媒体缓存目录与日志文件
+//!
+//! 账号目录名由用户 ID 转换而来，同一账号在媒体缓存与日志目录下使用相同的名称。
+//! 登录后的日志只写入当前账号的日志文件，公共日志只保留未登录期间的记录，删除账号时不会在
+//! 公共日志中留下该账号的记录。切换账号时通过 [`set_account`] 切换日志文件。
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use sha2::{Digest, Sha256};
+use tracing::log::Record;
+
+/// 未登录时使用的目录名
+const GUEST_DIR: &str = "guest";
+
+/// 日志目录下存放各账号日志的子目录
+const ACCOUNT_LOG_DIR: &str = "accounts";
+
+const ACCOUNT_LOG_FILE: &str = "account.log";
+
+/// 单个账号日志文件的大小上限，超出后在切换到该账号时轮转
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+static LOG_ROOT: OnceLock<PathBuf> = OnceLock::new();
+static ACCOUNT_LOG: Mutex<Option<LineWriter<File>>> = Mutex::new(None);
+
+/// 账号的目录名
+///
+/// 保留用户 ID 中的可读部分便于排查问题，并附加哈希避免不同用户 ID 转换后重名。
+pub fn account_dir_name(uid: &str) -> String {
+    if uid.is_empty() {
+        return GUEST_DIR.to_string();
+    }
+    let readable: String = uid
+        .trim_start_matches('@')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let hash = Sha256::digest(uid.as_bytes());
+    let suffix: String = hash[..4].iter().map(|b| format!("{b:02x}")).collect();
+    format!("{readable}_{suffix}")
+}
+
+/// 设置日志根目录，应用启动时调用一次
+pub fn init_log_root(log_dir: PathBuf) {
+    let _ = LOG_ROOT.set(log_dir);
+}
+
+/// 账号的日志目录
+pub fn account_log_dir(uid: &str) -> Option<PathBuf> {
+    LOG_ROOT
+        .get()
+        .map(|root| root.join(ACCOUNT_LOG_DIR).join(account_dir_name(uid)))
+}
+
+fn open_log(dir: &Path) -> std::io::Result<LineWriter<File>> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(ACCOUNT_LOG_FILE);
+    if fs::metadata(&path).is_ok_and(|metadata| metadata.len() > MAX_LOG_SIZE) {
+        fs::rename(&path, dir.join(format!("{ACCOUNT_LOG_FILE}.1")))?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(LineWriter::new(file))
+}
+
+/// 切换当前账号的日志文件，`None` 表示退出登录后不再写入账号日志
+pub fn set_account(uid: Option<&str>) {
+    let writer = uid
+        .filter(|uid| !uid.is_empty())
+        .and_then(account_log_dir)
+        .and_then(|dir| match open_log(&dir) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                // 此时不能通过日志插件输出，否则会再次进入 write
+                eprintln!("Failed to open account log in {}: {e}", dir.display());
+                None
+            }
+        });
+    *ACCOUNT_LOG.lock().unwrap_or_else(|e| e.into_inner()) = writer;
+}
+
+/// 是否正在写入账号日志，公共日志的文件输出据此跳过账号期间的记录
+pub fn has_account_log() -> bool {
+    ACCOUNT_LOG
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some()
+}
+
+/// 日志插件的输出目标，记录已由插件格式化
+pub fn write(record: &Record) {
+    let mut guard = ACCOUNT_LOG.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(writer) = guard.as_mut() {
+        let _ = writeln!(writer, "{}", record.args());
+    }
+}
@@ -0,0 +1,362 @@
+//! Matrix 端到端加密附件（`EncryptedFile`）的校验与解密
+//!
+//! 附件使用 AES-256-CTR 加密，密钥以 JWK 形式随事件下发，
+//! `hashes.sha256` 为密文的 SHA-256。解密时必须先校验密文哈希，再写出明文；
+//! 上传时边加密边计算哈希，生成对应的 `EncryptedFile`。
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use aes::Aes256;
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::error::AppError;
+
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+/// 每次读取的块大小
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// JWK 格式的对称密钥
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonWebKey {
+    pub kty: String,
+    #[serde(default)]
+    pub key_ops: Vec<String>,
+    pub alg: String,
+    /// base64url 编码的密钥
+    pub k: String,
+    #[serde(default)]
+    pub ext: bool,
+}
+
+/// 加密附件描述，对应事件内容中的 `file` / `thumbnail_file`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedFile {
+    pub url: String,
+    pub key: JsonWebKey,
+    /// base64 编码的 16 字节初始计数器
+    pub iv: String,
+    /// 算法 -> base64 编码的密文摘要
+    pub hashes: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub v: Option<String>,
+}
+
+/// 从 `EncryptedFile` 解析出的密钥材料
+pub struct AttachmentCipher {
+    key: [u8; 32],
+    iv: [u8; 16],
+    sha256: Vec<u8>,
+}
+
+/// 边加密边计算密文哈希的流式加密器
+pub struct AttachmentEncryptor {
+    cipher: Aes256Ctr,
+    hasher: Sha256,
+}
+
+impl AttachmentEncryptor {
+    /// 原地加密一段明文
+    pub fn encrypt(&mut self, chunk: &mut [u8]) {
+        self.cipher.apply_keystream(chunk);
+        self.hasher.update(&*chunk);
+    }
+
+    /// 返回已加密内容的 SHA-256
+    pub fn finalize(self) -> Vec<u8> {
+        self.hasher.finalize().to_vec()
+    }
+}
+
+/// 兼容有无填充、标准与 URL 安全两种 base64 字母表
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim_end_matches('=');
+    STANDARD_NO_PAD
+        .decode(value)
+        .or_else(|_| URL_SAFE_NO_PAD.decode(value))
+        .ok()
+}
+
+impl AttachmentCipher {
+    /// 校验 `EncryptedFile` 的版本、算法与密钥长度
+    pub fn new(file: &EncryptedFile) -> Result<Self, AppError> {
+        if let Some(version) = file.v.as_deref()
+            && version != "v2"
+        {
+            return Err(AppError::Decryption(format!(
+                "Unsupported encrypted file version: {version}"
+            )));
+        }
+        if file.key.kty != "oct" || file.key.alg != "A256CTR" {
+            return Err(AppError::Decryption(format!(
+                "Unsupported key: kty={}, alg={}",
+                file.key.kty, file.key.alg
+            )));
+        }
+        if !file.key.key_ops.is_empty() && !file.key.key_ops.iter().any(|op| op == "decrypt") {
+            return Err(AppError::Decryption(
+                "Key is not allowed to decrypt".to_string(),
+            ));
+        }
+
+        let key = decode_base64(&file.key.k)
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .ok_or_else(|| AppError::Decryption("Invalid AES-256 key".to_string()))?;
+        let iv = decode_base64(&file.iv)
+            .and_then(|iv| <[u8; 16]>::try_from(iv).ok())
+            .ok_or_else(|| AppError::Decryption("Invalid IV".to_string()))?;
+        let sha256 = file
+            .hashes
+            .get("sha256")
+            .and_then(|hash| decode_base64(hash))
+            .filter(|hash| hash.len() == 32)
+            .ok_or_else(|| AppError::Decryption("Missing SHA-256 hash".to_string()))?;
+
+        Ok(Self { key, iv, sha256 })
+    }
+
+    /// 为新上传的附件生成随机密钥
+    ///
+    /// 按规范只随机 IV 的高 64 位，低 64 位计数器从 0 开始。
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        let mut iv = [0u8; 16];
+        let mut rng = rand::rng();
+        rng.fill_bytes(&mut key);
+        rng.fill_bytes(&mut iv[..8]);
+
+        Self {
+            key,
+            iv,
+            sha256: Vec::new(),
+        }
+    }
+
+    /// 创建流式加密器
+    pub fn encryptor(&self) -> AttachmentEncryptor {
+        AttachmentEncryptor {
+            cipher: Aes256Ctr::new(&self.key.into(), &self.iv.into()),
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// 生成上传后写入事件内容的 `EncryptedFile`
+    pub fn to_encrypted_file(&self, url: String, sha256: &[u8]) -> EncryptedFile {
+        EncryptedFile {
+            url,
+            key: JsonWebKey {
+                kty: "oct".to_string(),
+                key_ops: vec!["encrypt".to_string(), "decrypt".to_string()],
+                alg: "A256CTR".to_string(),
+                k: URL_SAFE_NO_PAD.encode(self.key),
+                ext: true,
+            },
+            iv: STANDARD_NO_PAD.encode(self.iv),
+            hashes: HashMap::from([("sha256".to_string(), STANDARD_NO_PAD.encode(sha256))]),
+            v: Some("v2".to_string()),
+        }
+    }
+
+    /// 校验密文文件的 SHA-256 是否与事件中的一致
+    pub async fn verify_file(&self, path: &Path) -> std::io::Result<bool> {
+        let mut input = fs::File::open(path).await?;
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut hasher = Sha256::new();
+
+        loop {
+            let read = input.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        Ok(hasher.finalize().as_slice() == self.sha256.as_slice())
+    }
+
+    /// 预先计算文件加密后的 SHA-256，不写出密文
+    ///
+    /// 异步上传时消息先于文件内容发送，需要提前得到 `EncryptedFile` 的哈希；
+    /// CTR 模式下同一密钥与 IV 的密文是确定的，上传时再次加密结果一致。
+    pub async fn hash_encrypted_file(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        let mut input = fs::File::open(path).await?;
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut encryptor = self.encryptor();
+
+        loop {
+            let read = input.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            encryptor.encrypt(&mut buffer[..read]);
+        }
+
+        Ok(encryptor.finalize())
+    }
+
+    /// 流式解密到目标文件，返回明文字节数
+    pub async fn decrypt_file(&self, input: &Path, output: &Path) -> std::io::Result<u64> {
+        let mut cipher = Aes256Ctr::new(&self.key.into(), &self.iv.into());
+        let mut input = fs::File::open(input).await?;
+        let mut output = fs::File::create(output).await?;
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut size = 0;
+
+        loop {
+            let read = input.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            cipher.apply_keystream(&mut buffer[..read]);
+            output.write_all(&buffer[..read]).await?;
+            size += read as u64;
+        }
+        output.flush().await?;
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(value: &str) -> Vec<u8> {
+        (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("attachment-crypto-{}-{name}", std::process::id()))
+    }
+
+    fn nist_cipher() -> AttachmentCipher {
+        // NIST SP 800-38A F.5.5 CTR-AES256.Encrypt
+        AttachmentCipher {
+            key: hex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4")
+                .try_into()
+                .unwrap(),
+            iv: hex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff").try_into().unwrap(),
+            sha256: Vec::new(),
+        }
+    }
+
+    fn encrypted_file() -> EncryptedFile {
+        AttachmentCipher::generate().to_encrypted_file("mxc://server/media".to_string(), &[7; 32])
+    }
+
+    #[test]
+    fn matches_aes_ctr_test_vector() {
+        let mut data = hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let mut encryptor = nist_cipher().encryptor();
+        // 分块加密与一次加密结果一致
+        let (first, second) = data.split_at_mut(5);
+        encryptor.encrypt(first);
+        encryptor.encrypt(second);
+        let sha256 = encryptor.finalize();
+
+        let expected = hex("601ec313775789a5b7a7f504bbf3d228f443e3ca4d62b59aca84e990cacaf5c5");
+        assert_eq!(data, expected);
+        assert_eq!(sha256, Sha256::digest(&expected).to_vec());
+    }
+
+    #[test]
+    fn generated_iv_counter_starts_at_zero() {
+        let cipher = AttachmentCipher::generate();
+        assert_eq!(cipher.iv[8..], [0; 8]);
+    }
+
+    #[test]
+    fn encrypted_file_round_trips() {
+        let cipher = AttachmentCipher::generate();
+        let file = cipher.to_encrypted_file("mxc://server/media".to_string(), &[7; 32]);
+        let parsed = AttachmentCipher::new(&file).unwrap();
+        assert_eq!(parsed.key, cipher.key);
+        assert_eq!(parsed.iv, cipher.iv);
+        assert_eq!(parsed.sha256, [7; 32]);
+    }
+
+    #[test]
+    fn accepts_padded_and_url_safe_base64() {
+        assert_eq!(decode_base64("+/8="), Some(vec![0xFB, 0xFF]));
+        assert_eq!(decode_base64("-_8"), Some(vec![0xFB, 0xFF]));
+        assert_eq!(decode_base64("!"), None);
+    }
+
+    #[test]
+    fn rejects_unsupported_files() {
+        let mut file = encrypted_file();
+        file.v = Some("v1".to_string());
+        assert!(AttachmentCipher::new(&file).is_err());
+
+        let mut file = encrypted_file();
+        file.key.alg = "A128CTR".to_string();
+        assert!(AttachmentCipher::new(&file).is_err());
+
+        let mut file = encrypted_file();
+        file.key.key_ops = vec!["encrypt".to_string()];
+        assert!(AttachmentCipher::new(&file).is_err());
+
+        let mut file = encrypted_file();
+        file.key.k = URL_SAFE_NO_PAD.encode([1; 16]);
+        assert!(AttachmentCipher::new(&file).is_err());
+
+        let mut file = encrypted_file();
+        file.iv = STANDARD_NO_PAD.encode([1; 8]);
+        assert!(AttachmentCipher::new(&file).is_err());
+
+        let mut file = encrypted_file();
+        file.hashes.clear();
+        assert!(AttachmentCipher::new(&file).is_err());
+    }
+
+    #[tokio::test]
+    async fn verifies_and_decrypts_file() {
+        let plaintext: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        let plain_path = temp_path("plain");
+        let cipher_path = temp_path("cipher");
+        let output_path = temp_path("output");
+        std::fs::write(&plain_path, &plaintext).unwrap();
+
+        let cipher = AttachmentCipher::generate();
+        let mut ciphertext = plaintext.clone();
+        let mut encryptor = cipher.encryptor();
+        encryptor.encrypt(&mut ciphertext);
+        let sha256 = encryptor.finalize();
+        std::fs::write(&cipher_path, &ciphertext).unwrap();
+
+        assert_eq!(
+            cipher.hash_encrypted_file(&plain_path).await.unwrap(),
+            sha256
+        );
+
+        let file = cipher.to_encrypted_file("mxc://server/media".to_string(), &sha256);
+        let cipher = AttachmentCipher::new(&file).unwrap();
+        assert!(cipher.verify_file(&cipher_path).await.unwrap());
+        let size = cipher
+            .decrypt_file(&cipher_path, &output_path)
+            .await
+            .unwrap();
+        assert_eq!(size, plaintext.len() as u64);
+        assert_eq!(std::fs::read(&output_path).unwrap(), plaintext);
+
+        // 密文被篡改时哈希不一致
+        ciphertext[0] ^= 1;
+        std::fs::write(&cipher_path, &ciphertext).unwrap();
+        assert!(!cipher.verify_file(&cipher_path).await.unwrap());
+
+        for path in [plain_path, cipher_path, output_path] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
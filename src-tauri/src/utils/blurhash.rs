@@ -0,0 +1,241 @@
+//! BlurHash 编解码
+//!
+//! BlurHash 把图片压缩成 20-30 个字符的 DCT 系数，Matrix 客户端通过
+//! `info` 中的 `xyz.amorgan.blurhash` 字段发送，用于媒体加载前的模糊占位图。
+//! 算法参考 <https://github.com/woltapp/blurhash/blob/master/Algorithm.md>。
+
+use std::f32::consts::PI;
+
+use image::RgbImage;
+
+use crate::error::AppError;
+
+const BASE83_CHARS: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn encode_base83(value: u32, length: u32, output: &mut String) {
+    for i in 1..=length {
+        let digit = (value / 83u32.pow(length - i)) % 83;
+        output.push(BASE83_CHARS[digit as usize] as char);
+    }
+}
+
+fn decode_base83(value: &str) -> Result<u32, AppError> {
+    value.bytes().try_fold(0u32, |acc, byte| {
+        BASE83_CHARS
+            .iter()
+            .position(|&c| c == byte)
+            .map(|digit| acc * 83 + digit as u32)
+            .ok_or_else(|| AppError::Request(format!("Invalid blurhash character: {value}")))
+    })
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        (v * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * v.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+fn sign_pow(value: f32, exp: f32) -> f32 {
+    value.abs().powf(exp).copysign(value)
+}
+
+/// 计算图片的 BlurHash，分量数取值范围为 1-9
+///
+/// 分量的计算量与像素数成正比，调用方应先把图片缩小到百像素以内。
+pub fn encode(image: &RgbImage, components_x: u32, components_y: u32) -> Result<String, AppError> {
+    if !(1..=9).contains(&components_x) || !(1..=9).contains(&components_y) {
+        return Err(AppError::Request(
+            "BlurHash components must be between 1 and 9".to_string(),
+        ));
+    }
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err(AppError::Request("Image is empty".to_string()));
+    }
+
+    let linear: Vec<[f32; 3]> = image
+        .pixels()
+        .map(|pixel| pixel.0.map(srgb_to_linear))
+        .collect();
+
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0f32; 3];
+            for y in 0..height {
+                let basis_y = (PI * j as f32 * y as f32 / height as f32).cos();
+                for x in 0..width {
+                    let basis = basis_y * (PI * i as f32 * x as f32 / width as f32).cos();
+                    let pixel = linear[(y * width + x) as usize];
+                    for (value, channel) in factor.iter_mut().zip(pixel) {
+                        *value += basis * channel;
+                    }
+                }
+            }
+            let scale = normalisation / (width * height) as f32;
+            factors.push(factor.map(|value| value * scale));
+        }
+    }
+
+    let mut hash = String::with_capacity(4 + 2 * factors.len());
+    encode_base83((components_x - 1) + (components_y - 1) * 9, 1, &mut hash);
+
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let maximum_value = if ac.is_empty() {
+        encode_base83(0, 1, &mut hash);
+        1.0
+    } else {
+        let actual_maximum = ac
+            .iter()
+            .flat_map(|factor| factor.iter())
+            .fold(0.0f32, |max, value| max.max(value.abs()));
+        let quantised_maximum = ((actual_maximum * 166.0 - 0.5).floor()).clamp(0.0, 82.0) as u32;
+        encode_base83(quantised_maximum, 1, &mut hash);
+        (quantised_maximum + 1) as f32 / 166.0
+    };
+
+    let [r, g, b] = dc.map(linear_to_srgb);
+    encode_base83((r << 16) + (g << 8) + b, 4, &mut hash);
+
+    for factor in ac {
+        let [r, g, b] = factor.map(|value| {
+            (sign_pow(value / maximum_value, 0.5) * 9.0 + 9.5)
+                .floor()
+                .clamp(0.0, 18.0) as u32
+        });
+        encode_base83(r * 19 * 19 + g * 19 + b, 2, &mut hash);
+    }
+
+    Ok(hash)
+}
+
+/// 把 BlurHash 还原为指定尺寸的图片，`punch` 用于调整对比度，默认为 1
+pub fn decode(hash: &str, width: u32, height: u32, punch: f32) -> Result<RgbImage, AppError> {
+    if hash.len() < 6 || !hash.is_ascii() {
+        return Err(AppError::Request(format!("Invalid blurhash: {hash}")));
+    }
+
+    let size_flag = decode_base83(&hash[0..1])?;
+    let components_y = size_flag / 9 + 1;
+    let components_x = size_flag % 9 + 1;
+    if hash.len() != (4 + 2 * components_x * components_y) as usize {
+        return Err(AppError::Request(format!(
+            "Invalid blurhash length: {hash}"
+        )));
+    }
+
+    let quantised_maximum = decode_base83(&hash[1..2])?;
+    let maximum_value = (quantised_maximum + 1) as f32 / 166.0 * punch;
+
+    let mut colors = Vec::with_capacity((components_x * components_y) as usize);
+    let dc = decode_base83(&hash[2..6])?;
+    colors.push([
+        srgb_to_linear((dc >> 16) as u8),
+        srgb_to_linear((dc >> 8) as u8),
+        srgb_to_linear(dc as u8),
+    ]);
+    for i in 1..(components_x * components_y) as usize {
+        let value = decode_base83(&hash[4 + i * 2..6 + i * 2])?;
+        colors.push(
+            [value / (19 * 19), (value / 19) % 19, value % 19]
+                .map(|quantised| sign_pow((quantised as f32 - 9.0) / 9.0, 2.0) * maximum_value),
+        );
+    }
+
+    let mut image = RgbImage::new(width, height);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let mut color = [0.0f32; 3];
+        for j in 0..components_y {
+            let basis_y = (PI * y as f32 * j as f32 / height as f32).cos();
+            for i in 0..components_x {
+                let basis = basis_y * (PI * x as f32 * i as f32 / width as f32).cos();
+                let component = colors[(j * components_x + i) as usize];
+                for (value, channel) in color.iter_mut().zip(component) {
+                    *value += channel * basis;
+                }
+            }
+        }
+        pixel.0 = color.map(|value| linear_to_srgb(value) as u8);
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn base83_round_trips() {
+        for value in [0, 1, 82, 83, 6888, 83u32.pow(4) - 1] {
+            let mut encoded = String::new();
+            encode_base83(value, 4, &mut encoded);
+            assert_eq!(encoded.len(), 4);
+            assert_eq!(decode_base83(&encoded).unwrap(), value);
+        }
+        assert!(decode_base83("ab\"").is_err());
+    }
+
+    #[test]
+    fn srgb_conversion_round_trips() {
+        for value in 0..=255u8 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value as u32);
+        }
+    }
+
+    #[test]
+    fn solid_color_round_trips() {
+        let color = Rgb([200, 100, 50]);
+        let hash = encode(&RgbImage::from_pixel(8, 8, color), 1, 1).unwrap();
+        assert_eq!(hash.len(), 6);
+
+        let image = decode(&hash, 4, 4, 1.0).unwrap();
+        assert_eq!(image.dimensions(), (4, 4));
+        assert!(image.pixels().all(|pixel| *pixel == color));
+    }
+
+    #[test]
+    fn gradient_keeps_direction() {
+        let image = RgbImage::from_fn(32, 16, |x, _| Rgb([(x * 8) as u8; 3]));
+        let hash = encode(&image, 4, 3).unwrap();
+        assert_eq!(hash.len(), 4 + 2 * 4 * 3);
+
+        let decoded = decode(&hash, 32, 16, 1.0).unwrap();
+        assert!(decoded.get_pixel(2, 8).0[0] + 100 < decoded.get_pixel(29, 8).0[0]);
+    }
+
+    #[test]
+    fn decodes_reference_hash() {
+        // 算法说明中的示例
+        let image = decode("LEHV6nWB2yk8pyo0adR*.7kCMdnj", 32, 32, 1.0).unwrap();
+        assert_eq!(image.dimensions(), (32, 32));
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let image = RgbImage::new(4, 4);
+        assert!(encode(&image, 0, 3).is_err());
+        assert!(encode(&image, 4, 10).is_err());
+        assert!(encode(&RgbImage::new(0, 4), 4, 3).is_err());
+
+        assert!(decode("LEHV6", 8, 8, 1.0).is_err());
+        assert!(decode("LEHV6nWB2yk8pyo0adR*.7kCMdn", 8, 8, 1.0).is_err());
+        assert!(decode("LEHV6nWB2yk8pyo0adR*.7kCMdn\"", 8, 8, 1.0).is_err());
+        assert!(decode("LEHV6nWB2yk8pyo0adR*.7kCMdné", 8, 8, 1.0).is_err());
+    }
+}
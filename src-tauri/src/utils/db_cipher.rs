@@ -0,0 +1,300 @@
+//! 本地数据库加密（SQLCipher）
+//!
+//! 数据库密钥为 32 字节随机数，以十六进制保存在 [`secret_store`] 中，通过 `PRAGMA key = "x'..'"`
+//! 以原始密钥方式打开数据库，跳过 PBKDF2 派生。旧版本的明文数据库在启动时通过
+//! `sqlcipher_export` 导出为加密副本后替换原文件。
+//!
+//! 更换密钥时新密钥先以 [`PENDING_KEY_NAME`] 保存，`PRAGMA rekey` 完成后再替换正式密钥；
+//! 中途退出时下次启动会判断哪个密钥能打开数据库。
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rand::RngCore;
+use sea_orm::sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sea_orm::sqlx::{self, ConnectOptions, Connection};
+use tracing::{error, info, warn};
+
+use crate::error::AppError;
+use crate::utils::secret_store;
+
+/// 数据库密钥在安全存储中的名称
+const DATABASE_KEY_NAME: &str = "database_key";
+
+/// 更换中的数据库密钥在安全存储中的名称
+const PENDING_KEY_NAME: &str = "database_key_pending";
+
+/// 明文 SQLite 文件的文件头
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+const KEY_SIZE: usize = 32;
+
+/// 钥匙串读取失败时的尝试次数与间隔
+const KEY_LOAD_ATTEMPTS: u32 = 3;
+const KEY_LOAD_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+fn sql_error(e: sqlx::Error) -> AppError {
+    AppError::Database(e.to_string())
+}
+
+/// 生成新的随机密钥，返回十六进制字符串
+pub fn generate_key() -> String {
+    let mut key = [0u8; KEY_SIZE];
+    rand::rng().fill_bytes(&mut key);
+    key.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// `PRAGMA key`、`PRAGMA rekey` 与 `ATTACH ... KEY` 中使用的原始密钥字面量
+pub fn key_literal(key: &str) -> String {
+    format!("\"x'{key}'\"")
+}
+
+/// 数据库文件是否为未加密的 SQLite 数据库
+///
+/// 文件不存在或为空时返回 false，SQLCipher 会直接创建加密数据库。
+pub fn is_plaintext(path: &Path) -> io::Result<bool> {
+    let mut header = [0u8; SQLITE_HEADER.len()];
+    match std::fs::File::open(path) {
+        Ok(mut file) => match file.read_exact(&mut header) {
+            Ok(()) => Ok(&header == SQLITE_HEADER),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn sidecar_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+async fn connect(path: &Path, key: Option<&str>) -> Result<SqliteConnection, AppError> {
+    // ATTACH 的导出目标与主库使用相同的打开方式，需要允许创建文件
+    let mut options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true);
+    if let Some(key) = key {
+        options = options.pragma("key", key_literal(key));
+    }
+    options.connect().await.map_err(sql_error)
+}
+
+/// 使用指定密钥能否读取数据库
+async fn can_open(path: &Path, key: &str) -> bool {
+    let Ok(mut conn) = connect(path, Some(key)).await else {
+        return false;
+    };
+    let ok = sqlx::query("SELECT count(*) FROM sqlite_master")
+        .fetch_one(&mut conn)
+        .await
+        .is_ok();
+    let _ = conn.close().await;
+    ok
+}
+
+/// 读取密钥，钥匙串暂时不可用时重试，仍然失败时返回错误
+async fn load_secret(name: &'static str) -> Result<Option<String>, AppError> {
+    let mut attempt = 1;
+    loop {
+        let result = tokio::task::spawn_blocking(move || secret_store::load_by_name(name))
+            .await
+            .map_err(|e| AppError::Unexpected(e.to_string()))?;
+        match result {
+            Err(e) if attempt < KEY_LOAD_ATTEMPTS => {
+                warn!("Failed to load {} (attempt {}): {}", name, attempt, e);
+                attempt += 1;
+                tokio::time::sleep(KEY_LOAD_RETRY_DELAY).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn save_secret(name: &'static str, secret: String) -> Result<(), AppError> {
+    tokio::task::spawn_blocking(move || secret_store::save(name, &secret).map(|_| ()))
+        .await
+        .map_err(|e| AppError::Unexpected(e.to_string()))?
+}
+
+async fn delete_secret(name: &'static str) -> Result<(), AppError> {
+    tokio::task::spawn_blocking(move || secret_store::delete_by_name(name))
+        .await
+        .map_err(|e| AppError::Unexpected(e.to_string()))?
+}
+
+/// 打开数据库前调用：取得密钥，必要时完成中断的密钥更换并加密明文数据库
+///
+/// 只有钥匙串与加密文件都确定没有密钥时，才把无法打开的加密数据库移到一旁并创建新数据库；
+/// 钥匙串读取失败或密钥无法打开数据库时返回错误，原文件保持不变。
+pub async fn prepare(path: &Path) -> Result<String, AppError> {
+    let key = load_secret(DATABASE_KEY_NAME).await;
+
+    match load_secret(PENDING_KEY_NAME).await {
+        Ok(Some(pending)) => {
+            if path.exists() && can_open(path, &pending).await {
+                warn!("Recovering interrupted database rekey");
+                save_secret(DATABASE_KEY_NAME, pending.clone()).await?;
+                delete_secret(PENDING_KEY_NAME).await?;
+                return Ok(pending);
+            }
+            delete_secret(PENDING_KEY_NAME).await?;
+        }
+        Ok(None) => {}
+        // 无法判断是否有中断的密钥更换，下面用正式密钥打开数据库时会校验
+        Err(e) => warn!("Failed to load pending database key: {}", e),
+    }
+
+    let plaintext = is_plaintext(path).map_err(|e| AppError::Io(e.to_string()))?;
+    let encrypted =
+        path.exists() && !plaintext && std::fs::metadata(path).is_ok_and(|m| m.len() > 0);
+    let key = match key {
+        Ok(Some(key)) => {
+            if encrypted && !can_open(path, &key).await {
+                return Err(AppError::Decryption(format!(
+                    "The stored database key does not open {}",
+                    path.display()
+                )));
+            }
+            key
+        }
+        Ok(None) if encrypted => {
+            // 密钥确定已丢失，保留原文件后以新数据库启动
+            let locked_path = set_aside(path)?;
+            error!(
+                "Database is encrypted but its key is neither in the system keyring nor in the \
+                 secret file; the encrypted database was moved to {:?} and a new one is created",
+                locked_path
+            );
+            let key = generate_key();
+            save_secret(DATABASE_KEY_NAME, key.clone()).await?;
+            key
+        }
+        Err(e) if encrypted => {
+            return Err(AppError::Decryption(format!(
+                "Failed to load the database key, the system keyring may be locked or unavailable: {e}"
+            )));
+        }
+        Ok(None) | Err(_) => {
+            let key = generate_key();
+            save_secret(DATABASE_KEY_NAME, key.clone()).await?;
+            key
+        }
+    };
+
+    if plaintext {
+        encrypt_in_place(path, &key).await?;
+    }
+    Ok(key)
+}
+
+/// 把无法打开的加密数据库连同 WAL 文件移到一旁，返回移动后的路径
+fn set_aside(path: &Path) -> Result<PathBuf, AppError> {
+    let suffix = format!(".locked-{}", chrono::Local::now().format("%Y%m%d%H%M%S"));
+    let locked_path = sidecar_path(path, &suffix);
+    std::fs::rename(path, &locked_path).map_err(|e| AppError::Io(e.to_string()))?;
+    for sidecar in ["-wal", "-shm"] {
+        let _ = std::fs::rename(
+            sidecar_path(path, sidecar),
+            sidecar_path(&locked_path, sidecar),
+        );
+    }
+    Ok(locked_path)
+}
+
+/// 把明文数据库加密后替换原文件
+///
+/// 先导出到临时文件，完成后再替换，过程中断时原文件保持不变。
+///
+/// `sqlcipher_export` 会重新编号没有 INTEGER PRIMARY KEY 的表（如 im_message）的 rowid，
+/// 全文索引通过 im_message_fts_doc 的 doc_id 关联消息，不受影响，无需重建。
+pub async fn encrypt_in_place(path: &Path, key: &str) -> Result<(), AppError> {
+    info!("Encrypting plaintext database {:?}", path);
+    let temp_path = sidecar_path(path, ".encrypting");
+    let _ = std::fs::remove_file(&temp_path);
+
+    let mut conn = connect(path, None).await?;
+    // 导出前把 WAL 中的内容写回主文件，替换后旧的 WAL 不再有效
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .execute(&mut conn)
+        .await
+        .map_err(sql_error)?;
+    sqlx::query(&format!(
+        "ATTACH DATABASE ?1 AS encrypted KEY {}",
+        key_literal(key)
+    ))
+    .bind(temp_path.to_string_lossy().into_owned())
+    .execute(&mut conn)
+    .await
+    .map_err(sql_error)?;
+    sqlx::query("SELECT sqlcipher_export('encrypted')")
+        .fetch_all(&mut conn)
+        .await
+        .map_err(sql_error)?;
+    sqlx::query("DETACH DATABASE encrypted")
+        .execute(&mut conn)
+        .await
+        .map_err(sql_error)?;
+    conn.close().await.map_err(sql_error)?;
+
+    std::fs::rename(&temp_path, path).map_err(|e| AppError::Io(e.to_string()))?;
+    for suffix in ["-wal", "-shm"] {
+        let _ = std::fs::remove_file(sidecar_path(path, suffix));
+    }
+    info!("Database encrypted");
+    Ok(())
+}
+
+/// 更换数据库密钥
+///
+/// 连接池中的其他连接仍持有旧密钥，调用方需要在完成后重启应用。
+pub async fn rekey(pool: &sqlx::SqlitePool) -> Result<(), AppError> {
+    let new_key = generate_key();
+    save_secret(PENDING_KEY_NAME, new_key.clone()).await?;
+
+    let mut conn = pool.acquire().await.map_err(sql_error)?;
+    if let Err(e) = sqlx::query(&format!("PRAGMA rekey = {}", key_literal(&new_key)))
+        .execute(&mut *conn)
+        .await
+    {
+        let _ = delete_secret(PENDING_KEY_NAME).await;
+        return Err(sql_error(e));
+    }
+    // 连接池中的其他连接仍使用旧密钥，该连接不再放回连接池
+    let _ = conn.detach().close().await;
+
+    save_secret(DATABASE_KEY_NAME, new_key).await?;
+    delete_secret(PENDING_KEY_NAME).await?;
+    info!("Database rekeyed");
+    Ok(())
+}
+
+/// 把加密数据库导出为明文副本，供排查问题使用
+pub async fn export_plaintext(pool: &sqlx::SqlitePool, target: &Path) -> Result<(), AppError> {
+    if target.exists() {
+        return Err(AppError::Io(format!("{} already exists", target.display())));
+    }
+
+    let mut conn = pool.acquire().await.map_err(sql_error)?;
+    sqlx::query("ATTACH DATABASE ?1 AS plaintext KEY ''")
+        .bind(target.to_string_lossy().into_owned())
+        .execute(&mut *conn)
+        .await
+        .map_err(sql_error)?;
+    let exported = sqlx::query("SELECT sqlcipher_export('plaintext')")
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(sql_error);
+    sqlx::query("DETACH DATABASE plaintext")
+        .execute(&mut *conn)
+        .await
+        .map_err(sql_error)?;
+    if let Err(e) = exported {
+        let _ = std::fs::remove_file(target);
+        return Err(e);
+    }
+    Ok(())
+}
@@ -0,0 +1,231 @@
+//! 外部 ffmpeg/ffprobe 调用
+//!
+//! 应用不附带 ffmpeg，只在 PATH 与常见安装目录中查找用户安装的程序，
+//! 查找结果在进程内缓存。所有函数都是阻塞的，需要在 `spawn_blocking` 中调用。
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use serde_json::Value;
+
+use crate::utils::media_probe::{AudioTrackInfo, ContainerInfo, VideoTrackInfo};
+
+/// PATH 之外的常见安装目录
+#[cfg(target_os = "macos")]
+const EXTRA_SEARCH_DIRS: &[&str] = &["/opt/homebrew/bin", "/usr/local/bin", "/opt/local/bin"];
+#[cfg(target_os = "linux")]
+const EXTRA_SEARCH_DIRS: &[&str] = &["/usr/bin", "/usr/local/bin", "/snap/bin"];
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+const EXTRA_SEARCH_DIRS: &[&str] = &[];
+
+static FFMPEG: OnceLock<Option<PathBuf>> = OnceLock::new();
+static FFPROBE: OnceLock<Option<PathBuf>> = OnceLock::new();
+static ENCODERS: OnceLock<String> = OnceLock::new();
+
+fn find_program(name: &str) -> Option<PathBuf> {
+    let file_name = if cfg!(target_os = "windows") {
+        format!("{name}.exe")
+    } else {
+        name.to_string()
+    };
+
+    let path_dirs = std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    path_dirs
+        .into_iter()
+        .chain(EXTRA_SEARCH_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file())
+}
+
+/// 查找 ffmpeg 可执行文件
+pub fn ffmpeg_path() -> Option<&'static Path> {
+    FFMPEG.get_or_init(|| find_program("ffmpeg")).as_deref()
+}
+
+/// 查找 ffprobe 可执行文件
+pub fn ffprobe_path() -> Option<&'static Path> {
+    FFPROBE.get_or_init(|| find_program("ffprobe")).as_deref()
+}
+
+/// ffmpeg 是否支持指定的编码器，不同发行版的编译选项不同
+pub fn has_encoder(name: &str) -> bool {
+    let encoders = ENCODERS.get_or_init(|| {
+        let Some(ffmpeg) = ffmpeg_path() else {
+            return String::new();
+        };
+        let mut command = command(ffmpeg);
+        command.args(["-hide_banner", "-encoders"]);
+        run(command)
+            .map(|output| String::from_utf8_lossy(&output).into_owned())
+            .unwrap_or_default()
+    });
+    // 每行格式为 " V....D libx264   描述"
+    encoders
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(name))
+}
+
+/// 创建命令，Windows 上不弹出控制台窗口
+pub fn command(program: &Path) -> Command {
+    let mut command = Command::new(program);
+    command.stdin(Stdio::null());
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    command
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("未找到 {name}"))
+}
+
+/// 运行命令并返回标准输出，退出码非 0 时返回标准错误中的信息
+fn run(mut command: Command) -> io::Result<Vec<u8>> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// 使用 ffprobe 读取容器信息
+pub fn probe(path: &Path) -> io::Result<ContainerInfo> {
+    let ffprobe = ffprobe_path().ok_or_else(|| not_found("ffprobe"))?;
+    let mut command = command(ffprobe);
+    command
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(path);
+    let output: Value = serde_json::from_slice(&run(command)?).map_err(io::Error::other)?;
+
+    let format = &output["format"];
+    let mut info = ContainerInfo {
+        container: format["format_name"]
+            .as_str()
+            .and_then(|name| name.split(',').next())
+            .unwrap_or_default()
+            .to_string(),
+        duration: format["duration"]
+            .as_str()
+            .and_then(|duration| duration.parse().ok()),
+        bitrate: format["bit_rate"]
+            .as_str()
+            .and_then(|bitrate| bitrate.parse().ok()),
+        ..Default::default()
+    };
+
+    for stream in output["streams"].as_array().into_iter().flatten() {
+        let codec = stream["codec_name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match stream["codec_type"].as_str() {
+            // 封面图以 attached_pic 视频流的形式出现
+            Some("video") if info.video.is_none() && stream["disposition"]["attached_pic"] != 1 => {
+                info.video = Some(VideoTrackInfo {
+                    codec,
+                    width: stream["width"].as_u64().unwrap_or_default() as u32,
+                    height: stream["height"].as_u64().unwrap_or_default() as u32,
+                    rotation: stream_rotation(stream),
+                });
+            }
+            Some("audio") if info.audio.is_none() => {
+                info.audio = Some(AudioTrackInfo {
+                    codec,
+                    sample_rate: stream["sample_rate"]
+                        .as_str()
+                        .and_then(|rate| rate.parse().ok()),
+                    channels: stream["channels"].as_u64().map(|channels| channels as u32),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(info)
+}
+
+/// 视频流的顺时针旋转角度
+///
+/// 旧版本通过 `rotate` 标签给出顺时针角度，新版本通过显示矩阵给出逆时针角度。
+fn stream_rotation(stream: &Value) -> u32 {
+    let degrees = stream["tags"]["rotate"]
+        .as_str()
+        .and_then(|rotate| rotate.parse::<f64>().ok())
+        .or_else(|| {
+            stream["side_data_list"]
+                .as_array()?
+                .iter()
+                .find_map(|side_data| side_data["rotation"].as_f64())
+                .map(|rotation| -rotation)
+        })
+        .unwrap_or_default();
+    ((degrees / 90.0).round() as i64 * 90).rem_euclid(360) as u32
+}
+
+/// 使用 ffmpeg 截取 `time` 秒处的一帧，返回 JPEG 数据
+///
+/// 画面按旋转信息摆正，并等比缩小到不超过 `max_size`。
+pub fn extract_frame(path: &Path, time: f64, max_size: u32) -> io::Result<Vec<u8>> {
+    let ffmpeg = ffmpeg_path().ok_or_else(|| not_found("ffmpeg"))?;
+    let scale = format!(
+        "scale=w='min({max_size},iw)':h='min({max_size},ih)':force_original_aspect_ratio=decrease"
+    );
+    let seek = format!("{time:.3}");
+    let mut command = command(ffmpeg);
+    command
+        .args(["-v", "error", "-ss", seek.as_str(), "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-vf", scale.as_str()])
+        .args(["-f", "image2pipe", "-c:v", "mjpeg", "-q:v", "4", "-"]);
+
+    let frame = run(command)?;
+    if frame.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "ffmpeg 未输出视频帧",
+        ));
+    }
+    Ok(frame)
+}
+
+/// 使用 ffmpeg 把 HEIC/AVIF 等图片解码为 PNG
+///
+/// 只取第一张图，方向按图片中的旋转信息摆正。
+pub fn decode_image(path: &Path) -> io::Result<Vec<u8>> {
+    let ffmpeg = ffmpeg_path().ok_or_else(|| not_found("ffmpeg"))?;
+    let mut command = command(ffmpeg);
+    command.args(["-v", "error", "-i"]).arg(path).args([
+        "-frames:v",
+        "1",
+        "-f",
+        "image2pipe",
+        "-c:v",
+        "png",
+        "-",
+    ]);
+
+    let image = run(command)?;
+    if image.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "ffmpeg 未输出图片",
+        ));
+    }
+    Ok(image)
+}
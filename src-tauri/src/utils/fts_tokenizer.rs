@@ -0,0 +1,276 @@
+//! 聊天记录全文检索的分词工具
+//!
+//! SQLite 内置的 `unicode61` 分词器会把连续的中日韩文字当成一个词，无法按词检索。
+//! 这里在写入 FTS5 表之前先完成分词：中日韩文字按二元组(bigram)切分，
+//! 拉丁字母与数字按单词切分并转小写，最后以空格拼接交给 `unicode61` 建索引。
+
+/// 文本片段
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    /// 连续的中日韩文字
+    Cjk(Vec<char>),
+    /// 小写后的单词
+    Word(String),
+}
+
+/// 判断是否为中日韩文字（汉字、假名、谚文）
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF   // 平假名、片假名
+        | 0x3400..=0x4DBF // 扩展 A
+        | 0x4E00..=0x9FFF // 基本汉字
+        | 0xAC00..=0xD7AF // 谚文音节
+        | 0xF900..=0xFAFF // 兼容汉字
+        | 0x20000..=0x2FA1F // 扩展 B 及以后
+    )
+}
+
+fn segments(text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut cjk = Vec::new();
+    let mut word = String::new();
+
+    let flush_cjk = |cjk: &mut Vec<char>, segments: &mut Vec<Segment>| {
+        if !cjk.is_empty() {
+            segments.push(Segment::Cjk(std::mem::take(cjk)));
+        }
+    };
+    let flush_word = |word: &mut String, segments: &mut Vec<Segment>| {
+        if !word.is_empty() {
+            segments.push(Segment::Word(std::mem::take(word)));
+        }
+    };
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            flush_word(&mut word, &mut segments);
+            cjk.push(c);
+        } else if c.is_alphanumeric() {
+            flush_cjk(&mut cjk, &mut segments);
+            word.extend(c.to_lowercase());
+        } else {
+            flush_cjk(&mut cjk, &mut segments);
+            flush_word(&mut word, &mut segments);
+        }
+    }
+    flush_cjk(&mut cjk, &mut segments);
+    flush_word(&mut word, &mut segments);
+
+    segments
+}
+
+fn bigrams(chars: &[char]) -> impl Iterator<Item = String> + '_ {
+    chars.windows(2).map(|pair| pair.iter().collect())
+}
+
+/// 生成写入 FTS5 表的分词结果
+///
+/// 每段中日韩文字输出全部二元组，再追加末尾单字，
+/// 这样单字查询可以用前缀匹配覆盖该字出现的所有位置。
+pub fn tokenize_for_index(text: &str) -> String {
+    let mut tokens: Vec<String> = Vec::new();
+
+    for segment in segments(text) {
+        match segment {
+            Segment::Cjk(chars) => {
+                tokens.extend(bigrams(&chars));
+                if let Some(last) = chars.last() {
+                    tokens.push(last.to_string());
+                }
+            }
+            Segment::Word(word) => tokens.push(word),
+        }
+    }
+
+    tokens.join(" ")
+}
+
+/// 将用户输入转换为 FTS5 MATCH 表达式，输入中没有可检索内容时返回 `None`
+///
+/// - 单个中日韩文字：前缀匹配 `"字"*`
+/// - 多个连续中日韩文字：二元组短语匹配，保证相邻
+/// - 单词：前缀匹配，便于边输入边搜索
+///
+/// 各片段之间为 AND 关系。
+pub fn build_match_query(query: &str) -> Option<String> {
+    let clauses: Vec<String> = segments(query)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Cjk(chars) if chars.len() == 1 => format!("\"{}\"*", chars[0]),
+            Segment::Cjk(chars) => format!("\"{}\"", bigrams(&chars).collect::<Vec<_>>().join(" ")),
+            Segment::Word(word) => format!("\"{word}\"*"),
+        })
+        .collect();
+
+    if clauses.is_empty() {
+        None
+    } else {
+        Some(clauses.join(" AND "))
+    }
+}
+
+/// 用于高亮的查询词（小写）
+pub fn highlight_terms(query: &str) -> Vec<String> {
+    segments(query)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Cjk(chars) => chars.into_iter().collect(),
+            Segment::Word(word) => word,
+        })
+        .collect()
+}
+
+fn escape_html(c: char, out: &mut String) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        _ => out.push(c),
+    }
+}
+
+/// 生成带 `<mark>` 高亮的摘要，原文会做 HTML 转义
+///
+/// 摘要围绕第一处命中截取，`context` 为命中前后各保留的字符数；没有命中时截取开头
+/// `context * 2` 个字符。
+pub fn highlight_snippet(text: &str, terms: &[String], context: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    // 标记每个字符是否落在命中范围内
+    let mut marked = vec![false; chars.len()];
+    for term in terms {
+        let term: Vec<char> = term.chars().collect();
+        if term.is_empty() {
+            continue;
+        }
+        for (start, window) in lower.windows(term.len()).enumerate() {
+            if window == term.as_slice() {
+                marked[start..start + term.len()].fill(true);
+            }
+        }
+    }
+
+    let (start, end) = match marked.iter().position(|m| *m) {
+        Some(first_hit) => {
+            let hit_end = marked[first_hit..]
+                .iter()
+                .position(|m| !m)
+                .map_or(chars.len(), |len| first_hit + len);
+            (
+                first_hit.saturating_sub(context),
+                (hit_end + context).min(chars.len()),
+            )
+        }
+        None => (0, (context * 2).min(chars.len())),
+    };
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut in_mark = false;
+    for (&c, &hit) in chars[start..end].iter().zip(&marked[start..end]) {
+        if hit && !in_mark {
+            snippet.push_str("<mark>");
+            in_mark = true;
+        } else if !hit && in_mark {
+            snippet.push_str("</mark>");
+            in_mark = false;
+        }
+        escape_html(c, &mut snippet);
+    }
+    if in_mark {
+        snippet.push_str("</mark>");
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_cjk_and_words() {
+        assert_eq!(
+            segments("Hello世界123, foo"),
+            [
+                Segment::Word("hello".to_string()),
+                Segment::Cjk(vec!['世', '界']),
+                Segment::Word("123".to_string()),
+                Segment::Word("foo".to_string()),
+            ]
+        );
+        assert!(segments(" ,.!? ").is_empty());
+    }
+
+    #[test]
+    fn indexes_bigrams_and_last_char() {
+        assert_eq!(
+            tokenize_for_index("我爱北京 Rust"),
+            "我爱 爱北 北京 京 rust"
+        );
+        assert_eq!(tokenize_for_index("好"), "好");
+        assert_eq!(tokenize_for_index("こんにちは"), "こん んに にち ちは は");
+        assert_eq!(tokenize_for_index(""), "");
+    }
+
+    #[test]
+    fn builds_match_query() {
+        assert_eq!(build_match_query("京").as_deref(), Some("\"京\"*"));
+        assert_eq!(build_match_query("北京").as_deref(), Some("\"北京\""));
+        assert_eq!(
+            build_match_query("Rust 北京天安门").as_deref(),
+            Some("\"rust\"* AND \"北京 京天 天安 安门\"")
+        );
+        // 引号等符号不会进入表达式
+        assert_eq!(
+            build_match_query("a\"b").as_deref(),
+            Some("\"a\"* AND \"b\"*")
+        );
+        assert_eq!(build_match_query(" \"*() "), None);
+    }
+
+    #[test]
+    fn lowercases_highlight_terms() {
+        assert_eq!(highlight_terms("Rust 北京"), ["rust", "北京"]);
+    }
+
+    #[test]
+    fn highlights_and_escapes() {
+        assert_eq!(
+            highlight_snippet("<b>Rust</b> & RUST", &highlight_terms("rust"), 100),
+            "&lt;b&gt;<mark>Rust</mark>&lt;/b&gt; &amp; <mark>RUST</mark>"
+        );
+    }
+
+    #[test]
+    fn keeps_context_on_both_sides() {
+        let text = "abcdefghij北京klmnopqrst";
+        assert_eq!(
+            highlight_snippet(text, &highlight_terms("北京"), 3),
+            "…hij<mark>北京</mark>klm…"
+        );
+        assert_eq!(
+            highlight_snippet(text, &highlight_terms("abc"), 3),
+            "<mark>abc</mark>def…"
+        );
+    }
+
+    #[test]
+    fn snippet_without_hit_starts_at_beginning() {
+        assert_eq!(
+            highlight_snippet("abcdefgh", &highlight_terms("zz"), 2),
+            "abcd…"
+        );
+        assert_eq!(highlight_snippet("abc", &[], 2), "abc");
+    }
+}
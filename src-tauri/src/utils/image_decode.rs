@@ -0,0 +1,181 @@
+//! 图片解码
+//!
+//! JPEG/PNG/GIF/WebP/BMP/TIFF 由 image crate 直接解码，并按 EXIF 方向摆正；
+//! 动图（GIF、动态 WebP、APNG）只取第一帧作为静态封面。
+//! HEIC/AVIF 没有可用的纯 Rust 解码器，交给系统工具转换：macOS 使用自带的 sips，
+//! 其他平台使用用户安装的 ffmpeg。所有函数都是阻塞的，需要在 `spawn_blocking` 中调用。
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::path::Path;
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::metadata::Orientation;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+
+use crate::error::AppError;
+use crate::utils::ffmpeg;
+
+/// 识别格式所需读取的文件头长度
+const HEADER_SIZE: usize = 64;
+
+/// 需要借助系统工具解码的 HEIF 系列格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeifKind {
+    Heic,
+    Avif,
+}
+
+impl HeifKind {
+    fn mime_type(self) -> &'static str {
+        match self {
+            HeifKind::Heic => "image/heic",
+            HeifKind::Avif => "image/avif",
+        }
+    }
+}
+
+/// 解码结果
+pub struct DecodedImage {
+    /// 已按方向摆正的图片，动图为第一帧
+    pub image: DynamicImage,
+    /// 原图的 MIME 类型
+    pub mime_type: &'static str,
+    /// 原图是否为动图
+    pub animated: bool,
+}
+
+/// 解码本地图片
+pub fn decode_file(path: &Path) -> Result<DecodedImage, AppError> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    File::open(path)
+        .and_then(|file| file.take(HEADER_SIZE as u64).read_to_end(&mut header))
+        .map_err(|e| AppError::Io(e.to_string()))?;
+
+    if let Some(kind) = sniff_heif(&header) {
+        let image = decode_with_system_tool(path).map_err(|e| {
+            AppError::Request(format!("Failed to decode {}: {e}", kind.mime_type()))
+        })?;
+        return Ok(DecodedImage {
+            image,
+            mime_type: kind.mime_type(),
+            animated: false,
+        });
+    }
+
+    let format = image::guess_format(&header)
+        .map_err(|e| AppError::Request(format!("Unrecognized image: {e}")))?;
+    let reader = BufReader::new(File::open(path).map_err(|e| AppError::Io(e.to_string()))?);
+    let decode_error =
+        |e: image::ImageError| AppError::Request(format!("Failed to decode image: {e}"));
+
+    let (image, animated) = match format {
+        ImageFormat::Gif => {
+            // 第一帧已按画布大小合成
+            let mut frames = GifDecoder::new(reader).map_err(decode_error)?.into_frames();
+            let first = frames
+                .next()
+                .ok_or_else(|| AppError::Request("GIF has no frames".to_string()))?
+                .map_err(decode_error)?;
+            let animated = frames.next().is_some();
+            (DynamicImage::ImageRgba8(first.into_buffer()), animated)
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader).map_err(decode_error)?;
+            let animated = decoder.has_animation();
+            (from_decoder(decoder)?, animated)
+        }
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader).map_err(decode_error)?;
+            let animated = decoder.is_apng().unwrap_or(false);
+            (from_decoder(decoder)?, animated)
+        }
+        format => {
+            let decoder = ImageReader::with_format(reader, format)
+                .into_decoder()
+                .map_err(decode_error)?;
+            (from_decoder(decoder)?, false)
+        }
+    };
+
+    Ok(DecodedImage {
+        image,
+        mime_type: format.to_mime_type(),
+        animated,
+    })
+}
+
+/// 解码并按 EXIF 方向旋转，动图解码器只输出第一帧
+fn from_decoder(mut decoder: impl ImageDecoder) -> Result<DynamicImage, AppError> {
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder)
+        .map_err(|e| AppError::Request(format!("Failed to decode image: {e}")))?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// 根据 ISO BMFF 的 `ftyp` 品牌识别 HEIC/AVIF
+fn sniff_heif(header: &[u8]) -> Option<HeifKind> {
+    if header.len() < 12 || &header[4..8] != b"ftyp" {
+        return None;
+    }
+    let box_size = u32::from_be_bytes(header[0..4].try_into().ok()?) as usize;
+    // 主品牌之后依次是版本号与兼容品牌列表
+    let brands = header[8..box_size.clamp(12, header.len())]
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(index, _)| *index != 1)
+        .map(|(_, brand)| brand);
+
+    let mut kind = None;
+    for brand in brands {
+        match brand {
+            b"avif" | b"avis" => return Some(HeifKind::Avif),
+            b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" => {
+                kind = Some(HeifKind::Heic)
+            }
+            // 通用 HEIF 品牌，编码格式由其他品牌决定，默认按 HEVC 处理
+            b"mif1" | b"msf1" => {
+                kind.get_or_insert(HeifKind::Heic);
+            }
+            _ => {}
+        }
+    }
+    kind
+}
+
+/// 使用系统工具把图片转换为 PNG 后解码
+#[cfg(target_os = "macos")]
+fn decode_with_system_tool(path: &Path) -> io::Result<DynamicImage> {
+    let output = std::env::temp_dir().join(format!("{}.png", uuid::Uuid::new_v4()));
+    let status = ffmpeg::command(Path::new("/usr/bin/sips"))
+        .args(["-s", "format", "png"])
+        .arg(path)
+        .arg("--out")
+        .arg(&output)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+
+    let result = match status {
+        Ok(status) if status.success() => std::fs::read(&output).and_then(|data| decode_png(&data)),
+        // sips 不支持的格式（如旧系统上的 AVIF）再尝试 ffmpeg
+        _ => ffmpeg::decode_image(path).and_then(|data| decode_png(&data)),
+    };
+    let _ = std::fs::remove_file(&output);
+    result
+}
+
+/// 使用系统工具把图片转换为 PNG 后解码
+#[cfg(not(target_os = "macos"))]
+fn decode_with_system_tool(path: &Path) -> io::Result<DynamicImage> {
+    ffmpeg::decode_image(path).and_then(|data| decode_png(&data))
+}
+
+fn decode_png(data: &[u8]) -> io::Result<DynamicImage> {
+    ImageReader::with_format(Cursor::new(data), ImageFormat::Png)
+        .decode()
+        .map_err(io::Error::other)
+}
@@ -0,0 +1,320 @@
+//! 无损移除图片元数据
+//!
+//! 以“原图”发送时不重新编码，只在容器层面删除 EXIF、XMP、IPTC 与文本注释，
+//! 避免泄露拍摄位置等信息。JPEG 的方向信息会以仅包含 Orientation 的最小 EXIF 写回，
+//! 保证接收方显示方向正确。
+
+/// JPEG 中需要删除的段：APP1（EXIF、XMP）、APP13（IPTC）、COM（注释）
+const JPEG_METADATA_MARKERS: [u8; 3] = [0xE1, 0xED, 0xFE];
+
+/// PNG 中需要删除的块
+const PNG_METADATA_CHUNKS: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// WebP 中需要删除的块
+const WEBP_METADATA_CHUNKS: [&[u8; 4]; 2] = [b"EXIF", b"XMP "];
+
+/// WebP VP8X 头中 EXIF 与 XMP 的标志位
+const WEBP_METADATA_FLAGS: u8 = 0x08 | 0x04;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// 生成只包含 Orientation 标签的 APP1 段
+fn orientation_app1(orientation: u8) -> Vec<u8> {
+    let mut tiff = Vec::with_capacity(26);
+    // 大端 TIFF 头，IFD0 紧随其后
+    tiff.extend_from_slice(b"MM\x00\x2a\x00\x00\x00\x08");
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    // Orientation(0x0112)，类型 SHORT，数量 1
+    tiff.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01]);
+    tiff.extend_from_slice(&[0x00, orientation, 0x00, 0x00]);
+    // 没有下一个 IFD
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+    segment.extend_from_slice(b"Exif\x00\x00");
+    segment.extend_from_slice(&tiff);
+    segment
+}
+
+/// 删除 JPEG 的元数据段，`orientation` 不为 1 时写回方向
+///
+/// 只处理扫描数据之前的段，图像数据原样保留。格式错误时返回 `None`。
+pub fn strip_jpeg_metadata(data: &[u8], orientation: u8) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    if orientation > 1 {
+        output.extend_from_slice(&orientation_app1(orientation));
+    }
+
+    let mut pos = 2;
+    while pos + 2 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        match marker {
+            // 填充字节
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // 没有长度字段的标记
+            0x01 | 0xD0..=0xD7 => {
+                output.extend_from_slice(&data[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            // SOS 之后是压缩数据，其余内容原样复制；EOI 为文件结尾
+            0xDA | 0xD9 => {
+                output.extend_from_slice(&data[pos..]);
+                return Some(output);
+            }
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let end = pos + 2 + length;
+        if length < 2 || end > data.len() {
+            return None;
+        }
+        if !JPEG_METADATA_MARKERS.contains(&marker) {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+
+    None
+}
+
+/// 删除 PNG 的 EXIF 与文本块，其余块（包括 CRC）原样保留
+pub fn strip_png_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(PNG_SIGNATURE);
+
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < data.len() {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let chunk_type = data.get(pos + 4..pos + 8)?;
+        // 长度 + 类型 + 数据 + CRC
+        let end = pos.checked_add(12)?.checked_add(length)?;
+        if end > data.len() {
+            return None;
+        }
+        if !PNG_METADATA_CHUNKS
+            .iter()
+            .any(|metadata| chunk_type == metadata.as_slice())
+        {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+        if chunk_type == b"IEND" {
+            break;
+        }
+    }
+
+    Some(output)
+}
+
+/// 删除 WebP 的 EXIF 与 XMP 块，并更新 RIFF 长度与 VP8X 标志位
+pub fn strip_webp_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..12]);
+
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let chunk_type = &data[pos..pos + 4];
+        let length = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().ok()?) as usize;
+        // 块数据按偶数字节对齐
+        let end = pos
+            .checked_add(8)?
+            .checked_add(length)?
+            .checked_add(length % 2)?
+            .min(data.len());
+        if pos + 8 + length > data.len() {
+            return None;
+        }
+
+        if !WEBP_METADATA_CHUNKS
+            .iter()
+            .any(|metadata| chunk_type == metadata.as_slice())
+        {
+            let start = output.len();
+            output.extend_from_slice(&data[pos..end]);
+            if chunk_type == b"VP8X" && length > 0 {
+                output[start + 8] &= !WEBP_METADATA_FLAGS;
+            }
+        }
+        pos = end;
+    }
+
+    let riff_size = (output.len() - 8) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    fn png_chunk(chunk_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = (payload.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(chunk_type);
+        chunk.extend_from_slice(payload);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    fn webp_chunk(chunk_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = chunk_type.to_vec();
+        chunk.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn webp(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&((body.len() + 4) as u32).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend_from_slice(&body);
+        data
+    }
+
+    #[test]
+    fn strips_jpeg_segments() {
+        let app0 = jpeg_segment(0xE0, b"JFIF\x00");
+        let dqt = jpeg_segment(0xDB, &[0; 5]);
+        // SOS 之后的数据原样保留
+        let scan = [0xFF, 0xDA, 0x00, 0x02, 0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD9];
+        let data = [
+            vec![0xFF, 0xD8],
+            app0.clone(),
+            jpeg_segment(0xE1, b"Exif\x00\x00MM"),
+            jpeg_segment(0xED, b"Photoshop 3.0\x00"),
+            jpeg_segment(0xFE, b"comment"),
+            dqt.clone(),
+            scan.to_vec(),
+        ]
+        .concat();
+
+        let expected = [vec![0xFF, 0xD8], app0, dqt, scan.to_vec()].concat();
+        assert_eq!(strip_jpeg_metadata(&data, 1).unwrap(), expected);
+    }
+
+    #[test]
+    fn writes_back_jpeg_orientation() {
+        let data = [
+            vec![0xFF, 0xD8],
+            jpeg_segment(0xE1, b"Exif\x00\x00MM"),
+            vec![0xFF, 0xD9],
+        ]
+        .concat();
+
+        let stripped = strip_jpeg_metadata(&data, 6).unwrap();
+        let app1 = orientation_app1(6);
+        assert_eq!(
+            stripped,
+            [&[0xFF, 0xD8], app1.as_slice(), &[0xFF, 0xD9]].concat()
+        );
+        // 段长度覆盖标识与 TIFF 数据
+        assert_eq!(
+            u16::from_be_bytes([app1[2], app1[3]]) as usize,
+            app1.len() - 2
+        );
+        assert_eq!(&app1[4..10], b"Exif\x00\x00");
+        assert_eq!(app1[10 + 8 + 2 + 9], 6);
+    }
+
+    #[test]
+    fn rejects_malformed_jpeg() {
+        assert_eq!(strip_jpeg_metadata(b"\x89PNG", 1), None);
+        // 段长度超出文件
+        assert_eq!(
+            strip_jpeg_metadata(&[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x10, 0x00], 1),
+            None
+        );
+        // 段之间出现非标记字节
+        assert_eq!(strip_jpeg_metadata(&[0xFF, 0xD8, 0x00, 0xDA], 1), None);
+        // 没有扫描数据
+        assert_eq!(strip_jpeg_metadata(&[0xFF, 0xD8], 1), None);
+    }
+
+    #[test]
+    fn strips_png_chunks() {
+        let ihdr = png_chunk(b"IHDR", &[0; 13]);
+        let idat = png_chunk(b"IDAT", &[1, 2, 3]);
+        let iend = png_chunk(b"IEND", &[]);
+        let data = [
+            PNG_SIGNATURE.to_vec(),
+            ihdr.clone(),
+            png_chunk(b"tEXt", b"Author\x00someone"),
+            png_chunk(b"eXIf", b"MM\x00\x2a"),
+            idat.clone(),
+            png_chunk(b"tIME", &[0; 7]),
+            iend.clone(),
+            // IEND 之后的内容会被丢弃
+            b"trailing".to_vec(),
+        ]
+        .concat();
+
+        let expected = [PNG_SIGNATURE.to_vec(), ihdr, idat, iend].concat();
+        assert_eq!(strip_png_metadata(&data).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_malformed_png() {
+        assert_eq!(strip_png_metadata(b"GIF89a"), None);
+        let mut truncated = [PNG_SIGNATURE.to_vec(), png_chunk(b"IHDR", &[0; 13])].concat();
+        truncated.truncate(truncated.len() - 2);
+        assert_eq!(strip_png_metadata(&truncated), None);
+    }
+
+    #[test]
+    fn strips_webp_chunks_and_flags() {
+        // 带 alpha(0x10)、EXIF、XMP 标志的 VP8X
+        let mut vp8x = vec![0x10 | WEBP_METADATA_FLAGS];
+        vp8x.extend_from_slice(&[0; 9]);
+        let data = webp(&[
+            webp_chunk(b"VP8X", &vp8x),
+            webp_chunk(b"VP8L", &[1, 2, 3]),
+            webp_chunk(b"EXIF", b"MM\x00\x2a"),
+            webp_chunk(b"XMP ", b"<x/>\n"),
+        ]);
+
+        vp8x[0] = 0x10;
+        let expected = webp(&[webp_chunk(b"VP8X", &vp8x), webp_chunk(b"VP8L", &[1, 2, 3])]);
+        assert_eq!(strip_webp_metadata(&data).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_malformed_webp() {
+        assert_eq!(strip_webp_metadata(b"RIFF\x00\x00\x00\x00WAVE"), None);
+        let mut truncated = webp(&[webp_chunk(b"VP8L", &[0; 8])]);
+        truncated.truncate(truncated.len() - 1);
+        assert_eq!(strip_webp_metadata(&truncated), None);
+    }
+}
@@ -0,0 +1,1568 @@
+//! 音视频容器解析
+//!
+//! 不依赖 ffmpeg，直接解析 MP4/MOV（ISO BMFF）、WebM/Matroska、Ogg 与 MP3 的头部信息，
+//! 得到时长、编码、码率、分辨率与旋转角度。视频帧无法在纯 Rust 中解码，
+//! 只能取出容器内嵌的封面图，或 MJPEG/PNG 编码轨道中指定时间的关键帧。
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::Serialize;
+
+/// 读入内存解析的头部元素（moov、Tracks 等）大小上限
+const MAX_METADATA_SIZE: u64 = 64 * 1024 * 1024;
+
+/// 读取单个视频帧的大小上限
+const MAX_FRAME_SIZE: u64 = 32 * 1024 * 1024;
+
+/// 容器信息
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerInfo {
+    /// 容器格式：mp4、mov、webm、matroska、ogg、mp3
+    pub container: String,
+    /// 时长（秒）
+    pub duration: Option<f64>,
+    /// 平均码率（bit/s）
+    pub bitrate: Option<u64>,
+    pub video: Option<VideoTrackInfo>,
+    pub audio: Option<AudioTrackInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoTrackInfo {
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    /// 显示时需要顺时针旋转的角度：0、90、180、270
+    pub rotation: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioTrackInfo {
+    pub codec: String,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+}
+
+/// 解析文件的容器信息，不是支持的容器时返回 `None`
+pub fn probe_file(path: &Path) -> io::Result<Option<ContainerInfo>> {
+    let mut file = File::open(path)?;
+    let info = if let Some(movie) = mp4::parse(&mut file)? {
+        movie.info
+    } else if let Some(segment) = matroska::parse(&mut file)? {
+        segment.info
+    } else if let Some(info) = ogg::parse(&mut file)? {
+        info
+    } else if let Some(info) = mp3::parse(&mut file)? {
+        info
+    } else {
+        return Ok(None);
+    };
+
+    // 容器没有记录码率时按文件大小估算
+    let file_len = file.metadata()?.len();
+    Ok(Some(ContainerInfo {
+        bitrate: info.bitrate.or_else(|| {
+            info.duration
+                .filter(|&duration| duration > 0.0)
+                .map(|duration| (file_len as f64 * 8.0 / duration) as u64)
+        }),
+        ..info
+    }))
+}
+
+/// 从容器中取出的静态图片
+#[derive(Debug, Clone)]
+pub struct StillImage {
+    /// JPEG 或 PNG 数据
+    pub data: Vec<u8>,
+    /// 是否为视频帧；视频帧显示时需要按轨道的旋转角度旋转，封面图不需要
+    pub is_frame: bool,
+}
+
+/// 不解码视频，尽量取出一张静态图片
+///
+/// 依次尝试 MJPEG/PNG 编码的视频轨道中 `time` 之前最近的关键帧，以及内嵌的封面图。
+pub fn extract_still_image(path: &Path, time: f64) -> io::Result<Option<StillImage>> {
+    let mut file = File::open(path)?;
+    let cover_art = if let Some(movie) = mp4::parse(&mut file)? {
+        if let Some(data) = movie.read_frame(&mut file, time)? {
+            return Ok(Some(StillImage {
+                data,
+                is_frame: true,
+            }));
+        }
+        movie.cover_art
+    } else if let Some(segment) = matroska::parse(&mut file)? {
+        segment.cover_art
+    } else {
+        None
+    };
+    Ok(cover_art.map(|data| StillImage {
+        data,
+        is_frame: false,
+    }))
+}
+
+/// 把旋转角度归一到 0、90、180、270
+fn normalize_rotation(degrees: f64) -> u32 {
+    ((degrees / 90.0).round() as i64 * 90).rem_euclid(360) as u32
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn be_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn read_exact_at(file: &mut File, offset: u64, size: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0u8; size as usize];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// 从 `offset` 开始读取至多 `size` 字节
+fn read_at_most(file: &mut File, offset: u64, size: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::new();
+    file.take(size).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// 把 `data` 写入临时文件后交给 `parse` 解析
+#[cfg(test)]
+fn with_temp_file<T>(data: &[u8], parse: impl FnOnce(&mut File) -> T) -> T {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "media-probe-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, data).unwrap();
+    let result = parse(&mut File::open(&path).unwrap());
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+/// ISO BMFF（MP4、MOV）
+mod mp4 {
+    use super::*;
+
+    /// 合法的顶层 box，用于识别文件格式
+    const TOP_LEVEL_BOXES: [&[u8; 4]; 8] = [
+        b"ftyp", b"moov", b"mdat", b"free", b"skip", b"wide", b"pnot", b"uuid",
+    ];
+
+    /// 内存中的 box 迭代器
+    struct Boxes<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Iterator for Boxes<'a> {
+        type Item = (&'a [u8], &'a [u8]);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let size = be_u32(self.data, 0)? as usize;
+            let kind = self.data.get(4..8)?;
+            let (header, size) = match size {
+                0 => (8, self.data.len()),
+                1 => (16, usize::try_from(be_u64(self.data, 8)?).ok()?),
+                size => (8, size),
+            };
+            if size < header || size > self.data.len() {
+                return None;
+            }
+            let payload = &self.data[header..size];
+            self.data = &self.data[size..];
+            Some((kind, payload))
+        }
+    }
+
+    fn boxes(data: &[u8]) -> Boxes<'_> {
+        Boxes { data }
+    }
+
+    fn find<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+        boxes(data)
+            .find(|(k, _)| *k == kind)
+            .map(|(_, payload)| payload)
+    }
+
+    /// 按路径查找嵌套的 box
+    fn find_path<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        path.iter().try_fold(data, |data, kind| find(data, kind))
+    }
+
+    /// 视频轨道的采样表
+    #[derive(Default)]
+    struct SampleTable {
+        /// (采样数, 时长)
+        time_to_sample: Vec<(u32, u32)>,
+        /// 关键帧序号（从 1 开始），为空表示全部是关键帧
+        sync_samples: Vec<u32>,
+        /// (首个 chunk 序号, 每个 chunk 的采样数)
+        sample_to_chunk: Vec<(u32, u32)>,
+        sample_size: u32,
+        sample_sizes: Vec<u32>,
+        chunk_offsets: Vec<u64>,
+    }
+
+    struct Track {
+        handler: [u8; 4],
+        timescale: u32,
+        codec: [u8; 4],
+        samples: SampleTable,
+    }
+
+    pub(super) struct Movie {
+        pub info: ContainerInfo,
+        pub cover_art: Option<Vec<u8>>,
+        video_track: Option<Track>,
+    }
+
+    pub(super) fn parse(file: &mut File) -> io::Result<Option<Movie>> {
+        let file_len = file.metadata()?.len();
+        let mut pos = 0u64;
+        let mut brand = None;
+        let mut movie = None;
+
+        while pos + 8 <= file_len {
+            let header = read_exact_at(file, pos, 8)?;
+            let kind: [u8; 4] = header[4..8].try_into().unwrap_or_default();
+            if pos == 0 && !TOP_LEVEL_BOXES.contains(&&kind) {
+                return Ok(None);
+            }
+
+            let (header_len, size) = match be_u32(&header, 0).unwrap_or_default() {
+                0 => (8, file_len - pos),
+                1 if file_len - pos >= 16 => {
+                    let large = read_exact_at(file, pos + 8, 8)?;
+                    (16, be_u64(&large, 0).unwrap_or_default())
+                }
+                1 => break,
+                size => (8, size as u64),
+            };
+            // largesize 来自文件，可能溢出
+            let Some(end) = pos
+                .checked_add(size)
+                .filter(|&end| size >= header_len && end <= file_len)
+            else {
+                break;
+            };
+
+            match &kind {
+                b"ftyp" => {
+                    let ftyp = read_exact_at(file, pos + header_len, (size - header_len).min(64))?;
+                    brand = ftyp.get(0..4).map(|brand| brand.to_vec());
+                }
+                b"moov" if size - header_len <= MAX_METADATA_SIZE => {
+                    let moov = read_exact_at(file, pos + header_len, size - header_len)?;
+                    movie = Some(parse_moov(&moov));
+                }
+                _ => {}
+            }
+            if movie.is_some() && brand.is_some() {
+                break;
+            }
+            pos = end;
+        }
+
+        Ok(movie.map(|mut movie| {
+            movie.info.container = match brand.as_deref() {
+                Some(b"qt  ") => "mov",
+                _ => "mp4",
+            }
+            .to_string();
+            movie
+        }))
+    }
+
+    fn parse_moov(moov: &[u8]) -> Movie {
+        let mut info = ContainerInfo::default();
+
+        if let Some(mvhd) = find(moov, b"mvhd") {
+            let (timescale, duration) = if mvhd.first() == Some(&1) {
+                (be_u32(mvhd, 20), be_u64(mvhd, 24))
+            } else {
+                (be_u32(mvhd, 12), be_u32(mvhd, 16).map(u64::from))
+            };
+            if let (Some(timescale), Some(duration)) = (timescale, duration)
+                && timescale > 0
+                && duration != u64::MAX
+                && duration != u32::MAX as u64
+            {
+                info.duration = Some(duration as f64 / timescale as f64);
+            }
+        }
+
+        let mut video_track = None;
+        for (kind, trak) in boxes(moov) {
+            if kind != b"trak" {
+                continue;
+            }
+            let Some((track, sample_entry)) = parse_trak(trak) else {
+                continue;
+            };
+            let codec = codec_name(&track.codec);
+
+            match &track.handler {
+                b"vide" if info.video.is_none() => {
+                    let (width, height, rotation) = find(trak, b"tkhd")
+                        .and_then(track_header)
+                        .unwrap_or_default();
+                    // tkhd 中的尺寸可能为 0，回退到采样描述中的编码尺寸
+                    let (width, height) = if width == 0 || height == 0 {
+                        (
+                            be_u16(sample_entry, 24).unwrap_or_default() as u32,
+                            be_u16(sample_entry, 26).unwrap_or_default() as u32,
+                        )
+                    } else {
+                        (width, height)
+                    };
+                    info.video = Some(VideoTrackInfo {
+                        codec,
+                        width,
+                        height,
+                        rotation,
+                    });
+                    video_track = Some(track);
+                }
+                b"soun" if info.audio.is_none() => {
+                    info.audio = Some(AudioTrackInfo {
+                        codec,
+                        channels: be_u16(sample_entry, 16).map(u32::from),
+                        sample_rate: be_u32(sample_entry, 24).map(|rate| rate >> 16),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Movie {
+            info,
+            cover_art: cover_art(moov),
+            video_track,
+        }
+    }
+
+    /// 返回轨道与首个采样描述（不含 box 头）
+    fn parse_trak(trak: &[u8]) -> Option<(Track, &[u8])> {
+        let mdia = find(trak, b"mdia")?;
+        let handler: [u8; 4] = find(mdia, b"hdlr")?.get(8..12)?.try_into().ok()?;
+        let mdhd = find(mdia, b"mdhd")?;
+        let timescale = if mdhd.first() == Some(&1) {
+            be_u32(mdhd, 20)?
+        } else {
+            be_u32(mdhd, 12)?
+        };
+
+        let stbl = find_path(mdia, &[b"minf", b"stbl"])?;
+        let stsd = find(stbl, b"stsd")?;
+        // 版本与标志(4) + 条目数(4)，之后是第一个采样描述 box
+        let (codec, sample_entry) = boxes(stsd.get(8..)?).next()?;
+
+        let mut samples = SampleTable::default();
+        if handler == *b"vide" {
+            samples = sample_table(stbl).unwrap_or_default();
+        }
+
+        Some((
+            Track {
+                handler,
+                timescale,
+                codec: codec.try_into().ok()?,
+                samples,
+            },
+            sample_entry,
+        ))
+    }
+
+    /// 解析 tkhd 中的显示尺寸与旋转矩阵
+    fn track_header(tkhd: &[u8]) -> Option<(u32, u32, u32)> {
+        let matrix = if tkhd.first() == Some(&1) { 52 } else { 40 };
+        let a = be_u32(tkhd, matrix)? as i32 as f64;
+        let b = be_u32(tkhd, matrix + 4)? as i32 as f64;
+        let width = be_u32(tkhd, matrix + 36)? >> 16;
+        let height = be_u32(tkhd, matrix + 40)? >> 16;
+        Some((width, height, normalize_rotation(b.atan2(a).to_degrees())))
+    }
+
+    fn sample_table(stbl: &[u8]) -> Option<SampleTable> {
+        let entries = |data: &[u8]| be_u32(data, 4).unwrap_or_default() as usize;
+
+        let stts = find(stbl, b"stts")?;
+        let time_to_sample = (0..entries(stts))
+            .map_while(|i| Some((be_u32(stts, 8 + i * 8)?, be_u32(stts, 12 + i * 8)?)))
+            .collect();
+
+        let sync_samples = find(stbl, b"stss")
+            .map(|stss| {
+                (0..entries(stss))
+                    .map_while(|i| be_u32(stss, 8 + i * 4))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let stsc = find(stbl, b"stsc")?;
+        let sample_to_chunk = (0..entries(stsc))
+            .map_while(|i| Some((be_u32(stsc, 8 + i * 12)?, be_u32(stsc, 12 + i * 12)?)))
+            .collect();
+
+        let stsz = find(stbl, b"stsz")?;
+        let sample_size = be_u32(stsz, 4)?;
+        let sample_sizes = if sample_size == 0 {
+            let count = be_u32(stsz, 8)? as usize;
+            (0..count).map_while(|i| be_u32(stsz, 12 + i * 4)).collect()
+        } else {
+            Vec::new()
+        };
+
+        let chunk_offsets = if let Some(stco) = find(stbl, b"stco") {
+            (0..entries(stco))
+                .map_while(|i| be_u32(stco, 8 + i * 4).map(u64::from))
+                .collect()
+        } else {
+            let co64 = find(stbl, b"co64")?;
+            (0..entries(co64))
+                .map_while(|i| be_u64(co64, 8 + i * 8))
+                .collect()
+        };
+
+        Some(SampleTable {
+            time_to_sample,
+            sync_samples,
+            sample_to_chunk,
+            sample_size,
+            sample_sizes,
+            chunk_offsets,
+        })
+    }
+
+    /// iTunes 元数据中的封面：moov/udta/meta/ilst/covr/data
+    fn cover_art(moov: &[u8]) -> Option<Vec<u8>> {
+        let meta = find_path(moov, &[b"udta", b"meta"])?;
+        // MP4 的 meta 是带版本号的 full box，QuickTime 的不是
+        let meta = if meta.get(4..8) == Some(b"hdlr") {
+            meta
+        } else {
+            meta.get(4..)?
+        };
+        let data = find_path(meta, &[b"ilst", b"covr", b"data"])?;
+        // 类型(4) + 语言(4)，13 为 JPEG，14 为 PNG
+        match be_u32(data, 0)? {
+            13 | 14 => Some(data.get(8..)?.to_vec()),
+            _ => None,
+        }
+    }
+
+    fn codec_name(fourcc: &[u8; 4]) -> String {
+        match fourcc {
+            b"avc1" | b"avc3" => "h264",
+            b"hvc1" | b"hev1" => "hevc",
+            b"av01" => "av1",
+            b"vp08" => "vp8",
+            b"vp09" => "vp9",
+            b"mp4v" => "mpeg4",
+            b"jpeg" | b"mjpa" => "mjpeg",
+            b"png " => "png",
+            b"mp4a" => "aac",
+            b"Opus" => "opus",
+            b".mp3" => "mp3",
+            b"ac-3" => "ac3",
+            b"ec-3" => "eac3",
+            b"fLaC" => "flac",
+            b"alac" => "alac",
+            b"sowt" | b"twos" | b"lpcm" => "pcm",
+            _ => return String::from_utf8_lossy(fourcc).trim().to_lowercase(),
+        }
+        .to_string()
+    }
+
+    impl Movie {
+        /// 读取 MJPEG/PNG 视频轨道中 `time` 之前最近的关键帧
+        pub fn read_frame(&self, file: &mut File, time: f64) -> io::Result<Option<Vec<u8>>> {
+            let Some(track) = &self.video_track else {
+                return Ok(None);
+            };
+            if !matches!(&track.codec, b"jpeg" | b"mjpa" | b"png ") {
+                return Ok(None);
+            }
+            let Some((offset, size)) = track.sample_location(time) else {
+                return Ok(None);
+            };
+            if size == 0 || size > MAX_FRAME_SIZE {
+                return Ok(None);
+            }
+            read_exact_at(file, offset, size).map(Some)
+        }
+    }
+
+    impl Track {
+        fn sample_size(&self, index: usize) -> Option<u64> {
+            if self.samples.sample_size > 0 {
+                Some(self.samples.sample_size as u64)
+            } else {
+                self.samples
+                    .sample_sizes
+                    .get(index)
+                    .map(|&size| size as u64)
+            }
+        }
+
+        /// 计算 `time` 对应关键帧在文件中的位置与大小
+        fn sample_location(&self, time: f64) -> Option<(u64, u64)> {
+            let table = &self.samples;
+            let target = (time.max(0.0) * self.timescale as f64) as u64;
+
+            // 时间 -> 采样序号（从 0 开始）
+            let mut sample = 0u64;
+            let mut elapsed = 0u64;
+            for &(count, delta) in &table.time_to_sample {
+                let span = count as u64 * delta as u64;
+                if delta > 0 && elapsed + span > target {
+                    sample += (target - elapsed) / delta as u64;
+                    break;
+                }
+                elapsed += span;
+                sample += count as u64;
+            }
+            let total = if table.sample_size > 0 {
+                table
+                    .time_to_sample
+                    .iter()
+                    .map(|&(count, _)| count as u64)
+                    .sum()
+            } else {
+                table.sample_sizes.len() as u64
+            };
+            let mut sample = sample.min(total.checked_sub(1)?);
+
+            // 回退到之前最近的关键帧
+            if !table.sync_samples.is_empty() {
+                sample = table
+                    .sync_samples
+                    .iter()
+                    .map(|&number| number.saturating_sub(1) as u64)
+                    .take_while(|&sync| sync <= sample)
+                    .last()
+                    .unwrap_or(0);
+            }
+
+            // 采样序号 -> chunk 与 chunk 内偏移
+            let mut first_sample = 0u64;
+            for (i, &(first_chunk, samples_per_chunk)) in table.sample_to_chunk.iter().enumerate() {
+                let next_chunk = table
+                    .sample_to_chunk
+                    .get(i + 1)
+                    .map(|&(chunk, _)| chunk)
+                    .unwrap_or(table.chunk_offsets.len() as u32 + 1);
+                let chunks = next_chunk.saturating_sub(first_chunk) as u64;
+                let samples = chunks * samples_per_chunk as u64;
+                if samples_per_chunk == 0 || sample >= first_sample + samples {
+                    first_sample += samples;
+                    continue;
+                }
+
+                let chunk_index = (sample - first_sample) / samples_per_chunk as u64;
+                // chunk 序号从 1 开始，为 0 时采样表已损坏
+                let chunk = (first_chunk as u64).checked_sub(1)? + chunk_index;
+                let chunk_first_sample = first_sample + chunk_index * samples_per_chunk as u64;
+                let mut offset = *table.chunk_offsets.get(chunk as usize)?;
+                for index in chunk_first_sample..sample {
+                    offset = offset.checked_add(self.sample_size(index as usize)?)?;
+                }
+                return Some((offset, self.sample_size(sample as usize)?));
+            }
+
+            None
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+            let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+            data.extend_from_slice(kind);
+            data.extend_from_slice(payload);
+            data
+        }
+
+        fn ftyp() -> Vec<u8> {
+            mp4_box(b"ftyp", b"isom\0\0\0\0")
+        }
+
+        fn moov(timescale: u32, duration: u32) -> Vec<u8> {
+            let mut mvhd = vec![0u8; 12];
+            mvhd.extend_from_slice(&timescale.to_be_bytes());
+            mvhd.extend_from_slice(&duration.to_be_bytes());
+            mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd))
+        }
+
+        fn jpeg_track(sample_to_chunk: Vec<(u32, u32)>, chunk_offsets: Vec<u64>) -> Track {
+            Track {
+                handler: *b"vide",
+                timescale: 1,
+                codec: *b"jpeg",
+                samples: SampleTable {
+                    time_to_sample: vec![(2, 1)],
+                    sample_to_chunk,
+                    sample_size: 10,
+                    chunk_offsets,
+                    ..Default::default()
+                },
+            }
+        }
+
+        #[test]
+        fn parses_movie_duration() {
+            let data = [ftyp(), moov(1000, 5000)].concat();
+            let movie = with_temp_file(&data, parse).unwrap().unwrap();
+            assert_eq!(movie.info.container, "mp4");
+            assert_eq!(movie.info.duration, Some(5.0));
+        }
+
+        #[test]
+        fn rejects_unknown_first_box() {
+            let data = mp4_box(b"abcd", &[0; 8]);
+            assert!(with_temp_file(&data, parse).unwrap().is_none());
+        }
+
+        #[test]
+        fn stops_at_truncated_box() {
+            // moov 声明的大小超出文件末尾
+            let mut data = [ftyp(), moov(1000, 5000)].concat();
+            data.truncate(data.len() - 4);
+            assert!(with_temp_file(&data, parse).unwrap().is_none());
+        }
+
+        #[test]
+        fn stops_at_truncated_large_size() {
+            let mut data = ftyp();
+            data.extend_from_slice(&1u32.to_be_bytes());
+            data.extend_from_slice(b"mdat\0\0\0\0");
+            assert!(with_temp_file(&data, parse).unwrap().is_none());
+        }
+
+        #[test]
+        fn stops_at_overflowing_large_size() {
+            let mut data = ftyp();
+            data.extend_from_slice(&1u32.to_be_bytes());
+            data.extend_from_slice(b"mdat");
+            data.extend_from_slice(&(u64::MAX - 4).to_be_bytes());
+            data.extend_from_slice(&moov(1000, 5000));
+            assert!(with_temp_file(&data, parse).unwrap().is_none());
+        }
+
+        #[test]
+        fn stops_at_undersized_box() {
+            let mut data = ftyp();
+            data.extend_from_slice(&4u32.to_be_bytes());
+            data.extend_from_slice(b"free");
+            data.extend_from_slice(&moov(1000, 5000));
+            assert!(with_temp_file(&data, parse).unwrap().is_none());
+        }
+
+        #[test]
+        fn in_memory_boxes_stop_at_bad_size() {
+            let mut data = mp4_box(b"free", &[1, 2, 3]);
+            data.extend_from_slice(&100u32.to_be_bytes());
+            data.extend_from_slice(b"skip");
+            let kinds: Vec<_> = boxes(&data).map(|(kind, _)| kind).collect();
+            assert_eq!(kinds, [b"free"]);
+
+            let mut data = 1u32.to_be_bytes().to_vec();
+            data.extend_from_slice(b"free");
+            data.extend_from_slice(&u64::MAX.to_be_bytes());
+            assert_eq!(boxes(&data).count(), 0);
+        }
+
+        #[test]
+        fn locates_sample() {
+            let track = jpeg_track(vec![(1, 2)], vec![100]);
+            assert_eq!(track.sample_location(0.0), Some((100, 10)));
+            assert_eq!(track.sample_location(1.0), Some((110, 10)));
+            // 超出时长时取最后一个采样
+            assert_eq!(track.sample_location(60.0), Some((110, 10)));
+        }
+
+        #[test]
+        fn rejects_zero_first_chunk() {
+            let track = jpeg_track(vec![(0, 2)], vec![100]);
+            assert_eq!(track.sample_location(0.0), None);
+        }
+
+        #[test]
+        fn rejects_overflowing_sample_offset() {
+            let track = jpeg_track(vec![(1, 2)], vec![u64::MAX - 4]);
+            assert_eq!(track.sample_location(1.0), None);
+        }
+    }
+}
+
+/// EBML（WebM、Matroska）
+mod matroska {
+    use super::*;
+
+    const EBML_HEADER: u32 = 0x1A45_DFA3;
+    const DOC_TYPE: u32 = 0x4282;
+    const SEGMENT: u32 = 0x1853_8067;
+    const INFO: u32 = 0x1549_A966;
+    const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+    const DURATION: u32 = 0x4489;
+    const TRACKS: u32 = 0x1654_AE6B;
+    const TRACK_ENTRY: u32 = 0xAE;
+    const TRACK_TYPE: u32 = 0x83;
+    const CODEC_ID: u32 = 0x86;
+    const VIDEO: u32 = 0xE0;
+    const PIXEL_WIDTH: u32 = 0xB0;
+    const PIXEL_HEIGHT: u32 = 0xBA;
+    const PROJECTION: u32 = 0x7670;
+    const PROJECTION_POSE_ROLL: u32 = 0x7675;
+    const AUDIO: u32 = 0xE1;
+    const SAMPLING_FREQUENCY: u32 = 0xB5;
+    const CHANNELS: u32 = 0x9F;
+    const ATTACHMENTS: u32 = 0x1941_A469;
+    const ATTACHED_FILE: u32 = 0x61A7;
+    const FILE_MIME_TYPE: u32 = 0x4660;
+    const FILE_DATA: u32 = 0x465C;
+    const CLUSTER: u32 = 0x1F43_B675;
+
+    pub(super) struct Segment {
+        pub info: ContainerInfo,
+        pub cover_art: Option<Vec<u8>>,
+    }
+
+    /// 解析变长整数，返回 (值, 长度)；`keep_marker` 为 true 时用于元素 ID
+    fn vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+        let first = *data.first()?;
+        let length = first.leading_zeros() as usize + 1;
+        if length > 8 || data.len() < length {
+            return None;
+        }
+        let mut value = if keep_marker {
+            first as u64
+        } else {
+            first as u64 & (0xFF >> length)
+        };
+        for &byte in &data[1..length] {
+            value = (value << 8) | byte as u64;
+        }
+        Some((value, length))
+    }
+
+    /// 解析元素头，返回 (ID, 头长度, 内容长度)；内容长度未知时为 `None`
+    fn element_header(data: &[u8]) -> Option<(u32, usize, Option<u64>)> {
+        let (id, id_len) = vint(data, true)?;
+        if id_len > 4 {
+            return None;
+        }
+        let (size, size_len) = vint(&data[id_len..], false)?;
+        let unknown = size == (1u64 << (7 * size_len)) - 1;
+        Some((id as u32, id_len + size_len, (!unknown).then_some(size)))
+    }
+
+    /// 内存中的子元素迭代器
+    fn elements(mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+        std::iter::from_fn(move || {
+            let (id, header, size) = element_header(data)?;
+            let end = header.checked_add(usize::try_from(size?).ok()?)?;
+            let payload = data.get(header..end)?;
+            data = &data[end..];
+            Some((id, payload))
+        })
+    }
+
+    fn find(data: &[u8], id: u32) -> Option<&[u8]> {
+        elements(data)
+            .find(|(element, _)| *element == id)
+            .map(|(_, payload)| payload)
+    }
+
+    fn uint(data: &[u8]) -> Option<u64> {
+        (data.len() <= 8).then(|| data.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+    }
+
+    fn float(data: &[u8]) -> Option<f64> {
+        match data.len() {
+            4 => Some(f32::from_be_bytes(data.try_into().ok()?) as f64),
+            8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    fn string(data: &[u8]) -> String {
+        String::from_utf8_lossy(data)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+
+    /// 从文件读取元素头
+    fn read_header(file: &mut File, pos: u64) -> io::Result<Option<(u32, usize, Option<u64>)>> {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut buffer = [0u8; 12];
+        let read = file.read(&mut buffer)?;
+        Ok(element_header(&buffer[..read]))
+    }
+
+    pub(super) fn parse(file: &mut File) -> io::Result<Option<Segment>> {
+        let file_len = file.metadata()?.len();
+
+        let Some((EBML_HEADER, header_len, Some(size))) = read_header(file, 0)? else {
+            return Ok(None);
+        };
+        if size > 4096 {
+            return Ok(None);
+        }
+        let ebml = read_exact_at(file, header_len as u64, size)?;
+        let doc_type = find(&ebml, DOC_TYPE).map(string).unwrap_or_default();
+        if doc_type != "webm" && doc_type != "matroska" {
+            return Ok(None);
+        }
+
+        let mut pos = header_len as u64 + size;
+        let Some((SEGMENT, header_len, size)) = read_header(file, pos)? else {
+            return Ok(None);
+        };
+        pos += header_len as u64;
+        let segment_end = size.map_or(file_len, |size| (pos + size).min(file_len));
+
+        let mut segment = Segment {
+            info: ContainerInfo {
+                container: doc_type,
+                ..Default::default()
+            },
+            cover_art: None,
+        };
+
+        // 头部信息位于第一个 Cluster 之前
+        while pos < segment_end {
+            let Some((id, header_len, Some(size))) = read_header(file, pos)? else {
+                break;
+            };
+            if id == CLUSTER {
+                break;
+            }
+            let payload_pos = pos + header_len as u64;
+            if matches!(id, INFO | TRACKS | ATTACHMENTS) && size <= MAX_METADATA_SIZE {
+                let payload = read_exact_at(file, payload_pos, size)?;
+                match id {
+                    INFO => parse_info(&payload, &mut segment.info),
+                    TRACKS => parse_tracks(&payload, &mut segment.info),
+                    _ => segment.cover_art = cover_art(&payload),
+                }
+            }
+            pos = payload_pos + size;
+        }
+
+        Ok(Some(segment))
+    }
+
+    fn parse_info(data: &[u8], info: &mut ContainerInfo) {
+        let timecode_scale = find(data, TIMECODE_SCALE)
+            .and_then(uint)
+            .unwrap_or(1_000_000);
+        info.duration = find(data, DURATION)
+            .and_then(float)
+            .map(|duration| duration * timecode_scale as f64 / 1e9);
+    }
+
+    fn parse_tracks(data: &[u8], info: &mut ContainerInfo) {
+        for (id, entry) in elements(data) {
+            if id != TRACK_ENTRY {
+                continue;
+            }
+            let codec = codec_name(&find(entry, CODEC_ID).map(string).unwrap_or_default());
+
+            match find(entry, TRACK_TYPE).and_then(uint) {
+                Some(1) if info.video.is_none() => {
+                    let video = find(entry, VIDEO).unwrap_or_default();
+                    let roll = find(video, PROJECTION)
+                        .and_then(|projection| find(projection, PROJECTION_POSE_ROLL))
+                        .and_then(float)
+                        .unwrap_or_default();
+                    info.video = Some(VideoTrackInfo {
+                        codec,
+                        width: find(video, PIXEL_WIDTH).and_then(uint).unwrap_or_default() as u32,
+                        height: find(video, PIXEL_HEIGHT).and_then(uint).unwrap_or_default() as u32,
+                        // roll 为逆时针角度
+                        rotation: normalize_rotation(-roll),
+                    });
+                }
+                Some(2) if info.audio.is_none() => {
+                    let audio = find(entry, AUDIO).unwrap_or_default();
+                    info.audio = Some(AudioTrackInfo {
+                        codec,
+                        sample_rate: find(audio, SAMPLING_FREQUENCY)
+                            .and_then(float)
+                            .map(|rate| rate as u32),
+                        channels: find(audio, CHANNELS).and_then(uint).map(|c| c as u32),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// 第一个图片类型的附件，通常是封面
+    fn cover_art(data: &[u8]) -> Option<Vec<u8>> {
+        elements(data)
+            .filter(|(id, _)| *id == ATTACHED_FILE)
+            .find(|(_, file)| {
+                find(file, FILE_MIME_TYPE)
+                    .map(string)
+                    .is_some_and(|mime| mime == "image/jpeg" || mime == "image/png")
+            })
+            .and_then(|(_, file)| find(file, FILE_DATA))
+            .map(<[u8]>::to_vec)
+    }
+
+    fn codec_name(codec_id: &str) -> String {
+        match codec_id {
+            "V_VP8" => "vp8",
+            "V_VP9" => "vp9",
+            "V_AV1" => "av1",
+            "V_MPEG4/ISO/AVC" => "h264",
+            "V_MPEGH/ISO/HEVC" => "hevc",
+            "V_MJPEG" => "mjpeg",
+            "A_OPUS" => "opus",
+            "A_VORBIS" => "vorbis",
+            "A_FLAC" => "flac",
+            "A_MPEG/L3" => "mp3",
+            "A_AC3" => "ac3",
+            "A_EAC3" => "eac3",
+            id if id.starts_with("A_AAC") => "aac",
+            id if id.starts_with("A_PCM") => "pcm",
+            id => return id.to_lowercase(),
+        }
+        .to_string()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// 内容长度固定编码为 8 字节
+        fn element(id: &[u8], payload: &[u8]) -> Vec<u8> {
+            let mut data = id.to_vec();
+            data.push(0x01);
+            data.extend_from_slice(&(payload.len() as u64).to_be_bytes()[1..]);
+            data.extend_from_slice(payload);
+            data
+        }
+
+        fn webm(info: &[u8]) -> Vec<u8> {
+            [
+                element(&EBML_HEADER.to_be_bytes(), &element(&[0x42, 0x82], b"webm")),
+                element(&SEGMENT.to_be_bytes(), &element(&INFO.to_be_bytes(), info)),
+            ]
+            .concat()
+        }
+
+        #[test]
+        fn parses_vint() {
+            assert_eq!(vint(&[0x81], false), Some((1, 1)));
+            assert_eq!(vint(&[0x40, 0x02], false), Some((2, 2)));
+            assert_eq!(vint(&[0x81], true), Some((0x81, 1)));
+            assert_eq!(
+                vint(&[0x1A, 0x45, 0xDF, 0xA3], true),
+                Some((EBML_HEADER as u64, 4))
+            );
+            assert_eq!(vint(&[0x01, 0, 0, 0, 0, 0, 1, 0], false), Some((256, 8)));
+        }
+
+        #[test]
+        fn rejects_invalid_vint() {
+            assert_eq!(vint(&[], false), None);
+            // 首字节为 0 时长度超过 8 字节
+            assert_eq!(vint(&[0x00, 0x81], false), None);
+            // 长度不足
+            assert_eq!(vint(&[0x40], false), None);
+            assert_eq!(vint(&[0x1A, 0x45, 0xDF], true), None);
+        }
+
+        #[test]
+        fn parses_element_header() {
+            assert_eq!(
+                element_header(&[0x42, 0x82, 0x84]),
+                Some((DOC_TYPE, 3, Some(4)))
+            );
+            // 全 1 表示内容长度未知
+            assert_eq!(
+                element_header(&[0x1F, 0x43, 0xB6, 0x75, 0xFF]),
+                Some((CLUSTER, 5, None))
+            );
+            // ID 最长 4 字节
+            assert_eq!(element_header(&[0x08, 0, 0, 0, 0, 0x81]), None);
+        }
+
+        #[test]
+        fn elements_stop_at_truncated_payload() {
+            let data = [element(&[0x83], &[1]), vec![0x86, 0x85, b'V', b'_']].concat();
+            let ids: Vec<_> = elements(&data).map(|(id, _)| id).collect();
+            assert_eq!(ids, [TRACK_TYPE]);
+
+            let huge = [0x86, 0x01, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+            assert_eq!(elements(&huge).count(), 0);
+        }
+
+        #[test]
+        fn parses_duration() {
+            let info = [
+                element(&TIMECODE_SCALE.to_be_bytes()[1..], &[0x0F, 0x42, 0x40]),
+                element(&DURATION.to_be_bytes()[2..], &2500f32.to_be_bytes()),
+            ]
+            .concat();
+            let segment = with_temp_file(&webm(&info), parse).unwrap().unwrap();
+            assert_eq!(segment.info.container, "webm");
+            assert_eq!(segment.info.duration, Some(2.5));
+        }
+
+        #[test]
+        fn rejects_other_doc_type() {
+            let data = element(&EBML_HEADER.to_be_bytes(), &element(&[0x42, 0x82], b"mka"));
+            assert!(with_temp_file(&data, parse).unwrap().is_none());
+        }
+    }
+}
+
+/// Ogg（Opus、Vorbis、FLAC、Theora）
+mod ogg {
+    use super::*;
+
+    /// 读取文件头部的长度，足够容纳各逻辑流的首页
+    const HEAD_SIZE: u64 = 64 * 1024;
+
+    /// 读取文件尾部的长度，用于查找最后一页的 granule position
+    const TAIL_SIZE: u64 = 64 * 1024;
+
+    /// 首页标志
+    const BEGINNING_OF_STREAM: u8 = 0x02;
+
+    struct Page<'a> {
+        header_type: u8,
+        serial: u32,
+        body: &'a [u8],
+        len: usize,
+    }
+
+    fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(
+            data.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    }
+
+    fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+
+    fn le_u64(data: &[u8], offset: usize) -> Option<u64> {
+        Some(u64::from_le_bytes(
+            data.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    }
+
+    fn parse_page(data: &[u8]) -> Option<Page<'_>> {
+        if !data.starts_with(b"OggS") {
+            return None;
+        }
+        let segments = *data.get(26)? as usize;
+        let body_start = 27 + segments;
+        let body_len: usize = data.get(27..body_start)?.iter().map(|&s| s as usize).sum();
+        Some(Page {
+            header_type: data[5],
+            serial: le_u32(data, 14)?,
+            body: data.get(body_start..body_start + body_len)?,
+            len: body_start + body_len,
+        })
+    }
+
+    /// 用于计算时长的音频流
+    struct AudioStream {
+        serial: u32,
+        /// granule position 每秒的增量
+        granule_rate: u32,
+        /// 解码器开头丢弃的采样数
+        pre_skip: u64,
+    }
+
+    pub(super) fn parse(file: &mut File) -> io::Result<Option<ContainerInfo>> {
+        let head = read_at_most(file, 0, HEAD_SIZE)?;
+        if !head.starts_with(b"OggS") {
+            return Ok(None);
+        }
+
+        let mut info = ContainerInfo {
+            container: "ogg".to_string(),
+            ..Default::default()
+        };
+        let mut audio_stream = None;
+
+        // 各逻辑流的首页都位于文件开头，首页中只有识别头
+        let mut pos = 0;
+        while let Some(page) = parse_page(&head[pos..]) {
+            if page.header_type & BEGINNING_OF_STREAM == 0 {
+                break;
+            }
+            pos += page.len;
+            let body = page.body;
+
+            if body.starts_with(b"OpusHead") && info.audio.is_none() {
+                info.audio = Some(AudioTrackInfo {
+                    codec: "opus".to_string(),
+                    // Opus 始终以 48kHz 解码
+                    sample_rate: Some(48_000),
+                    channels: body.get(9).map(|&channels| channels as u32),
+                });
+                audio_stream = Some(AudioStream {
+                    serial: page.serial,
+                    granule_rate: 48_000,
+                    pre_skip: le_u16(body, 10).unwrap_or_default() as u64,
+                });
+            } else if body.starts_with(b"\x01vorbis") && info.audio.is_none() {
+                let sample_rate = le_u32(body, 12).unwrap_or_default();
+                info.audio = Some(AudioTrackInfo {
+                    codec: "vorbis".to_string(),
+                    sample_rate: Some(sample_rate),
+                    channels: body.get(11).map(|&channels| channels as u32),
+                });
+                // 标称码率，为 0 表示未设置
+                info.bitrate = le_u32(body, 20)
+                    .filter(|&bitrate| bitrate as i32 > 0)
+                    .map(u64::from);
+                audio_stream = Some(AudioStream {
+                    serial: page.serial,
+                    granule_rate: sample_rate,
+                    pre_skip: 0,
+                });
+            } else if body.starts_with(b"\x7fFLAC") && info.audio.is_none() {
+                // 映射头(9) + "fLaC"(4) + 元数据块头(4)，之后是 STREAMINFO
+                let Some(stream_info) = body.get(17..35) else {
+                    continue;
+                };
+                let sample_rate = (stream_info[10] as u32) << 12
+                    | (stream_info[11] as u32) << 4
+                    | (stream_info[12] as u32) >> 4;
+                info.audio = Some(AudioTrackInfo {
+                    codec: "flac".to_string(),
+                    sample_rate: Some(sample_rate),
+                    channels: Some(((stream_info[12] >> 1) & 0x07) as u32 + 1),
+                });
+                audio_stream = Some(AudioStream {
+                    serial: page.serial,
+                    granule_rate: sample_rate,
+                    pre_skip: 0,
+                });
+            } else if body.starts_with(b"\x80theora") && info.video.is_none() {
+                let u24 = |offset: usize| {
+                    body.get(offset..offset + 3)
+                        .map(|b| (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32)
+                        .unwrap_or_default()
+                };
+                info.video = Some(VideoTrackInfo {
+                    codec: "theora".to_string(),
+                    width: u24(14),
+                    height: u24(17),
+                    rotation: 0,
+                });
+            }
+        }
+
+        if let Some(stream) = audio_stream.filter(|stream| stream.granule_rate > 0) {
+            let file_len = file.metadata()?.len();
+            let tail_start = file_len.saturating_sub(TAIL_SIZE);
+            let tail = read_at_most(file, tail_start, TAIL_SIZE)?;
+            info.duration = last_granule(&tail, stream.serial).map(|granule| {
+                granule.saturating_sub(stream.pre_skip) as f64 / stream.granule_rate as f64
+            });
+        }
+
+        Ok(Some(info))
+    }
+
+    /// 从后往前查找指定逻辑流最后一个有效的 granule position
+    fn last_granule(data: &[u8], serial: u32) -> Option<u64> {
+        (0..data.len().saturating_sub(27))
+            .rev()
+            .filter(|&pos| data[pos..].starts_with(b"OggS"))
+            .find_map(|pos| {
+                let granule = le_u64(data, pos + 6)?;
+                // -1 表示该页没有结束的数据包
+                (le_u32(data, pos + 14)? == serial && granule != u64::MAX).then_some(granule)
+            })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn page(header_type: u8, granule: u64, serial: u32, body: &[u8]) -> Vec<u8> {
+            let mut data = b"OggS\0".to_vec();
+            data.push(header_type);
+            data.extend_from_slice(&granule.to_le_bytes());
+            data.extend_from_slice(&serial.to_le_bytes());
+            // 页序号与校验和
+            data.extend_from_slice(&[0; 8]);
+            let mut lacing = vec![255u8; body.len() / 255];
+            lacing.push((body.len() % 255) as u8);
+            data.push(lacing.len() as u8);
+            data.extend_from_slice(&lacing);
+            data.extend_from_slice(body);
+            data
+        }
+
+        fn opus_head(pre_skip: u16) -> Vec<u8> {
+            let mut head = b"OpusHead\x01\x02".to_vec();
+            head.extend_from_slice(&pre_skip.to_le_bytes());
+            head.extend_from_slice(&48_000u32.to_le_bytes());
+            head.extend_from_slice(&[0, 0, 0]);
+            head
+        }
+
+        #[test]
+        fn parses_page() {
+            let body = vec![7u8; 300];
+            let data = page(BEGINNING_OF_STREAM, 0, 9, &body);
+            let parsed = parse_page(&data).unwrap();
+            assert_eq!(parsed.header_type, BEGINNING_OF_STREAM);
+            assert_eq!(parsed.serial, 9);
+            assert_eq!(parsed.body, body);
+            assert_eq!(parsed.len, data.len());
+        }
+
+        #[test]
+        fn rejects_truncated_page() {
+            let data = page(0, 0, 9, &[7; 300]);
+            assert!(parse_page(&data[..data.len() - 1]).is_none());
+            assert!(parse_page(&data[..20]).is_none());
+            assert!(parse_page(b"OggX").is_none());
+        }
+
+        #[test]
+        fn finds_last_granule_of_stream() {
+            let data = [
+                page(0, 960, 1, &[0; 10]),
+                page(0, 1920, 1, &[0; 10]),
+                page(0, 5000, 2, &[0; 10]),
+                // 没有结束数据包的页
+                page(0, u64::MAX, 1, &[0; 10]),
+            ]
+            .concat();
+            assert_eq!(last_granule(&data, 1), Some(1920));
+            assert_eq!(last_granule(&data, 2), Some(5000));
+            assert_eq!(last_granule(&data, 3), None);
+        }
+
+        #[test]
+        fn opus_duration_excludes_pre_skip() {
+            let data = [
+                page(BEGINNING_OF_STREAM, 0, 1, &opus_head(312)),
+                page(0, 0, 1, b"OpusTags"),
+                page(0, 48_000 + 312, 1, &[0; 10]),
+            ]
+            .concat();
+            let info = with_temp_file(&data, parse).unwrap().unwrap();
+            let audio = info.audio.unwrap();
+            assert_eq!(audio.codec, "opus");
+            assert_eq!(audio.channels, Some(2));
+            assert_eq!(info.duration, Some(1.0));
+        }
+
+        #[test]
+        fn pre_skip_larger_than_granule_is_zero() {
+            let data = [
+                page(BEGINNING_OF_STREAM, 0, 1, &opus_head(312)),
+                page(0, 100, 1, &[0; 10]),
+            ]
+            .concat();
+            let info = with_temp_file(&data, parse).unwrap().unwrap();
+            assert_eq!(info.duration, Some(0.0));
+        }
+    }
+}
+
+/// MPEG 音频（MP3）
+mod mp3 {
+    use super::*;
+
+    /// 查找第一个音频帧的范围
+    const SCAN_SIZE: u64 = 64 * 1024;
+
+    /// 码率表（kbit/s），按索引 1-14
+    const BITRATES_V1_L1: [u32; 14] = [
+        32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ];
+    const BITRATES_V1_L2: [u32; 14] = [
+        32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ];
+    const BITRATES_V1_L3: [u32; 14] = [
+        32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const BITRATES_V2_L1: [u32; 14] = [
+        32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ];
+    const BITRATES_V2_L23: [u32; 14] = [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+    struct FrameHeader {
+        mpeg1: bool,
+        layer: u32,
+        /// bit/s
+        bitrate: u32,
+        sample_rate: u32,
+        mono: bool,
+        samples_per_frame: u32,
+        frame_len: usize,
+    }
+
+    fn parse_header(data: &[u8]) -> Option<FrameHeader> {
+        let header = be_u32(data, 0)?;
+        if header >> 21 != 0x7FF {
+            return None;
+        }
+        // 版本：0 为 MPEG 2.5，2 为 MPEG 2，3 为 MPEG 1
+        let version = (header >> 19) & 0x03;
+        let layer = 4 - ((header >> 17) & 0x03);
+        let bitrate_index = ((header >> 12) & 0x0F) as usize;
+        let sample_rate_index = ((header >> 10) & 0x03) as usize;
+        if version == 1
+            || layer == 4
+            || !(1..=14).contains(&bitrate_index)
+            || sample_rate_index == 3
+        {
+            return None;
+        }
+
+        let mpeg1 = version == 3;
+        let bitrates = match (mpeg1, layer) {
+            (true, 1) => &BITRATES_V1_L1,
+            (true, 2) => &BITRATES_V1_L2,
+            (true, _) => &BITRATES_V1_L3,
+            (false, 1) => &BITRATES_V2_L1,
+            (false, _) => &BITRATES_V2_L23,
+        };
+        let bitrate = bitrates[bitrate_index - 1] * 1000;
+        let sample_rate = [44_100, 48_000, 32_000][sample_rate_index] >> (3 - version).min(2);
+        let padding = (header >> 9) & 0x01;
+        let samples_per_frame = match layer {
+            1 => 384,
+            3 if !mpeg1 => 576,
+            _ => 1152,
+        };
+        let frame_len = if layer == 1 {
+            (12 * bitrate / sample_rate + padding) * 4
+        } else {
+            samples_per_frame / 8 * bitrate / sample_rate + padding
+        };
+
+        Some(FrameHeader {
+            mpeg1,
+            layer,
+            bitrate,
+            sample_rate,
+            mono: (header >> 6) & 0x03 == 3,
+            samples_per_frame,
+            frame_len: frame_len as usize,
+        })
+    }
+
+    /// 查找第一个后面紧跟着另一个帧头的音频帧，避免把任意数据误判为帧同步
+    fn find_first_frame(data: &[u8]) -> Option<(usize, FrameHeader)> {
+        (0..data.len().saturating_sub(4)).find_map(|pos| {
+            let header = parse_header(&data[pos..])?;
+            let next = pos + header.frame_len;
+            let confirmed = match data.get(next..) {
+                Some(rest) if rest.len() >= 4 => {
+                    parse_header(rest).is_some_and(|next| next.sample_rate == header.sample_rate)
+                }
+                // 文件只有一帧
+                _ => next == data.len(),
+            };
+            confirmed.then_some((pos, header))
+        })
+    }
+
+    pub(super) fn parse(file: &mut File) -> io::Result<Option<ContainerInfo>> {
+        let file_len = file.metadata()?.len();
+
+        // 跳过 ID3v2 标签
+        let id3 = read_at_most(file, 0, 10)?;
+        let mut audio_start = 0u64;
+        if id3.len() == 10 && id3.starts_with(b"ID3") {
+            let size = id3[6..10]
+                .iter()
+                .fold(0u64, |size, &byte| (size << 7) | (byte & 0x7F) as u64);
+            let footer = if id3[5] & 0x10 != 0 { 10 } else { 0 };
+            audio_start = 10 + size + footer;
+        }
+
+        let data = read_at_most(file, audio_start, SCAN_SIZE)?;
+        let Some((offset, header)) = find_first_frame(&data) else {
+            return Ok(None);
+        };
+        // 没有 ID3 标签时音频帧应当从文件开头开始
+        if audio_start == 0 && offset > 0 {
+            return Ok(None);
+        }
+        let frame = &data[offset..];
+
+        // VBR 文件的第一帧是 Xing/Info 或 VBRI 头，记录了总帧数
+        let side_info = match (header.mpeg1, header.mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        };
+        let xing = 4 + side_info;
+        let frames = if matches!(frame.get(xing..xing + 4), Some(b"Xing" | b"Info")) {
+            be_u32(frame, xing + 4)
+                .filter(|flags| flags & 0x01 != 0)
+                .and_then(|_| be_u32(frame, xing + 8))
+        } else if frame.get(36..40) == Some(b"VBRI") {
+            be_u32(frame, 36 + 14)
+        } else {
+            None
+        };
+
+        // 末尾的 ID3v1 标签不计入音频数据
+        let id3v1 = read_at_most(file, file_len.saturating_sub(128), 3)?;
+        let tag_len = if id3v1 == b"TAG" { 128 } else { 0 };
+        let audio_bytes = file_len.saturating_sub(audio_start + offset as u64 + tag_len);
+
+        let duration = match frames {
+            Some(frames) if frames > 0 => {
+                frames as f64 * header.samples_per_frame as f64 / header.sample_rate as f64
+            }
+            _ => audio_bytes as f64 * 8.0 / header.bitrate as f64,
+        };
+        let bitrate = match frames {
+            Some(frames) if frames > 0 && duration > 0.0 => {
+                (audio_bytes as f64 * 8.0 / duration) as u64
+            }
+            _ => header.bitrate as u64,
+        };
+
+        Ok(Some(ContainerInfo {
+            container: "mp3".to_string(),
+            duration: Some(duration),
+            bitrate: Some(bitrate),
+            video: None,
+            audio: Some(AudioTrackInfo {
+                codec: format!("mp{}", header.layer),
+                sample_rate: Some(header.sample_rate),
+                channels: Some(if header.mono { 1 } else { 2 }),
+            }),
+        }))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// MPEG 1 Layer 3，128 kbit/s，44.1 kHz，联合立体声
+        const MPEG1_L3: [u8; 4] = [0xFF, 0xFB, 0x90, 0x64];
+
+        fn frame(header: [u8; 4]) -> Vec<u8> {
+            let mut data = header.to_vec();
+            data.resize(parse_header(&header).unwrap().frame_len, 0);
+            data
+        }
+
+        #[test]
+        fn parses_mpeg1_header() {
+            let header = parse_header(&MPEG1_L3).unwrap();
+            assert!(header.mpeg1);
+            assert_eq!(header.layer, 3);
+            assert_eq!(header.bitrate, 128_000);
+            assert_eq!(header.sample_rate, 44_100);
+            assert!(!header.mono);
+            assert_eq!(header.samples_per_frame, 1152);
+            assert_eq!(header.frame_len, 417);
+        }
+
+        #[test]
+        fn parses_mpeg2_header() {
+            // MPEG 2 Layer 3，80 kbit/s，22.05 kHz，单声道，带填充
+            let header = parse_header(&[0xFF, 0xF3, 0x92, 0xC4]).unwrap();
+            assert!(!header.mpeg1);
+            assert_eq!(header.bitrate, 80_000);
+            assert_eq!(header.sample_rate, 22_050);
+            assert!(header.mono);
+            assert_eq!(header.samples_per_frame, 576);
+            assert_eq!(header.frame_len, 262);
+        }
+
+        #[test]
+        fn rejects_invalid_header() {
+            assert!(parse_header(&[0xFF, 0xFB, 0x90]).is_none());
+            // 没有帧同步
+            assert!(parse_header(&[0xFF, 0x1B, 0x90, 0x64]).is_none());
+            // 保留的版本号
+            assert!(parse_header(&[0xFF, 0xEB, 0x90, 0x64]).is_none());
+            // 保留的层
+            assert!(parse_header(&[0xFF, 0xF9, 0x90, 0x64]).is_none());
+            // 无效的码率索引
+            assert!(parse_header(&[0xFF, 0xFB, 0xF0, 0x64]).is_none());
+            assert!(parse_header(&[0xFF, 0xFB, 0x00, 0x64]).is_none());
+            // 保留的采样率
+            assert!(parse_header(&[0xFF, 0xFB, 0x9C, 0x64]).is_none());
+        }
+
+        #[test]
+        fn first_frame_needs_following_header() {
+            let data = [vec![0xFF, 0xFB, 0x00], frame(MPEG1_L3), frame(MPEG1_L3)].concat();
+            assert_eq!(find_first_frame(&data).map(|(offset, _)| offset), Some(3));
+
+            // 只有一帧时必须正好到数据末尾
+            assert!(find_first_frame(&frame(MPEG1_L3)).is_some());
+            let mut truncated = frame(MPEG1_L3);
+            truncated.push(0);
+            assert!(find_first_frame(&truncated).is_none());
+        }
+
+        #[test]
+        fn estimates_cbr_duration() {
+            let data = [frame(MPEG1_L3), frame(MPEG1_L3)].concat();
+            let info = with_temp_file(&data, parse).unwrap().unwrap();
+            assert_eq!(info.container, "mp3");
+            assert_eq!(info.bitrate, Some(128_000));
+            assert_eq!(info.duration, Some(834.0 * 8.0 / 128_000.0));
+        }
+
+        #[test]
+        fn skips_id3v2_tag() {
+            let mut data = b"ID3\x04\0\0\0\0\0\x05".to_vec();
+            data.extend_from_slice(&[0; 5]);
+            data.extend_from_slice(&frame(MPEG1_L3));
+            data.extend_from_slice(&frame(MPEG1_L3));
+            let info = with_temp_file(&data, parse).unwrap().unwrap();
+            assert_eq!(info.duration, Some(834.0 * 8.0 / 128_000.0));
+        }
+
+        #[test]
+        fn rejects_leading_garbage_without_tag() {
+            let data = [vec![0; 3], frame(MPEG1_L3), frame(MPEG1_L3)].concat();
+            assert!(with_temp_file(&data, parse).unwrap().is_none());
+        }
+    }
+}
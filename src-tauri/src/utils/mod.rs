@@ -1 +1,11 @@
+pub mod account_storage;
+pub mod attachment_crypto;
+pub mod blurhash;
+pub mod db_cipher;
+pub mod ffmpeg;
+pub mod fts_tokenizer;
+pub mod image_decode;
+pub mod image_metadata;
+pub mod media_probe;
+pub mod secret_store;
 pub mod sql_debug;
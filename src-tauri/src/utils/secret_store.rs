@@ -0,0 +1,431 @@
+//! 敏感信息存储
+//!
+//! 优先使用系统钥匙串：macOS 钥匙串、Windows 凭据管理器、Linux Secret Service（libsecret）。
+//! 没有可用的钥匙串时（如 Linux 上未运行 keyring 守护进程，或在移动端），退回到应用数据目录中的
+//! 加密文件：内容使用 AES-256-CTR 加密并以 HMAC-SHA256 校验，密钥单独保存在仅当前用户可读的文件中。
+//! 加密文件只能防止数据库或备份泄露时连带泄露令牌，无法防御能读取应用目录的本地进程。
+//!
+//! 数据库中只保存形如 `keyring:<名称>` 或 `file:<名称>` 的引用。所有函数都是阻塞的，
+//! 需要在 `spawn_blocking` 中调用。
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use aes::Aes256;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use tracing::warn;
+
+use crate::error::AppError;
+
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// 钥匙串中的引用前缀
+const KEYRING_PREFIX: &str = "keyring:";
+
+/// 加密文件中的引用前缀
+const FILE_PREFIX: &str = "file:";
+
+/// 加密文件的密钥文件名
+const KEY_FILE: &str = "secrets.key";
+
+/// 加密文件名
+const SECRETS_FILE: &str = "secrets.json";
+
+const NONCE_SIZE: usize = 16;
+const MAC_SIZE: usize = 32;
+
+static STORE: OnceLock<SecretStore> = OnceLock::new();
+
+struct SecretStore {
+    /// 钥匙串中的服务名，使用应用标识
+    service: String,
+    /// 加密文件所在目录
+    fallback_dir: PathBuf,
+    /// 钥匙串访问失败后不再尝试，避免每次都等待 D-Bus 超时
+    keyring_available: AtomicBool,
+    /// 加密文件的读写锁
+    file_lock: Mutex<()>,
+}
+
+/// 初始化存储，应用启动时调用一次
+pub fn init(service: &str, fallback_dir: PathBuf) {
+    let _ = STORE.set(SecretStore {
+        service: service.to_string(),
+        fallback_dir,
+        keyring_available: AtomicBool::new(cfg!(desktop)),
+        file_lock: Mutex::new(()),
+    });
+}
+
+fn store() -> Result<&'static SecretStore, AppError> {
+    STORE
+        .get()
+        .ok_or_else(|| AppError::Unexpected("Secret store is not initialized".to_string()))
+}
+
+/// 是否为本模块生成的引用
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(KEYRING_PREFIX) || value.starts_with(FILE_PREFIX)
+}
+
+/// 保存敏感信息，返回写入数据库的引用
+pub fn save(name: &str, secret: &str) -> Result<String, AppError> {
+    let store = store()?;
+    if store.keyring_available.load(Ordering::Relaxed) {
+        match keyring_set(&store.service, name, secret) {
+            Ok(()) => {
+                // 之前可能退回过加密文件，删除旧副本
+                let _ = file_delete(store, name);
+                return Ok(format!("{KEYRING_PREFIX}{name}"));
+            }
+            Err(e) => {
+                warn!(
+                    "System keyring unavailable, falling back to encrypted file: {}",
+                    e
+                );
+                store.keyring_available.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    file_set(store, name, secret).map_err(|e| AppError::Io(e.to_string()))?;
+    Ok(format!("{FILE_PREFIX}{name}"))
+}
+
+/// 按引用读取敏感信息，不存在时返回 `None`
+pub fn load(reference: &str) -> Result<Option<String>, AppError> {
+    let store = store()?;
+    if let Some(name) = reference.strip_prefix(KEYRING_PREFIX) {
+        keyring_get(&store.service, name)
+    } else if let Some(name) = reference.strip_prefix(FILE_PREFIX) {
+        file_get(store, name)
+    } else {
+        Err(AppError::Unexpected(format!(
+            "Invalid secret reference: {reference}"
+        )))
+    }
+}
+
+/// 按名称读取敏感信息，用于无法在数据库中保存引用的条目（如数据库密钥）
+///
+/// 钥匙串读取失败时返回错误而不是当作不存在：返回 `Ok(None)` 表示加密文件与钥匙串中都确定
+/// 没有该条目，调用方可以据此判断条目已丢失。
+pub fn load_by_name(name: &str) -> Result<Option<String>, AppError> {
+    let store = store()?;
+    // 钥匙串写入失败时条目只保存在加密文件中
+    if let Some(secret) = file_get(store, name)? {
+        return Ok(Some(secret));
+    }
+    keyring_get(&store.service, name)
+}
+
+/// 按名称删除敏感信息，钥匙串与加密文件中的副本都会删除
+pub fn delete_by_name(name: &str) -> Result<(), AppError> {
+    let store = store()?;
+    if store.keyring_available.load(Ordering::Relaxed) {
+        keyring_delete(&store.service, name)?;
+    }
+    file_delete(store, name).map_err(|e| AppError::Io(e.to_string()))
+}
+
+/// 按引用删除敏感信息，不存在时忽略
+pub fn delete(reference: &str) -> Result<(), AppError> {
+    let store = store()?;
+    if let Some(name) = reference.strip_prefix(KEYRING_PREFIX) {
+        keyring_delete(&store.service, name)
+    } else if let Some(name) = reference.strip_prefix(FILE_PREFIX) {
+        file_delete(store, name).map_err(|e| AppError::Io(e.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(desktop)]
+fn keyring_set(service: &str, name: &str, secret: &str) -> Result<(), keyring::Error> {
+    keyring::Entry::new(service, name)?.set_password(secret)
+}
+
+#[cfg(not(desktop))]
+fn keyring_set(_service: &str, _name: &str, _secret: &str) -> Result<(), AppError> {
+    Err(AppError::Unexpected(
+        "System keyring is not supported on this platform".to_string(),
+    ))
+}
+
+#[cfg(desktop)]
+fn keyring_get(service: &str, name: &str) -> Result<Option<String>, AppError> {
+    match keyring::Entry::new(service, name).and_then(|entry| entry.get_password()) {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(AppError::Unexpected(format!(
+            "Failed to read from system keyring: {e}"
+        ))),
+    }
+}
+
+#[cfg(not(desktop))]
+fn keyring_get(_service: &str, _name: &str) -> Result<Option<String>, AppError> {
+    Ok(None)
+}
+
+#[cfg(desktop)]
+fn keyring_delete(service: &str, name: &str) -> Result<(), AppError> {
+    match keyring::Entry::new(service, name).and_then(|entry| entry.delete_credential()) {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(AppError::Unexpected(format!(
+            "Failed to delete from system keyring: {e}"
+        ))),
+    }
+}
+
+#[cfg(not(desktop))]
+fn keyring_delete(_service: &str, _name: &str) -> Result<(), AppError> {
+    Ok(())
+}
+
+fn file_get(store: &SecretStore, name: &str) -> Result<Option<String>, AppError> {
+    let _guard = store.file_lock.lock().unwrap_or_else(|e| e.into_inner());
+    let secrets = read_secrets(&store.fallback_dir).map_err(|e| AppError::Io(e.to_string()))?;
+    let Some(encrypted) = secrets.get(name) else {
+        return Ok(None);
+    };
+    let key = read_or_create_key(&store.fallback_dir).map_err(|e| AppError::Io(e.to_string()))?;
+    decrypt(&key, name, encrypted).map(Some)
+}
+
+fn file_set(store: &SecretStore, name: &str, secret: &str) -> io::Result<()> {
+    let _guard = store.file_lock.lock().unwrap_or_else(|e| e.into_inner());
+    let key = read_or_create_key(&store.fallback_dir)?;
+    let mut secrets = read_secrets(&store.fallback_dir)?;
+    secrets.insert(name.to_string(), encrypt(&key, name, secret));
+    write_secrets(&store.fallback_dir, &secrets)
+}
+
+fn file_delete(store: &SecretStore, name: &str) -> io::Result<()> {
+    let _guard = store.file_lock.lock().unwrap_or_else(|e| e.into_inner());
+    let mut secrets = read_secrets(&store.fallback_dir)?;
+    if secrets.remove(name).is_some() {
+        write_secrets(&store.fallback_dir, &secrets)?;
+    }
+    Ok(())
+}
+
+fn read_secrets(dir: &Path) -> io::Result<HashMap<String, String>> {
+    match fs::read(dir.join(SECRETS_FILE)) {
+        Ok(data) => serde_json::from_slice(&data).map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 先写临时文件再替换，避免写入中断导致所有条目丢失
+fn write_secrets(dir: &Path, secrets: &HashMap<String, String>) -> io::Result<()> {
+    let data = serde_json::to_vec(secrets).map_err(io::Error::other)?;
+    let path = dir.join(SECRETS_FILE);
+    let temp_path = dir.join(format!("{SECRETS_FILE}.tmp"));
+    write_private(&temp_path, &data)?;
+    fs::rename(temp_path, path)
+}
+
+/// 读取加密密钥，不存在时生成：前 32 字节用于加密，后 32 字节用于校验
+fn read_or_create_key(dir: &Path) -> io::Result<[u8; 64]> {
+    let path = dir.join(KEY_FILE);
+    match fs::read(&path) {
+        Ok(data) => data
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "密钥文件已损坏")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut key = [0u8; 64];
+            rand::rng().fill_bytes(&mut key);
+            fs::create_dir_all(dir)?;
+            write_private(&path, &key)?;
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// 写入仅当前用户可读写的文件
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+fn new_mac(key: &[u8; 64], name: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&key[32..]).expect("HMAC accepts any key length");
+    // 校验范围包含条目名称，防止条目之间互换
+    mac.update(name.as_bytes());
+    mac
+}
+
+/// 加密为 base64(nonce || 密文 || HMAC)
+fn encrypt(key: &[u8; 64], name: &str, secret: &str) -> String {
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::rng().fill_bytes(&mut nonce);
+
+    let mut data = secret.as_bytes().to_vec();
+    Aes256Ctr::new(key[..32].into(), &nonce.into()).apply_keystream(&mut data);
+
+    let mut mac = new_mac(key, name);
+    mac.update(&nonce);
+    mac.update(&data);
+
+    let mut output = Vec::with_capacity(NONCE_SIZE + data.len() + MAC_SIZE);
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&data);
+    output.extend_from_slice(&mac.finalize().into_bytes());
+    STANDARD.encode(output)
+}
+
+fn decrypt(key: &[u8; 64], name: &str, encrypted: &str) -> Result<String, AppError> {
+    let integrity_error = || AppError::IntegrityCheckFailed(format!("Secret {name} is corrupted"));
+    let data = STANDARD.decode(encrypted).map_err(|_| integrity_error())?;
+    if data.len() < NONCE_SIZE + MAC_SIZE {
+        return Err(integrity_error());
+    }
+    let (nonce, rest) = data.split_at(NONCE_SIZE);
+    let (ciphertext, tag) = rest.split_at(rest.len() - MAC_SIZE);
+
+    let mut mac = new_mac(key, name);
+    mac.update(nonce);
+    mac.update(ciphertext);
+    mac.verify_slice(tag).map_err(|_| integrity_error())?;
+
+    let mut plaintext = ciphertext.to_vec();
+    let nonce: [u8; NONCE_SIZE] = nonce.try_into().map_err(|_| integrity_error())?;
+    Aes256Ctr::new(key[..32].into(), &nonce.into()).apply_keystream(&mut plaintext);
+    String::from_utf8(plaintext).map_err(|e| AppError::Decryption(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> [u8; 64] {
+        let mut key = [0u8; 64];
+        rand::rng().fill_bytes(&mut key);
+        key
+    }
+
+    fn test_store(name: &str) -> SecretStore {
+        let fallback_dir =
+            std::env::temp_dir().join(format!("secret-store-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&fallback_dir);
+        SecretStore {
+            service: "test".to_string(),
+            fallback_dir,
+            keyring_available: AtomicBool::new(false),
+            file_lock: Mutex::new(()),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let key = test_key();
+        for secret in ["", "token", "密钥 🔑"] {
+            let encrypted = encrypt(&key, "name", secret);
+            assert_eq!(decrypt(&key, "name", &encrypted).unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn uses_fresh_nonce() {
+        let key = test_key();
+        assert_ne!(
+            encrypt(&key, "name", "token"),
+            encrypt(&key, "name", "token")
+        );
+    }
+
+    #[test]
+    fn rejects_other_name_or_key() {
+        let key = test_key();
+        let encrypted = encrypt(&key, "name", "token");
+        assert!(matches!(
+            decrypt(&key, "other", &encrypted),
+            Err(AppError::IntegrityCheckFailed(_))
+        ));
+        assert!(matches!(
+            decrypt(&test_key(), "name", &encrypted),
+            Err(AppError::IntegrityCheckFailed(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_data() {
+        let key = test_key();
+        let mut data = STANDARD.decode(encrypt(&key, "name", "token")).unwrap();
+        data[NONCE_SIZE] ^= 1;
+        assert!(matches!(
+            decrypt(&key, "name", &STANDARD.encode(&data)),
+            Err(AppError::IntegrityCheckFailed(_))
+        ));
+
+        let truncated = STANDARD.encode(&data[..NONCE_SIZE + MAC_SIZE - 1]);
+        assert!(matches!(
+            decrypt(&key, "name", &truncated),
+            Err(AppError::IntegrityCheckFailed(_))
+        ));
+        assert!(matches!(
+            decrypt(&key, "name", "not base64!"),
+            Err(AppError::IntegrityCheckFailed(_))
+        ));
+    }
+
+    #[test]
+    fn file_store_round_trips() {
+        let store = test_store("round-trip");
+        file_set(&store, "a", "first").unwrap();
+        file_set(&store, "b", "second").unwrap();
+        assert_eq!(file_get(&store, "a").unwrap().as_deref(), Some("first"));
+        assert_eq!(file_get(&store, "b").unwrap().as_deref(), Some("second"));
+
+        file_delete(&store, "a").unwrap();
+        assert_eq!(file_get(&store, "a").unwrap(), None);
+        assert_eq!(file_get(&store, "b").unwrap().as_deref(), Some("second"));
+
+        // 文件中只有密文
+        let secrets = fs::read_to_string(store.fallback_dir.join(SECRETS_FILE)).unwrap();
+        assert!(!secrets.contains("second"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(store.fallback_dir.join(KEY_FILE))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let _ = fs::remove_dir_all(&store.fallback_dir);
+    }
+
+    #[test]
+    fn rejects_corrupted_key_file() {
+        let store = test_store("corrupted-key");
+        file_set(&store, "a", "first").unwrap();
+        fs::write(store.fallback_dir.join(KEY_FILE), [0u8; 10]).unwrap();
+        assert!(matches!(file_get(&store, "a"), Err(AppError::Io(_))));
+
+        let _ = fs::remove_dir_all(&store.fallback_dir);
+    }
+}
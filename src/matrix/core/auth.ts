@@ -32,7 +32,7 @@ export function buildTokenRefreshFunction(baseUrl: string) {
     try {
       const newAccess = tokens.access_token
       const newRefresh = tokens.refresh_token || refreshToken
-      await invoke(TauriCommand.UPDATE_TOKEN, { token: newAccess, refresh_token: newRefresh }).catch(() => {})
+      await invoke(TauriCommand.UPDATE_TOKEN, { token: newAccess, refreshToken: newRefresh }).catch(() => {})
     } catch {}
     return tokens
   }
@@ -104,7 +104,9 @@ export class AuthStateManager {
       const loginResp = loginResponse as unknown as MatrixLoginResponse
       const tokenParams: MatrixUpdateTokenParams = {
         token: loginResp.access_token,
-        ...(loginResp.refresh_token !== undefined && { refresh_token: loginResp.refresh_token })
+        homeserver: homeserverUrl,
+        ...(loginResp.refresh_token !== undefined && { refreshToken: loginResp.refresh_token }),
+        ...(loginResp.user_id !== undefined && { uid: loginResp.user_id })
       }
       await invoke(TauriCommand.UPDATE_TOKEN, tokenParams).catch(() => {})
 
@@ -55,7 +55,9 @@ export interface MatrixLoginResponse {
 
 export interface MatrixUpdateTokenParams {
   token: string
-  refresh_token?: string
+  refreshToken?: string
+  uid?: string
+  homeserver?: string
   [key: string]: unknown
 }
 
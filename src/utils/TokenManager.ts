@@ -19,7 +19,7 @@ export class TokenManager {
         TauriCommand.UPDATE_TOKEN,
         {
           token,
-          refresh_token: refreshToken
+          refreshToken
         },
         {
           customErrorMessage: '更新 token 失败',
@@ -43,10 +43,8 @@ export class TokenManager {
       await invokeWithErrorHandler(
         TauriCommand.UPDATE_TOKEN,
         {
-          tokenInfo: {
-            token,
-            refreshToken
-          }
+          token,
+          refreshToken
         },
         {
           showError: false